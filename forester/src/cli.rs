@@ -1,4 +1,6 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use light_test_utils::forester_epoch::TreeType;
+use solana_sdk::pubkey::Pubkey;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about=None)]
@@ -11,4 +13,36 @@ pub struct Cli {
 pub enum Commands {
     Start,
     Status,
+    /// Rolls over a single tree which has crossed its rollover threshold.
+    Rollover {
+        #[arg(long)]
+        tree: Pubkey,
+        /// Detected from the tree account if omitted.
+        #[arg(long = "type", value_enum)]
+        tree_type: Option<TreeTypeArg>,
+    },
+    /// Lists the light slots in which the forester is eligible per tree.
+    Schedule {
+        /// Defaults to the current epoch.
+        #[arg(long)]
+        epoch: Option<u64>,
+    },
+    /// Re-submits the items recorded in the dead-letter store which are
+    /// still in their queues, in the current epoch.
+    ReplayDeadLetters,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TreeTypeArg {
+    State,
+    Address,
+}
+
+impl From<TreeTypeArg> for TreeType {
+    fn from(tree_type: TreeTypeArg) -> Self {
+        match tree_type {
+            TreeTypeArg::State => TreeType::State,
+            TreeTypeArg::Address => TreeType::Address,
+        }
+    }
 }
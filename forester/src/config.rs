@@ -1,7 +1,20 @@
+use crate::registry::RegistryProgram;
+use crate::tx_observer::TxObserver;
 use light_registry::ForesterEpochPda;
-use light_test_utils::forester_epoch::{Epoch, TreeAccounts, TreeForesterSchedule};
+use light_test_utils::forester_epoch::{Epoch, TreeAccounts, TreeForesterSchedule, TreeType};
+use log::info;
+use rand::Rng;
+use serde::Serialize;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_sdk::instruction::Instruction;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::Keypair;
+use solana_sdk::signature::{Keypair, Signer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub struct ForesterEpochInfo {
@@ -24,6 +37,105 @@ impl ForesterEpochInfo {
             self.trees.push(tree_schedule);
         }
     }
+
+    /// Returns the inclusive light slot ranges in which the forester is
+    /// eligible to perform work, per tree.
+    pub fn eligible_light_slot_ranges(&self) -> Vec<(TreeAccounts, Vec<(u64, u64)>)> {
+        self.trees
+            .iter()
+            .map(|tree| (tree.tree_accounts, eligible_light_slot_ranges(tree)))
+            .collect()
+    }
+
+    /// One line per tree summarizing the eligible light slot ranges of the
+    /// active phase.
+    pub fn eligibility_summary(&self) -> Vec<String> {
+        self.eligible_light_slot_ranges()
+            .into_iter()
+            .map(|(tree_accounts, ranges)| {
+                let eligible_slots: u64 = ranges.iter().map(|(start, end)| end - start + 1).sum();
+                let ranges = ranges
+                    .iter()
+                    .map(|(start, end)| format!("{}-{}", start, end))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(
+                    "Epoch {} {:?} tree {}: {} eligible light slots [{}]",
+                    self.epoch.epoch,
+                    tree_accounts.tree_type,
+                    tree_accounts.merkle_tree,
+                    eligible_slots,
+                    ranges
+                )
+            })
+            .collect()
+    }
+
+    pub fn log_eligibility_summary(&self) {
+        for line in self.eligibility_summary() {
+            info!("{}", line);
+        }
+    }
+
+    pub fn schedule_report(&self) -> ScheduleReport {
+        ScheduleReport {
+            epoch: self.epoch.epoch,
+            trees: self
+                .trees
+                .iter()
+                .map(|tree| {
+                    let eligible = tree.slots.iter().map(Option::is_some).collect();
+                    (tree.tree_accounts.merkle_tree.to_string(), eligible)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The light slots of an epoch's active phase in which the forester is
+/// eligible, per tree.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScheduleReport {
+    pub epoch: u64,
+    /// Tree pubkey to whether the forester is eligible in each light slot.
+    pub trees: BTreeMap<String, Vec<bool>>,
+}
+
+/// Returns the light slots of the active phase in which `forester_pubkey` is
+/// eligible to perform work, per tree. Empty if `epoch_info` belongs to
+/// another forester.
+pub fn simulate_schedule(
+    epoch_info: &ForesterEpochInfo,
+    forester_pubkey: &Pubkey,
+) -> Vec<(Pubkey, Vec<u64>)> {
+    if epoch_info.epoch_pda.authority != *forester_pubkey {
+        return Vec::new();
+    }
+    epoch_info
+        .trees
+        .iter()
+        .map(|tree| {
+            let eligible_light_slots = (0..tree.slots.len() as u64)
+                .filter(|light_slot| tree.is_eligible(*light_slot))
+                .collect();
+            (tree.tree_accounts.merkle_tree, eligible_light_slots)
+        })
+        .collect()
+}
+
+fn eligible_light_slot_ranges(tree_schedule: &TreeForesterSchedule) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for (light_slot, slot) in tree_schedule.slots.iter().enumerate() {
+        if slot.is_none() {
+            continue;
+        }
+        let light_slot = light_slot as u64;
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == light_slot => *end = light_slot,
+            _ => ranges.push((light_slot, light_slot)),
+        }
+    }
+    ranges
 }
 
 #[derive(Debug)]
@@ -39,6 +151,92 @@ pub struct ForesterConfig {
     pub max_retries: usize,
     pub rpc_pool_size: usize,
     pub slot_update_interval_seconds: u64,
+    pub log_eligibility_summary: bool,
+    pub dry_run: bool,
+    pub batch_sizing: BatchSizing,
+    pub shutdown_grace_period_seconds: u64,
+    /// Queue items older than this are skipped. Queues don't record when an
+    /// item was inserted, so the age is measured from when this forester
+    /// first observed it. Skipped items are re-admitted after being skipped
+    /// for as many slots again, see [`crate::queue_helpers::QueueItemAgeTracker`].
+    pub max_queue_item_age_slots: Option<u64>,
+    pub validate_proof_roots: bool,
+    pub rpc_max_latency_ms: u64,
+    pub rpc_health_check_interval_seconds: u64,
+    pub enable_work_processing: bool,
+    pub enable_rollover: bool,
+    pub enable_reporting: bool,
+    pub rpc_endpoint_cooldown_seconds: u64,
+    pub max_tps: Option<u32>,
+    pub prioritize_full_queues: bool,
+    pub work_commitment: CommitmentConfig,
+    pub rollover_commitment: CommitmentConfig,
+    pub max_transactions_per_epoch: Option<usize>,
+    pub retry_policy: RetryPolicy,
+    pub tree_type: Option<TreeType>,
+    pub max_proof_root_seq_lag: Option<u64>,
+    pub queue_metrics_interval_seconds: u64,
+    pub proof_fetch_timeout_seconds: u64,
+    pub min_queue_items: usize,
+    pub phase_end_margin_slots: u64,
+    pub max_concurrent_batches_per_tree: Option<usize>,
+    pub wait_for_eligible_slot: bool,
+    pub tree_discovery_interval_seconds: u64,
+    pub max_concurrent_epochs: usize,
+    pub proof_batch_size: usize,
+    pub force_register: bool,
+    pub cu_price: Option<u64>,
+    pub metrics_port: Option<u16>,
+    pub health_staleness_seconds: u64,
+    /// Durable nonce account whose nonce is used instead of a recent
+    /// blockhash for transaction batches.
+    pub nonce_account: Option<Pubkey>,
+    /// Authority of `nonce_account`, the forester signer if not set.
+    pub nonce_authority: Option<Keypair>,
+    /// File recording items whose transaction batch failed after all
+    /// retries, see [`crate::dead_letter`].
+    pub dead_letter_path: Option<String>,
+    pub rollover_confirm_timeout_seconds: u64,
+    pub rollover_confirm_poll_interval_ms: u64,
+    /// Upper bound of the delay after the registration window opens before
+    /// this forester registers, see [`crate::epoch_manager::registration_offset`].
+    pub registration_jitter_slots: u64,
+    /// Only tracks queues, eligibility and estimated work of each epoch
+    /// without registering or sending any transactions.
+    pub observer: bool,
+    /// Time to wait for a pooled RPC connection before failing with
+    /// [`crate::errors::ForesterError::PoolExhausted`].
+    pub rpc_pool_acquire_timeout_ms: u64,
+    /// Notified of every transaction batch sent, not configurable through
+    /// the environment.
+    pub tx_observer: Option<Arc<dyn TxObserver>>,
+    /// Upper bound on how long the epoch monitor uses the protocol config
+    /// without re-fetching it, so that changed phase lengths are picked up.
+    pub protocol_config_refresh_interval_seconds: u64,
+    /// Concurrently processed indexer batches across all address queues,
+    /// `indexer_max_concurrent_batches` if not set. Concurrent address
+    /// updates conflict on the changelog, so this is usually kept low.
+    pub address_max_concurrent_batches: Option<usize>,
+    /// Concurrently processed indexer batches across all state queues,
+    /// `indexer_max_concurrent_batches` if not set.
+    pub state_max_concurrent_batches: Option<usize>,
+    /// Wall-clock time after which the forester shuts down gracefully, e.g.
+    /// for chaos testing. Runs until shut down if not set.
+    pub run_duration_seconds: Option<u64>,
+    /// Trees whose queues are processed one transaction at a time, each
+    /// with freshly fetched proofs and sent only after the previous one is
+    /// confirmed, e.g. address trees whose concurrent updates race on the
+    /// changelog.
+    pub serial_trees: Vec<Pubkey>,
+    /// Queues processed concurrently in response to pubsub updates.
+    pub max_concurrent_queue_tasks: usize,
+    /// Lamports below which no new work batches are started until the payer
+    /// is topped up. Not checked if not set.
+    pub min_payer_balance: Option<u64>,
+    /// When the forester enters an active phase after its first light slot,
+    /// e.g. because it was started mid-epoch, process the queues repeatedly
+    /// until the backlog is drained before relying on queue updates.
+    pub catch_up: bool,
     pub address_tree_data: Vec<TreeAccounts>,
     pub state_tree_data: Vec<TreeAccounts>,
 }
@@ -59,16 +257,493 @@ impl Clone for ForesterConfig {
             state_tree_data: self.state_tree_data.clone(),
             address_tree_data: self.address_tree_data.clone(),
             slot_update_interval_seconds: self.slot_update_interval_seconds,
+            log_eligibility_summary: self.log_eligibility_summary,
+            dry_run: self.dry_run,
+            batch_sizing: self.batch_sizing,
+            shutdown_grace_period_seconds: self.shutdown_grace_period_seconds,
+            max_queue_item_age_slots: self.max_queue_item_age_slots,
+            validate_proof_roots: self.validate_proof_roots,
+            rpc_max_latency_ms: self.rpc_max_latency_ms,
+            rpc_health_check_interval_seconds: self.rpc_health_check_interval_seconds,
+            enable_work_processing: self.enable_work_processing,
+            enable_rollover: self.enable_rollover,
+            enable_reporting: self.enable_reporting,
+            rpc_endpoint_cooldown_seconds: self.rpc_endpoint_cooldown_seconds,
+            max_tps: self.max_tps,
+            prioritize_full_queues: self.prioritize_full_queues,
+            work_commitment: self.work_commitment,
+            rollover_commitment: self.rollover_commitment,
+            max_transactions_per_epoch: self.max_transactions_per_epoch,
+            retry_policy: self.retry_policy,
+            tree_type: self.tree_type,
+            max_proof_root_seq_lag: self.max_proof_root_seq_lag,
+            queue_metrics_interval_seconds: self.queue_metrics_interval_seconds,
+            proof_fetch_timeout_seconds: self.proof_fetch_timeout_seconds,
+            min_queue_items: self.min_queue_items,
+            phase_end_margin_slots: self.phase_end_margin_slots,
+            max_concurrent_batches_per_tree: self.max_concurrent_batches_per_tree,
+            wait_for_eligible_slot: self.wait_for_eligible_slot,
+            tree_discovery_interval_seconds: self.tree_discovery_interval_seconds,
+            max_concurrent_epochs: self.max_concurrent_epochs,
+            proof_batch_size: self.proof_batch_size,
+            force_register: self.force_register,
+            cu_price: self.cu_price,
+            metrics_port: self.metrics_port,
+            health_staleness_seconds: self.health_staleness_seconds,
+            nonce_account: self.nonce_account,
+            nonce_authority: self
+                .nonce_authority
+                .as_ref()
+                .map(|keypair| keypair.insecure_clone()),
+            dead_letter_path: self.dead_letter_path.clone(),
+            rollover_confirm_timeout_seconds: self.rollover_confirm_timeout_seconds,
+            rollover_confirm_poll_interval_ms: self.rollover_confirm_poll_interval_ms,
+            registration_jitter_slots: self.registration_jitter_slots,
+            observer: self.observer,
+            rpc_pool_acquire_timeout_ms: self.rpc_pool_acquire_timeout_ms,
+            tx_observer: self.tx_observer.clone(),
+            protocol_config_refresh_interval_seconds: self.protocol_config_refresh_interval_seconds,
+            address_max_concurrent_batches: self.address_max_concurrent_batches,
+            state_max_concurrent_batches: self.state_max_concurrent_batches,
+            run_duration_seconds: self.run_duration_seconds,
+            serial_trees: self.serial_trees.clone(),
+            max_concurrent_queue_tasks: self.max_concurrent_queue_tasks,
+            min_payer_balance: self.min_payer_balance,
+            catch_up: self.catch_up,
+        }
+    }
+}
+
+/// How rollover transactions are confirmed after they are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RolloverConfirmation {
+    pub commitment: CommitmentConfig,
+    /// Time after which an unconfirmed rollover transaction fails with
+    /// [`ForesterError::RolloverConfirmationTimeout`](crate::errors::ForesterError::RolloverConfirmationTimeout).
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+}
+
+/// How work items are grouped into transactions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum BatchSizing {
+    /// Send up to `transaction_batch_size` instructions per transaction,
+    /// splitting transactions which would exceed the packet size limit.
+    #[default]
+    Fixed,
+    /// Pack as many instructions per transaction as fit into the packet size limit.
+    PackToLimit,
+}
+
+impl FromStr for BatchSizing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fixed" => Ok(BatchSizing::Fixed),
+            "pack_to_limit" => Ok(BatchSizing::PackToLimit),
+            _ => Err(format!("Unknown batch sizing: {}", s)),
+        }
+    }
+}
+
+/// Computes the delay before retrying a failed transaction batch.
+pub trait RetryDelay {
+    fn retry_delay(&self, retry: usize) -> Duration;
+}
+
+/// Delay between retries of a failed transaction batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RetryPolicy {
+    /// Exponential backoff from 100ms with up to 50ms of random jitter.
+    #[default]
+    JitteredExponential,
+    /// Backoff multiplied by `factor` after every retry, without jitter and
+    /// capped at `max`.
+    Deterministic {
+        base_delay: Duration,
+        factor: u32,
+        max: Duration,
+    },
+}
+
+impl RetryDelay for RetryPolicy {
+    fn retry_delay(&self, retry: usize) -> Duration {
+        match *self {
+            RetryPolicy::JitteredExponential => {
+                const BASE_RETRY_DELAY: Duration = Duration::from_millis(100);
+                let delay = BASE_RETRY_DELAY.saturating_mul(2u32.saturating_pow(retry as u32));
+                let jitter = rand::thread_rng().gen_range(0..=50);
+                delay + Duration::from_millis(jitter)
+            }
+            RetryPolicy::Deterministic {
+                base_delay,
+                factor,
+                max,
+            } => base_delay
+                .saturating_mul(factor.saturating_pow(retry as u32))
+                .min(max),
+        }
+    }
+}
+
+/// Maximum compute units a transaction may request.
+pub const MAX_COMPUTE_UNIT_LIMIT: u32 = 1_400_000;
+
+impl ForesterConfig {
+    /// Rejects values which would otherwise only fail at runtime, e.g. a
+    /// compute unit limit every transaction exceeds or a concurrency of zero
+    /// which never lets a batch run.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.cu_limit == 0 || self.cu_limit > MAX_COMPUTE_UNIT_LIMIT {
+            return Err(format!(
+                "cu_limit must be between 1 and {}, got {}",
+                MAX_COMPUTE_UNIT_LIMIT, self.cu_limit
+            ));
+        }
+        let non_zero = [
+            ("indexer_batch_size", self.indexer_batch_size),
+            (
+                "indexer_max_concurrent_batches",
+                self.indexer_max_concurrent_batches,
+            ),
+            ("transaction_batch_size", self.transaction_batch_size),
+            (
+                "transaction_max_concurrent_batches",
+                self.transaction_max_concurrent_batches,
+            ),
+            ("rpc_pool_size", self.rpc_pool_size),
+            ("max_concurrent_epochs", self.max_concurrent_epochs),
+            (
+                "max_concurrent_queue_tasks",
+                self.max_concurrent_queue_tasks,
+            ),
+            (
+                "max_concurrent_batches_per_tree",
+                self.max_concurrent_batches_per_tree.unwrap_or(1),
+            ),
+            (
+                "address_max_concurrent_batches",
+                self.address_max_concurrent_batches.unwrap_or(1),
+            ),
+            (
+                "state_max_concurrent_batches",
+                self.state_max_concurrent_batches.unwrap_or(1),
+            ),
+        ];
+        match non_zero.iter().find(|(_, value)| *value == 0) {
+            Some((name, _)) => Err(format!("{} must not be 0", name)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn startup_summary(&self, trees: &[TreeAccounts]) -> StartupSummary {
+        let state_trees = trees
+            .iter()
+            .filter(|tree| tree.tree_type == TreeType::State)
+            .count();
+        StartupSummary {
+            forester_pubkey: self.payer_keypair.pubkey().to_string(),
+            derivation: self.external_services.derivation.clone(),
+            state_trees,
+            address_trees: trees.len() - state_trees,
+            indexer_batch_size: self.indexer_batch_size,
+            indexer_max_concurrent_batches: self.indexer_max_concurrent_batches,
+            transaction_batch_size: self.transaction_batch_size,
+            transaction_max_concurrent_batches: self.transaction_max_concurrent_batches,
+            cu_limit: self.cu_limit,
+            rpc_pool_size: self.rpc_pool_size,
+            dry_run: self.dry_run,
+        }
+    }
+
+    /// Compute budget instructions prepended to every forester transaction:
+    /// the CU limit and, if configured, the CU price.
+    pub fn compute_budget_instructions(&self) -> Vec<Instruction> {
+        let mut instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(
+            self.cu_limit,
+        )];
+        if let Some(cu_price) = self.cu_price {
+            instructions.push(ComputeBudgetInstruction::set_compute_unit_price(cu_price));
+        }
+        instructions
+    }
+
+    pub fn rollover_confirmation(&self) -> RolloverConfirmation {
+        RolloverConfirmation {
+            commitment: self.rollover_commitment,
+            timeout: Duration::from_secs(self.rollover_confirm_timeout_seconds),
+            poll_interval: Duration::from_millis(self.rollover_confirm_poll_interval_ms),
         }
     }
+
+    /// Registry program this forester registers with, reports work to and
+    /// sends its transactions through.
+    pub fn registry(&self) -> RegistryProgram {
+        RegistryProgram::new(self.registry_pubkey)
+    }
+}
+
+/// Identity and configuration of a running forester, logged once at startup
+/// so that deployments can be verified.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StartupSummary {
+    pub forester_pubkey: String,
+    pub derivation: String,
+    pub state_trees: usize,
+    pub address_trees: usize,
+    pub indexer_batch_size: usize,
+    pub indexer_max_concurrent_batches: usize,
+    pub transaction_batch_size: usize,
+    pub transaction_max_concurrent_batches: usize,
+    pub cu_limit: u32,
+    pub rpc_pool_size: usize,
+    pub dry_run: bool,
+}
+
+impl fmt::Display for StartupSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "forester_pubkey={} derivation={} state_trees={} address_trees={} \
+             indexer_batch_size={} indexer_max_concurrent_batches={} \
+             transaction_batch_size={} transaction_max_concurrent_batches={} \
+             cu_limit={} rpc_pool_size={} dry_run={}",
+            self.forester_pubkey,
+            self.derivation,
+            self.state_trees,
+            self.address_trees,
+            self.indexer_batch_size,
+            self.indexer_max_concurrent_batches,
+            self.transaction_batch_size,
+            self.transaction_max_concurrent_batches,
+            self.cu_limit,
+            self.rpc_pool_size,
+            self.dry_run
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ExternalServicesConfig {
     pub rpc_url: String,
+    /// Used in turn when `rpc_url` is rate-limiting or unreachable.
+    pub fallback_rpc_urls: Vec<String>,
     pub ws_rpc_url: String,
     pub indexer_url: String,
     pub prover_url: String,
     pub photon_api_key: Option<String>,
+    /// Signing service holding the forester authority key. If not set,
+    /// transactions are signed with the payer keypair.
+    pub remote_signer_url: Option<String>,
+    /// WebSocket endpoint of the indexer. If set, proofs are requested over
+    /// a single persistent connection instead of one HTTP request each.
+    pub indexer_ws_url: Option<String>,
     pub derivation: String,
 }
+
+impl ExternalServicesConfig {
+    /// `rpc_url` followed by the fallback RPC URLs.
+    pub fn rpc_urls(&self) -> Vec<String> {
+        std::iter::once(self.rpc_url.clone())
+            .chain(self.fallback_rpc_urls.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::test_config;
+    use light_test_utils::forester_epoch::ForesterSlot;
+
+    fn schedule(tree_accounts: TreeAccounts, eligible: &[bool]) -> TreeForesterSchedule {
+        TreeForesterSchedule {
+            tree_accounts,
+            slots: eligible
+                .iter()
+                .enumerate()
+                .map(|(i, eligible)| {
+                    eligible.then_some(ForesterSlot {
+                        slot: i as u64,
+                        start_solana_slot: i as u64 * 10,
+                        end_solana_slot: (i as u64 + 1) * 10,
+                        forester_index: 0,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_deterministic_retry_delay() {
+        let policy = RetryPolicy::Deterministic {
+            base_delay: Duration::from_millis(100),
+            factor: 2,
+            max: Duration::from_millis(500),
+        };
+        let delays: Vec<_> = (0..5).map(|retry| policy.retry_delay(retry)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 400, 500, 500]
+                .map(Duration::from_millis)
+                .to_vec()
+        );
+
+        let zero_delay = RetryPolicy::Deterministic {
+            base_delay: Duration::ZERO,
+            factor: 2,
+            max: Duration::ZERO,
+        };
+        assert_eq!(zero_delay.retry_delay(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_jittered_retry_delay() {
+        let delay = RetryPolicy::JitteredExponential.retry_delay(2);
+        assert!(delay >= Duration::from_millis(400));
+        assert!(delay <= Duration::from_millis(450));
+    }
+
+    #[test]
+    fn test_eligibility_summary() {
+        let state_tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let address_tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::Address,
+            false,
+        );
+        let epoch_info = ForesterEpochInfo {
+            epoch: Epoch {
+                epoch: 3,
+                ..Default::default()
+            },
+            epoch_pda: ForesterEpochPda::default(),
+            trees: vec![
+                schedule(
+                    state_tree,
+                    &[true, true, false, false, true, false, true, true, true],
+                ),
+                schedule(address_tree, &[false, false, false]),
+            ],
+        };
+
+        let ranges = epoch_info.eligible_light_slot_ranges();
+        assert_eq!(ranges[0], (state_tree, vec![(0, 1), (4, 4), (6, 8)]));
+        assert_eq!(ranges[1], (address_tree, vec![]));
+
+        let summary = epoch_info.eligibility_summary();
+        assert_eq!(
+            summary[0],
+            format!(
+                "Epoch 3 State tree {}: 6 eligible light slots [0-1, 4-4, 6-8]",
+                state_tree.merkle_tree
+            )
+        );
+        assert_eq!(
+            summary[1],
+            format!(
+                "Epoch 3 Address tree {}: 0 eligible light slots []",
+                address_tree.merkle_tree
+            )
+        );
+    }
+
+    #[test]
+    fn test_simulate_schedule() {
+        let forester = Pubkey::new_unique();
+        let tree_accounts = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let epoch_info = ForesterEpochInfo {
+            epoch: Epoch::default(),
+            epoch_pda: ForesterEpochPda {
+                authority: forester,
+                ..Default::default()
+            },
+            trees: vec![schedule(tree_accounts, &[true, false, true, true])],
+        };
+
+        assert_eq!(
+            simulate_schedule(&epoch_info, &forester),
+            vec![(tree_accounts.merkle_tree, vec![0, 2, 3])]
+        );
+        assert!(simulate_schedule(&epoch_info, &Pubkey::new_unique()).is_empty());
+
+        let report = epoch_info.schedule_report();
+        assert_eq!(
+            report.trees[&tree_accounts.merkle_tree.to_string()],
+            vec![true, false, true, true]
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(test_config().validate(), Ok(()));
+
+        let mut config = test_config();
+        config.cu_limit = 0;
+        assert!(config.validate().is_err());
+        config.cu_limit = MAX_COMPUTE_UNIT_LIMIT + 1;
+        assert!(config.validate().is_err());
+
+        let mut config = test_config();
+        config.transaction_max_concurrent_batches = 0;
+        assert_eq!(
+            config.validate(),
+            Err("transaction_max_concurrent_batches must not be 0".to_string())
+        );
+
+        let mut config = test_config();
+        config.max_concurrent_batches_per_tree = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_startup_summary() {
+        let config = test_config();
+        let trees = vec![
+            TreeAccounts::new(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                TreeType::State,
+                false,
+            ),
+            TreeAccounts::new(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                TreeType::State,
+                false,
+            ),
+            TreeAccounts::new(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                TreeType::Address,
+                false,
+            ),
+        ];
+
+        let summary = config.startup_summary(&trees);
+        assert_eq!(summary.state_trees, 2);
+        assert_eq!(summary.address_trees, 1);
+
+        let line = summary.to_string();
+        assert!(line.contains(&format!(
+            "forester_pubkey={}",
+            config.payer_keypair.pubkey()
+        )));
+        assert!(line.contains("indexer_batch_size=50"));
+        assert!(line.contains("transaction_batch_size=1"));
+        assert!(line.contains("transaction_max_concurrent_batches=20"));
+        assert!(line.contains("cu_limit=1000000"));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::errors::ForesterError;
+use crate::Result;
+use light_test_utils::forester_epoch::{TreeAccounts, TreeType};
+use serde::{Deserialize, Serialize};
+use solana_sdk::bs58;
+use solana_sdk::pubkey::Pubkey;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+/// Format version written with every dead letter. Bump it when the format
+/// changes and migrate older versions in [`VersionedDeadLetter::migrate`].
+const DEAD_LETTER_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "TreeType")]
+enum TreeTypeDef {
+    Address,
+    State,
+}
+
+/// A queue item whose transaction batch still failed after `max_retries`, or
+/// failed with an error which retrying doesn't fix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub epoch: u64,
+    pub tree: String,
+    pub queue: String,
+    #[serde(with = "TreeTypeDef")]
+    pub tree_type: TreeType,
+    /// Base58 encoded hash of the queue item.
+    pub hash: String,
+    pub index: usize,
+    pub error: String,
+}
+
+impl DeadLetter {
+    pub fn new(
+        epoch: u64,
+        tree_accounts: &TreeAccounts,
+        hash: &[u8; 32],
+        index: usize,
+        error: &ForesterError,
+    ) -> Self {
+        Self {
+            epoch,
+            tree: tree_accounts.merkle_tree.to_string(),
+            queue: tree_accounts.queue.to_string(),
+            tree_type: tree_accounts.tree_type,
+            hash: bs58::encode(hash).into_string(),
+            index,
+            error: error.to_string(),
+        }
+    }
+
+    /// `None` if the dead letter was edited into an invalid pubkey.
+    pub fn tree_accounts(&self) -> Option<TreeAccounts> {
+        Some(TreeAccounts::new(
+            Pubkey::from_str(&self.tree).ok()?,
+            Pubkey::from_str(&self.queue).ok()?,
+            self.tree_type,
+            false,
+        ))
+    }
+
+    /// `None` if the dead letter was edited into an invalid hash.
+    pub fn hash(&self) -> Option<[u8; 32]> {
+        bs58::decode(&self.hash).into_vec().ok()?.try_into().ok()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct VersionedDeadLetter {
+    /// 0 for dead letters written before the format was versioned.
+    #[serde(default)]
+    version: u32,
+    #[serde(flatten)]
+    dead_letter: DeadLetter,
+}
+
+impl VersionedDeadLetter {
+    /// Fails for versions written by a newer forester, which this one can't
+    /// interpret.
+    fn migrate(self) -> std::result::Result<DeadLetter, String> {
+        match self.version {
+            // Unversioned dead letters have the same fields as version 1.
+            0 | DEAD_LETTER_VERSION => Ok(self.dead_letter),
+            version => Err(format!(
+                "unsupported dead letter version {}, the latest supported version is {}",
+                version, DEAD_LETTER_VERSION
+            )),
+        }
+    }
+}
+
+/// Dead letters stored as JSON lines in a file, so that they can be
+/// inspected and replayed with the `replay-dead-letters` command.
+#[derive(Debug)]
+pub struct DeadLetterStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl DeadLetterStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn record(&self, dead_letters: &[DeadLetter]) -> Result<()> {
+        let _lock = self.lock.lock().await;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| self.error(e))?;
+        file.write_all(
+            to_json_lines(dead_letters)
+                .map_err(|e| self.error(e))?
+                .as_bytes(),
+        )
+        .await
+        .map_err(|e| self.error(e))
+    }
+
+    /// Returns no dead letters if the store does not exist yet.
+    pub async fn read(&self) -> Result<Vec<DeadLetter>> {
+        let _lock = self.lock.lock().await;
+        let contents = match fs::read_to_string(&self.path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(self.error(e)),
+        };
+        let mut dead_letters = Vec::new();
+        for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+            let versioned: VersionedDeadLetter =
+                serde_json::from_str(line).map_err(|e| self.error(e))?;
+            dead_letters.push(versioned.migrate().map_err(|e| self.error(e))?);
+        }
+        Ok(dead_letters)
+    }
+
+    /// Replaces the stored dead letters with `dead_letters`.
+    pub async fn replace(&self, dead_letters: &[DeadLetter]) -> Result<()> {
+        let _lock = self.lock.lock().await;
+        fs::write(
+            &self.path,
+            to_json_lines(dead_letters).map_err(|e| self.error(e))?,
+        )
+        .await
+        .map_err(|e| self.error(e))
+    }
+
+    fn error(&self, error: impl std::fmt::Display) -> ForesterError {
+        ForesterError::Custom(format!(
+            "Dead letter store {}: {}",
+            self.path.display(),
+            error
+        ))
+    }
+}
+
+fn to_json_lines(dead_letters: &[DeadLetter]) -> serde_json::Result<String> {
+    let mut lines = String::new();
+    for dead_letter in dead_letters {
+        lines.push_str(&serde_json::to_string(&VersionedDeadLetter {
+            version: DEAD_LETTER_VERSION,
+            dead_letter: dead_letter.clone(),
+        })?);
+        lines.push('\n');
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dead_letter_store() {
+        let path = std::env::temp_dir().join(format!(
+            "forester-dead-letters-{}.jsonl",
+            Pubkey::new_unique()
+        ));
+        let store = DeadLetterStore::new(&path);
+        assert!(store.read().await.unwrap().is_empty());
+
+        let tree_accounts = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::Address,
+            false,
+        );
+        let dead_letters: Vec<_> = (0..3)
+            .map(|index| {
+                DeadLetter::new(
+                    7,
+                    &tree_accounts,
+                    &[index as u8; 32],
+                    index,
+                    &ForesterError::MaxRetriesReached,
+                )
+            })
+            .collect();
+        store.record(&dead_letters[..2]).await.unwrap();
+        store.record(&dead_letters[2..]).await.unwrap();
+        let stored = store.read().await.unwrap();
+        assert_eq!(stored, dead_letters);
+        assert_eq!(stored[1].tree_accounts(), Some(tree_accounts));
+        assert_eq!(stored[1].hash(), Some([1; 32]));
+
+        store.replace(&dead_letters[1..2]).await.unwrap();
+        assert_eq!(store.read().await.unwrap(), dead_letters[1..2]);
+
+        fs::remove_file(&path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dead_letter_versions() {
+        let path = std::env::temp_dir().join(format!(
+            "forester-dead-letters-{}.jsonl",
+            Pubkey::new_unique()
+        ));
+        let store = DeadLetterStore::new(&path);
+        let tree = Pubkey::new_unique().to_string();
+        let queue = Pubkey::new_unique().to_string();
+        let unversioned = format!(
+            r#"{{"epoch":1,"tree":"{}","queue":"{}","tree_type":"State","hash":"1","index":0,"error":"e"}}"#,
+            tree, queue
+        );
+        fs::write(&path, format!("{}\n", unversioned))
+            .await
+            .unwrap();
+        let stored = store.read().await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].tree, tree);
+
+        let newer = unversioned.replacen('{', r#"{"version":99,"#, 1);
+        fs::write(&path, format!("{}\n", newer)).await.unwrap();
+        assert!(store.read().await.is_err());
+
+        fs::remove_file(&path).await.unwrap();
+    }
+}
@@ -1,47 +1,87 @@
+use crate::config::{BatchSizing, RetryDelay};
+use crate::dead_letter::{DeadLetter, DeadLetterStore};
 use crate::errors::ForesterError;
+use crate::health::{HealthCheck, HealthState};
+use crate::latency::{LatencyPercentiles, LatencyRecorder};
+use crate::metrics::{
+    increment_stale_queue_items_skipped, increment_transactions, run_metrics_server,
+    run_queue_fill_ratio_emitter, set_payer_balance, set_transaction_landing_latency,
+};
 use crate::pubsub_client::setup_pubsub_client;
-use crate::queue_helpers::{fetch_queue_item_data, QueueItemData, QueueUpdate};
+use crate::queue_helpers::{
+    fetch_queue_data, fetch_queue_data_multi, fetch_queue_item_data, sort_by_insertion_order,
+    QueueItemAgeTracker, QueueItemData, QueueUpdate,
+};
+use crate::rate_limiter::RateLimiter;
+use crate::registry::RegistryProgram;
 use crate::rollover::{
     is_tree_ready_for_rollover, rollover_address_merkle_tree, rollover_state_merkle_tree,
+    rollover_with_retry, RolloverOutcome,
 };
 use crate::rpc_pool::SolanaRpcPool;
-use crate::slot_tracker::{wait_until_slot_reached, SlotTracker};
-use crate::tree_data_sync::fetch_trees;
+use crate::signer::{sign_transaction, ForesterSigner};
+use crate::slot_tracker::{
+    slot_duration, wait_until_estimated_slot_reached, wait_until_slot_reached, SlotTracker,
+};
+use crate::tree_data_sync::{fetch_trees, fetch_trees_by_type, try_fetch_trees};
+use crate::tree_helpers::fetch_merkle_tree_header;
+use crate::tx_observer::{TransactionRecord, TxObserver};
+use crate::utils::{log_simulation_result, try_get_protocol_config};
 use crate::Result;
 use crate::{ForesterConfig, ForesterEpochInfo};
+use account_compression::errors::AccountCompressionErrorCode;
 use account_compression::utils::constants::{
     ADDRESS_MERKLE_TREE_CHANGELOG, ADDRESS_MERKLE_TREE_INDEXED_CHANGELOG,
     STATE_MERKLE_TREE_CHANGELOG,
 };
+use arc_swap::ArcSwap;
 use futures::future::join_all;
+use light_concurrent_merkle_tree::errors::ConcurrentMerkleTreeError;
+use light_hash_set::HashSetError;
+use light_indexed_merkle_tree::errors::IndexedMerkleTreeError;
 use light_registry::account_compression_cpi::sdk::{
-    create_nullify_instruction, create_update_address_merkle_tree_instruction,
     CreateNullifyInstructionInputs, UpdateAddressMerkleTreeInstructionInputs,
 };
 use light_registry::protocol_config::state::ProtocolConfig;
-use light_registry::sdk::{
-    create_finalize_registration_instruction, create_report_work_instruction,
-};
-use light_registry::ForesterEpochPda;
+use light_registry::{EpochPda, ForesterEpochPda};
 use light_test_utils::forester_epoch::{
     get_epoch_phases, Epoch, TreeAccounts, TreeForesterSchedule, TreeType,
 };
 use light_test_utils::indexer::{Indexer, MerkleProof, NewAddressProofWithContext};
+use light_test_utils::rpc::errors::RpcError;
 use light_test_utils::rpc::rpc_connection::RpcConnection;
-use log::{debug, error, info, warn};
-use rand::Rng;
-use solana_sdk::compute_budget::ComputeBudgetInstruction;
+use solana_client::nonce_utils;
+use solana_sdk::hash::Hash;
 use solana_sdk::instruction::Instruction;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::packet::PACKET_DATA_SIZE;
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::{Signature, Signer};
-use solana_sdk::transaction::Transaction;
-use std::collections::HashMap;
-use std::iter::Zip;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use solana_sdk::signature::{Keypair, Signature};
+use solana_sdk::system_instruction::{self, SystemError};
+use solana_sdk::transaction::{Transaction, TransactionError};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{mpsc, oneshot, Mutex, Semaphore};
-use tokio::time::{sleep, Instant};
+use tokio::sync::{mpsc, oneshot, Mutex, RwLock, Semaphore};
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+use tracing::{debug, error, info, instrument, warn, Instrument};
+
+/// Slots before the end of the active phase from which queues are processed
+/// regardless of `min_queue_items`.
+const DEFERRAL_DEADLINE_SLOTS: u64 = 50;
+/// Slots before the dispatch cutoff, `phase_end_margin_slots` before the end
+/// of the active phase, at which queue updates stop being awaited and the
+/// queues are drained a final time while batches can still land.
+const QUEUE_DRAIN_SLOTS: u64 = 10;
+/// Interval in which the payer balance is compared to `min_payer_balance`.
+const PAYER_BALANCE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+/// Age of the slot estimate after which `process_queue` reconciles it with
+/// the actual slot before checking the phase.
+const SLOT_ESTIMATE_MAX_AGE: Duration = Duration::from_secs(5);
 
 #[derive(Clone, Debug)]
 pub struct WorkReport {
@@ -49,12 +89,91 @@ pub struct WorkReport {
     pub processed_items: usize,
 }
 
+/// Operator commands accepted by a running forester.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlCommand {
+    /// Stop starting new work batches. Registration, rollover and work
+    /// reporting continue.
+    Pause,
+    Resume,
+}
+
+/// Lifecycle events emitted by the forester for callers embedding it as a
+/// library.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForesterEvent {
+    Registered {
+        epoch: u64,
+    },
+    ActivePhaseStarted {
+        epoch: u64,
+    },
+    BatchProcessed {
+        epoch: u64,
+        tree: Pubkey,
+        signature: Signature,
+        instructions: usize,
+    },
+    RolloverPerformed {
+        tree: Pubkey,
+        tree_type: TreeType,
+    },
+    WorkReported {
+        epoch: u64,
+        processed_items: usize,
+    },
+}
+
 #[derive(Debug, Clone)]
 struct WorkItem {
     tree_account: TreeAccounts,
     queue_item_data: QueueItemData,
 }
 
+/// Outcome of a transaction batch that did not fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BatchOutcome {
+    Processed(Signature),
+    /// The forester was not eligible for the tree in `light_slot`.
+    SkippedNotEligible {
+        light_slot: u64,
+    },
+    /// Skipped for another reason, e.g. because the transaction cap of the
+    /// epoch was reached or all proofs were stale.
+    Skipped,
+    /// Only simulated because of `dry_run`, nothing was sent.
+    Simulated,
+}
+
+/// Outcome of processing a single work item.
+#[derive(Debug)]
+struct WorkItemResult {
+    work_item: WorkItem,
+    result: Result<BatchOutcome>,
+}
+
+/// Number of work items of a queue per outcome.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct WorkItemSummary {
+    processed: usize,
+    skipped_not_eligible: usize,
+    skipped: usize,
+    simulated: usize,
+    failed: usize,
+}
+
+impl WorkItemSummary {
+    fn record(&mut self, result: &Result<BatchOutcome>) {
+        match result {
+            Ok(BatchOutcome::Processed(_)) => self.processed += 1,
+            Ok(BatchOutcome::SkippedNotEligible { .. }) => self.skipped_not_eligible += 1,
+            Ok(BatchOutcome::Skipped) => self.skipped += 1,
+            Ok(BatchOutcome::Simulated) => self.simulated += 1,
+            Err(_) => self.failed += 1,
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone)]
 enum Proof {
@@ -62,16 +181,154 @@ enum Proof {
     StateProof(MerkleProof),
 }
 
+impl Proof {
+    fn root_seq(&self) -> u64 {
+        match self {
+            Proof::AddressProof(proof) => proof.root_seq,
+            Proof::StateProof(proof) => proof.root_seq,
+        }
+    }
+
+    /// A proof can only be patched on chain while the changelog still holds
+    /// the root it was created against. `max_lag` tightens that limit to the
+    /// given number of sequence numbers.
+    fn is_stale(&self, on_chain_sequence_number: u64, max_lag: Option<u64>) -> bool {
+        let changelog_size = match self {
+            Proof::AddressProof(_) => ADDRESS_MERKLE_TREE_CHANGELOG,
+            Proof::StateProof(_) => STATE_MERKLE_TREE_CHANGELOG,
+        };
+        let max_lag = max_lag.map_or(changelog_size - 1, |max_lag| {
+            max_lag.min(changelog_size - 1)
+        });
+        on_chain_sequence_number.saturating_sub(self.root_seq()) > max_lag
+    }
+}
+
+/// A transaction batch with the items whose proofs went stale removed.
+#[derive(Debug, Default)]
+struct ValidatedBatch {
+    instructions: Vec<Instruction>,
+    proofs: Vec<Proof>,
+    work_items: Vec<WorkItem>,
+    stale_work_items: Vec<WorkItem>,
+}
+
+/// Indexer updates already applied per epoch, keyed by merkle tree and queue
+/// element, so that a batch which lands more than once (e.g. a timed out
+/// attempt followed by a successful retry) only updates the indexer once.
+#[derive(Debug, Default)]
+struct AppliedIndexerUpdates {
+    updates: HashMap<u64, HashSet<(Pubkey, [u8; 32])>>,
+}
+
+impl AppliedIndexerUpdates {
+    /// Returns `false` if the update has already been applied.
+    fn insert(&mut self, epoch: u64, merkle_tree: Pubkey, value: [u8; 32]) -> bool {
+        self.updates
+            .entry(epoch)
+            .or_default()
+            .insert((merkle_tree, value))
+    }
+
+    fn clear_epoch(&mut self, epoch: u64) {
+        self.updates.remove(&epoch);
+    }
+}
+
+/// Queues with a task processing them in response to pubsub updates, and
+/// whether another update arrived since the task started. Updates arriving
+/// while a queue is processed are coalesced into a single re-run.
+#[derive(Debug, Default)]
+struct QueueTasks {
+    in_flight: HashMap<Pubkey, bool>,
+}
+
+impl QueueTasks {
+    /// Returns `false` if a task for the queue is already in flight, which
+    /// then re-runs once it finishes.
+    fn start(&mut self, queue: Pubkey) -> bool {
+        match self.in_flight.get_mut(&queue) {
+            Some(updated) => {
+                *updated = true;
+                false
+            }
+            None => {
+                self.in_flight.insert(queue, false);
+                true
+            }
+        }
+    }
+
+    /// Returns `true` if the queue was updated while it was processed and
+    /// must be processed again.
+    fn finish(&mut self, queue: Pubkey) -> bool {
+        match self.in_flight.get_mut(&queue) {
+            Some(updated) if *updated => {
+                *updated = false;
+                true
+            }
+            _ => {
+                self.in_flight.remove(&queue);
+                false
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
-struct EpochManager<R: RpcConnection, I: Indexer<R>> {
+pub(crate) struct EpochManager<R: RpcConnection, I: Indexer<R>> {
     config: Arc<ForesterConfig>,
-    protocol_config: Arc<ProtocolConfig>,
+    /// Refreshed by the epoch monitor, see [`Self::refresh_protocol_config`].
+    protocol_config: Arc<ArcSwap<ProtocolConfig>>,
     rpc_pool: Arc<SolanaRpcPool<R>>,
-    indexer: Arc<Mutex<I>>,
+    indexer: Arc<RwLock<I>>,
+    signer: Arc<dyn ForesterSigner>,
     work_report_sender: mpsc::Sender<WorkReport>,
+    event_sender: Option<mpsc::Sender<ForesterEvent>>,
     processed_items_per_epoch_count: Arc<Mutex<HashMap<u64, AtomicUsize>>>,
-    trees: Vec<TreeAccounts>,
+    /// Refreshed every `tree_discovery_interval_seconds`, new trees are
+    /// scheduled from the next registered epoch on.
+    trees: Arc<Mutex<Vec<TreeAccounts>>>,
     slot_tracker: Arc<SlotTracker>,
+    /// Upper bound on instructions per transaction in `PackToLimit` mode,
+    /// lowered whenever a packed transaction turns out to be too large.
+    max_instructions_per_transaction: Arc<AtomicUsize>,
+    applied_indexer_updates: Arc<Mutex<AppliedIndexerUpdates>>,
+    epoch_tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+    /// Epoch, tree and balance monitors spawned by [`Self::run`].
+    monitor_tasks: Arc<Mutex<Vec<AbortHandle>>>,
+    /// Queue and batch tasks spawned by epoch tasks, which keep running when
+    /// the epoch task is aborted.
+    work_tasks: Arc<std::sync::Mutex<Vec<AbortHandle>>>,
+    queue_item_ages: Arc<Mutex<QueueItemAgeTracker>>,
+    /// On-chain tree sequence numbers and the slot they were read at.
+    tree_sequence_numbers: Arc<Mutex<HashMap<Pubkey, (u64, u64)>>>,
+    /// Shared by all batch tasks, `None` if `max_tps` is not set.
+    rate_limiter: Option<Arc<RateLimiter>>,
+    landing_latencies: Arc<Mutex<LatencyRecorder>>,
+    /// Queues with fewer than `min_queue_items` items, keyed by epoch, which
+    /// are processed once the active phase is about to end.
+    deferred_queues: Arc<Mutex<HashSet<(u64, Pubkey)>>>,
+    /// Limits queues processed concurrently in response to pubsub updates.
+    queue_task_semaphore: Arc<Semaphore>,
+    /// Limit concurrently processed indexer batches across all address and
+    /// all state queues respectively, so that the two don't compete for
+    /// permits.
+    address_batch_semaphore: Arc<Semaphore>,
+    state_batch_semaphore: Arc<Semaphore>,
+    /// Per-tree limits of concurrently processed indexer batches, only used
+    /// if `max_concurrent_batches_per_tree` is configured.
+    tree_semaphores: Arc<Mutex<HashMap<Pubkey, Arc<Semaphore>>>>,
+    paused: Arc<AtomicBool>,
+    /// Set while the payer balance is below `min_payer_balance`, pauses work
+    /// like [`Self::pause`] without overriding the operator's pause state.
+    low_balance: Arc<AtomicBool>,
+    health: Arc<HealthState>,
+    /// Serializes transaction batches while a durable nonce is used, since
+    /// every batch advances the nonce the next one is signed with.
+    nonce_lock: Arc<Mutex<()>>,
+    dead_letters: Option<Arc<DeadLetterStore>>,
+    tx_observer: Option<Arc<dyn TxObserver>>,
 }
 
 impl<R: RpcConnection, I: Indexer<R>> Clone for EpochManager<R, I> {
@@ -81,62 +338,339 @@ impl<R: RpcConnection, I: Indexer<R>> Clone for EpochManager<R, I> {
             protocol_config: self.protocol_config.clone(),
             rpc_pool: self.rpc_pool.clone(),
             indexer: self.indexer.clone(),
+            signer: self.signer.clone(),
             work_report_sender: self.work_report_sender.clone(),
+            event_sender: self.event_sender.clone(),
             processed_items_per_epoch_count: self.processed_items_per_epoch_count.clone(),
             trees: self.trees.clone(),
             slot_tracker: self.slot_tracker.clone(),
+            max_instructions_per_transaction: self.max_instructions_per_transaction.clone(),
+            applied_indexer_updates: self.applied_indexer_updates.clone(),
+            epoch_tasks: self.epoch_tasks.clone(),
+            monitor_tasks: self.monitor_tasks.clone(),
+            work_tasks: self.work_tasks.clone(),
+            queue_item_ages: self.queue_item_ages.clone(),
+            tree_sequence_numbers: self.tree_sequence_numbers.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            landing_latencies: self.landing_latencies.clone(),
+            deferred_queues: self.deferred_queues.clone(),
+            queue_task_semaphore: self.queue_task_semaphore.clone(),
+            address_batch_semaphore: self.address_batch_semaphore.clone(),
+            state_batch_semaphore: self.state_batch_semaphore.clone(),
+            tree_semaphores: self.tree_semaphores.clone(),
+            paused: self.paused.clone(),
+            low_balance: self.low_balance.clone(),
+            health: self.health.clone(),
+            nonce_lock: self.nonce_lock.clone(),
+            dead_letters: self.dead_letters.clone(),
+            tx_observer: self.tx_observer.clone(),
         }
     }
 }
 
 impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         config: Arc<ForesterConfig>,
-        protocol_config: Arc<ProtocolConfig>,
+        protocol_config: Arc<ArcSwap<ProtocolConfig>>,
         rpc_pool: Arc<SolanaRpcPool<R>>,
-        indexer: Arc<Mutex<I>>,
+        indexer: Arc<RwLock<I>>,
+        signer: Arc<dyn ForesterSigner>,
         work_report_sender: mpsc::Sender<WorkReport>,
+        event_sender: Option<mpsc::Sender<ForesterEvent>>,
         trees: Vec<TreeAccounts>,
         slot_tracker: Arc<SlotTracker>,
+        health: Arc<HealthState>,
     ) -> Result<Self> {
+        let rate_limiter = config
+            .max_tps
+            .map(|max_tps| Arc::new(RateLimiter::new(max_tps)));
+        let queue_task_semaphore = Arc::new(Semaphore::new(config.max_concurrent_queue_tasks));
+        let address_batch_semaphore = Arc::new(Semaphore::new(
+            config
+                .address_max_concurrent_batches
+                .unwrap_or(config.indexer_max_concurrent_batches),
+        ));
+        let state_batch_semaphore = Arc::new(Semaphore::new(
+            config
+                .state_max_concurrent_batches
+                .unwrap_or(config.indexer_max_concurrent_batches),
+        ));
+        let dead_letters = config
+            .dead_letter_path
+            .as_ref()
+            .map(|path| Arc::new(DeadLetterStore::new(path)));
+        let tx_observer = config.tx_observer.clone();
         Ok(Self {
             config,
             protocol_config,
             rpc_pool,
             indexer,
+            signer,
             work_report_sender,
+            event_sender,
             processed_items_per_epoch_count: Arc::new(Mutex::new(HashMap::new())),
-            trees,
+            trees: Arc::new(Mutex::new(trees)),
             slot_tracker,
+            max_instructions_per_transaction: Arc::new(AtomicUsize::new(usize::MAX)),
+            applied_indexer_updates: Arc::new(Mutex::new(AppliedIndexerUpdates::default())),
+            epoch_tasks: Arc::new(Mutex::new(Vec::new())),
+            monitor_tasks: Arc::new(Mutex::new(Vec::new())),
+            work_tasks: Arc::new(std::sync::Mutex::new(Vec::new())),
+            queue_item_ages: Arc::new(Mutex::new(QueueItemAgeTracker::default())),
+            tree_sequence_numbers: Arc::new(Mutex::new(HashMap::new())),
+            rate_limiter,
+            landing_latencies: Arc::new(Mutex::new(LatencyRecorder::default())),
+            deferred_queues: Arc::new(Mutex::new(HashSet::new())),
+            queue_task_semaphore,
+            address_batch_semaphore,
+            state_batch_semaphore,
+            tree_semaphores: Arc::new(Mutex::new(HashMap::new())),
+            paused: Arc::new(AtomicBool::new(false)),
+            low_balance: Arc::new(AtomicBool::new(false)),
+            health,
+            nonce_lock: Arc::new(Mutex::new(())),
+            dead_letters,
+            tx_observer,
         })
     }
 
+    /// Percentiles of recent transaction landing latencies, `None` until a
+    /// transaction has landed.
+    pub async fn latency_percentiles(&self) -> Option<LatencyPercentiles> {
+        // Sort a copy so that recording isn't blocked while sorting.
+        let landing_latencies = self.landing_latencies.lock().await.clone();
+        landing_latencies.percentiles()
+    }
+
+    async fn record_landing_latency(&self, latency: Duration) {
+        self.landing_latencies.lock().await.record(latency);
+    }
+
+    /// Updates the landing latency metric each `queue_metrics_interval_seconds`,
+    /// so that percentiles aren't computed for every landed transaction.
+    async fn emit_landing_latency(&self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            self.config.queue_metrics_interval_seconds.max(1),
+        ));
+        loop {
+            interval.tick().await;
+            if let Some(percentiles) = self.latency_percentiles().await {
+                set_transaction_landing_latency(&percentiles);
+            }
+        }
+    }
+
     pub async fn run(self: Arc<Self>) -> Result<()> {
-        let (tx, mut rx) = mpsc::channel(100);
+        // Epochs are only received while fewer than `max_concurrent_epochs`
+        // are processed, so the monitor blocks on a full channel.
+        let (tx, mut rx) = mpsc::channel(1);
+        let epoch_semaphore = Arc::new(Semaphore::new(self.config.max_concurrent_epochs.max(1)));
 
         let monitor_handle = tokio::spawn({
             let self_clone = Arc::clone(&self);
             async move { self_clone.monitor_epochs(tx).await }
         });
+        let tree_discovery_handle = tokio::spawn({
+            let self_clone = Arc::clone(&self);
+            async move { self_clone.discover_trees().await }
+        });
+        let balance_monitor_handle = tokio::spawn({
+            let self_clone = Arc::clone(&self);
+            async move { self_clone.monitor_payer_balance().await }
+        });
+        let latency_emitter_handle = tokio::spawn({
+            let self_clone = Arc::clone(&self);
+            async move { self_clone.emit_landing_latency().await }
+        });
+        *self.monitor_tasks.lock().await = vec![
+            monitor_handle.abort_handle(),
+            tree_discovery_handle.abort_handle(),
+            balance_monitor_handle.abort_handle(),
+            latency_emitter_handle.abort_handle(),
+        ];
 
-        while let Some(epoch) = rx.recv().await {
+        loop {
+            let permit = match epoch_semaphore.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    warn!(
+                        "Processing {} epochs already, waiting for one to complete",
+                        self.config.max_concurrent_epochs
+                    );
+                    epoch_semaphore
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .map_err(|e| ForesterError::Custom(e.to_string()))?
+                }
+            };
+            let Some(epoch) = rx.recv().await else {
+                break;
+            };
             let self_clone = Arc::clone(&self);
-            tokio::spawn(async move {
+            let handle = tokio::spawn(async move {
                 if let Err(e) = self_clone.process_epoch(epoch).await {
                     error!("Error processing epoch {}: {:?}", epoch, e);
                 }
+                drop(permit);
             });
+            let mut epoch_tasks = self.epoch_tasks.lock().await;
+            epoch_tasks.retain(|task| !task.is_finished());
+            epoch_tasks.push(handle);
         }
 
+        tree_discovery_handle.abort();
+        balance_monitor_handle.abort();
+        latency_emitter_handle.abort();
         monitor_handle.await??;
         Ok(())
     }
 
+    /// Periodically re-fetches the trees so that trees created while the
+    /// forester is running, e.g. by another forester's rollover, are picked up
+    /// without a restart. An interval of 0 disables tree discovery.
+    async fn discover_trees(&self) {
+        if self.config.tree_discovery_interval_seconds == 0 {
+            return;
+        }
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            self.config.tree_discovery_interval_seconds,
+        ));
+        // The first tick completes immediately and the trees were just fetched.
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let fetched = match self.rpc_pool.get_connection().await {
+                Ok(rpc) => try_fetch_trees(&*rpc).await.map_err(ForesterError::from),
+                Err(e) => Err(e.into()),
+            };
+            let mut trees = match fetched {
+                Ok(trees) => trees,
+                Err(e) => {
+                    warn!("Failed to refresh trees: {:?}", e);
+                    continue;
+                }
+            };
+            if let Some(tree_type) = self.config.tree_type {
+                trees.retain(|tree| tree.tree_type == tree_type);
+            }
+
+            let mut current_trees = self.trees.lock().await;
+            let (added, removed) = diff_trees(&current_trees, &trees);
+            for tree in &added {
+                info!(
+                    "Discovered {:?} tree {}, scheduling it from the next epoch on",
+                    tree.tree_type, tree.merkle_tree
+                );
+            }
+            for tree in &removed {
+                info!(
+                    "{:?} tree {} is no longer found on chain",
+                    tree.tree_type, tree.merkle_tree
+                );
+            }
+            *current_trees = trees;
+        }
+    }
+
+    /// Spawns a queue or batch task, which [`Self::drain`] aborts if it is
+    /// still running once the epoch tasks are done.
+    fn spawn_work_task<F>(&self, future: F) -> JoinHandle<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(future);
+        let mut work_tasks = self.work_tasks.lock().unwrap();
+        work_tasks.retain(|task| !task.is_finished());
+        work_tasks.push(handle.abort_handle());
+        handle
+    }
+
+    /// Stops the monitors and waits for in-flight epoch tasks to complete,
+    /// aborting the ones still running once the grace period has elapsed.
+    /// Queue and batch tasks still running afterwards are aborted too.
+    async fn drain(&self, grace_period: Duration) {
+        for monitor_task in self.monitor_tasks.lock().await.drain(..) {
+            monitor_task.abort();
+        }
+        self.drain_epoch_tasks(grace_period).await;
+        for work_task in self.work_tasks.lock().unwrap().drain(..) {
+            work_task.abort();
+        }
+    }
+
+    async fn drain_epoch_tasks(&self, grace_period: Duration) {
+        let epoch_tasks = std::mem::take(&mut *self.epoch_tasks.lock().await);
+        let epoch_tasks: Vec<_> = epoch_tasks
+            .into_iter()
+            .filter(|task| !task.is_finished())
+            .collect();
+        if epoch_tasks.is_empty() {
+            return;
+        }
+        info!(
+            "Waiting up to {:?} for {} in-flight epoch tasks to complete",
+            grace_period,
+            epoch_tasks.len()
+        );
+        let abort_handles: Vec<_> = epoch_tasks.iter().map(|task| task.abort_handle()).collect();
+        if timeout(grace_period, join_all(epoch_tasks)).await.is_err() {
+            warn!("Shutdown grace period elapsed, aborting remaining epoch tasks");
+            for abort_handle in abort_handles {
+                abort_handle.abort();
+            }
+        } else {
+            info!("All in-flight epoch tasks completed");
+        }
+    }
+
+    fn protocol_config(&self) -> Arc<ProtocolConfig> {
+        self.protocol_config.load_full()
+    }
+
+    /// Re-fetches the protocol config, which governance may update on chain,
+    /// so that phases aren't computed from stale phase lengths.
+    async fn refresh_protocol_config(&self) -> Result<()> {
+        let protocol_config = {
+            let mut rpc = self.rpc_pool.get_connection().await?;
+            try_get_protocol_config(&mut *rpc, &self.config.registry()).await?
+        };
+        let current = self.protocol_config();
+        if *current == protocol_config {
+            return Ok(());
+        }
+        if phase_parameters_changed(&current, &protocol_config) {
+            warn!(
+                "Protocol config phase parameters changed: genesis slot {} -> {}, slot length {} -> {}, registration phase {} -> {}, active phase {} -> {}, report work phase {} -> {}",
+                current.genesis_slot,
+                protocol_config.genesis_slot,
+                current.slot_length,
+                protocol_config.slot_length,
+                current.registration_phase_length,
+                protocol_config.registration_phase_length,
+                current.active_phase_length,
+                protocol_config.active_phase_length,
+                current.report_work_phase_length,
+                protocol_config.report_work_phase_length,
+            );
+        } else {
+            info!("Protocol config changed: {:?}", protocol_config);
+        }
+        self.protocol_config.store(Arc::new(protocol_config));
+        Ok(())
+    }
+
     async fn monitor_epochs(&self, tx: mpsc::Sender<u64>) -> Result<()> {
         let mut last_epoch: Option<u64> = None;
+        let refresh_interval =
+            Duration::from_secs(self.config.protocol_config_refresh_interval_seconds);
         debug!("Starting epoch monitor");
 
         loop {
+            if let Err(e) = self.refresh_protocol_config().await {
+                warn!("Failed to refresh protocol config: {:?}", e);
+            }
             let (slot, current_epoch) = self.get_current_slot_and_epoch().await?;
             debug!(
                 "last_epoch: {:?}, current_epoch: {:?}, slot: {:?}",
@@ -144,8 +678,21 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             );
             if last_epoch.map_or(true, |last| current_epoch > last) {
                 debug!("New epoch detected: {}", current_epoch);
-                let phases = get_epoch_phases(&self.protocol_config, current_epoch);
-                if slot < phases.registration.end {
+                let phases = get_epoch_phases(&self.protocol_config(), current_epoch);
+                let registration_check_slot = if self.config.force_register {
+                    let mut rpc = self.rpc_pool.get_connection().await?;
+                    self.registration_check_slot(&mut *rpc, slot).await?
+                } else {
+                    slot
+                };
+                // An observer doesn't register, so it can still observe an
+                // epoch whose registration phase has ended.
+                let epoch_end = if self.config.observer {
+                    phases.active.end
+                } else {
+                    phases.registration.end
+                };
+                if registration_check_slot < epoch_end {
                     tx.send(current_epoch).await.map_err(|e| {
                         ForesterError::Custom(format!("Failed to send new epoch: {}", e))
                     })?;
@@ -154,22 +701,43 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             }
 
             let next_epoch = current_epoch + 1;
-            let next_phases = get_epoch_phases(&self.protocol_config, next_epoch);
-            let mut rpc = self.rpc_pool.get_connection().await?;
+            let next_phases = get_epoch_phases(&self.protocol_config(), next_epoch);
             let slots_to_wait = next_phases.registration.start.saturating_sub(slot);
             info!(
                 "Waiting for epoch {} registration phase to start. Current slot: {}, Registration phase start slot: {}, Slots to wait: {}",
                 next_epoch, slot, next_phases.registration.start, slots_to_wait
             );
 
-            if let Err(e) = wait_until_slot_reached(
-                &mut *rpc,
-                &self.slot_tracker,
-                next_phases.registration.start,
+            // Epoch tasks may keep the pool busy, so a connection is only
+            // checked out once the registration phase is about to open. The
+            // wait is cut short to refresh the protocol config, the phases
+            // are then recomputed.
+            if timeout(
+                refresh_interval,
+                wait_until_estimated_slot_reached(
+                    &self.slot_tracker,
+                    next_phases.registration.start,
+                ),
             )
             .await
+            .is_err()
             {
+                continue;
+            }
+            let wait_result = match self.rpc_pool.get_connection().await {
+                Ok(mut rpc) => {
+                    wait_until_slot_reached(
+                        &mut *rpc,
+                        &self.slot_tracker,
+                        next_phases.registration.start,
+                    )
+                    .await
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = wait_result {
                 error!("Error waiting for next registration phase: {:?}", e);
+                sleep(slot_duration()).await;
                 continue;
             }
         }
@@ -188,25 +756,158 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             .entry(epoch)
             .or_insert_with(|| AtomicUsize::new(0))
             .fetch_add(1, Ordering::Relaxed);
+        self.health.record_processed_item();
+    }
+
+    /// Stops starting new work batches until [`Self::resume`] is called.
+    /// Batches in flight are completed.
+    pub fn pause(&self) {
+        if !self.paused.swap(true, Ordering::SeqCst) {
+            info!("Forester paused, no new work batches are started");
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.paused.swap(false, Ordering::SeqCst) {
+            info!("Forester resumed");
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst) || self.low_balance.load(Ordering::SeqCst)
+    }
+
+    /// Pauses work while the payer balance is below `min_payer_balance`, so
+    /// that batches don't fail one after another for insufficient funds.
+    async fn monitor_payer_balance(&self) {
+        let Some(min_balance) = self.config.min_payer_balance else {
+            return;
+        };
+        let payer = self.signer.pubkey();
+        loop {
+            let balance = match self.rpc_pool.get_connection().await {
+                Ok(mut rpc) => rpc.get_balance(&payer).await.map_err(ForesterError::from),
+                Err(e) => Err(e.into()),
+            };
+            match balance {
+                Ok(balance) => {
+                    set_payer_balance(balance);
+                    let low = balance < min_balance;
+                    if low != self.low_balance.swap(low, Ordering::SeqCst) {
+                        if low {
+                            error!(
+                                "Payer {} balance of {} lamports is below the minimum of {}, pausing work until it is topped up",
+                                payer, balance, min_balance
+                            );
+                        } else {
+                            info!(
+                                "Payer {} balance of {} lamports is above the minimum of {} again, resuming work",
+                                payer, balance, min_balance
+                            );
+                        }
+                    }
+                }
+                Err(e) => warn!("Failed to check payer balance: {:?}", e),
+            }
+            sleep(PAYER_BALANCE_CHECK_INTERVAL).await;
+        }
+    }
+
+    async fn handle_control_commands(&self, mut receiver: mpsc::Receiver<ControlCommand>) {
+        while let Some(command) = receiver.recv().await {
+            match command {
+                ControlCommand::Pause => self.pause(),
+                ControlCommand::Resume => self.resume(),
+            }
+        }
     }
 
+    /// Circuit breaker against runaway transaction counts, e.g. when an
+    /// indexer returns proofs for a phantom queue.
+    async fn transaction_cap_reached(&self, epoch: u64) -> bool {
+        match self.config.max_transactions_per_epoch {
+            Some(max_transactions) => {
+                self.get_processed_items_count(epoch).await >= max_transactions
+            }
+            None => false,
+        }
+    }
+
+    /// Doesn't wait for a full channel, so that a slow subscriber can't hold
+    /// up the forester; the event is dropped instead.
+    async fn emit_event(&self, event: ForesterEvent) {
+        if let Some(event_sender) = &self.event_sender {
+            if let Err(e) = event_sender.try_send(event) {
+                debug!("Dropping forester event: {:?}", e);
+            }
+        }
+    }
+
+    #[instrument(
+        skip(self),
+        fields(epoch = epoch, forester_pubkey = %self.signer.pubkey())
+    )]
     async fn process_epoch(&self, epoch: u64) -> Result<()> {
         debug!("Processing epoch: {}", epoch);
+        if self.config.observer {
+            return self.observe_epoch(epoch).await;
+        }
 
         // Registration
-        let mut registration_info = self.register_for_epoch(epoch).await?;
+        let mut registration_info = match self.register_for_epoch(epoch).await {
+            Ok(registration_info) => registration_info,
+            Err(ForesterError::AlreadyRegistered) => {
+                info!(
+                    "Already registered for epoch {}. Recovering registration info.",
+                    epoch
+                );
+                self.recover_registration_info(epoch).await?
+            }
+            Err(ForesterError::DryRun) => {
+                info!(
+                    "Registration for epoch {} only simulated, skipping epoch",
+                    epoch
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        self.emit_event(ForesterEvent::Registered { epoch }).await;
 
         // Wait for active phase
-        registration_info = self.wait_for_active_phase(&registration_info).await?;
+        registration_info = match self.wait_for_active_phase(&registration_info).await {
+            Ok(registration_info) => registration_info,
+            Err(ForesterError::DryRun) => {
+                info!(
+                    "Finalizing the registration for epoch {} only simulated, skipping epoch",
+                    epoch
+                );
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        self.emit_event(ForesterEvent::ActivePhaseStarted { epoch })
+            .await;
 
-        // Perform work
-        self.perform_active_work(&registration_info).await?;
+        // Perform work. Errors are only returned after reporting, so that
+        // items processed before the failure are still credited.
+        let work_result = self.perform_active_work(&registration_info).await;
+        if let Err(e) = &work_result {
+            error!(
+                "Active work for epoch {} failed, reporting the work done so far: {:?}",
+                epoch, e
+            );
+        }
 
-        // Wait for report work phase
-        self.wait_for_report_work_phase(&registration_info).await?;
+        if self.config.enable_reporting {
+            // Wait for report work phase
+            self.wait_for_report_work_phase(&registration_info).await?;
 
-        // Report work
-        self.report_work(&registration_info).await?;
+            // Report work
+            self.report_work(&registration_info).await?;
+        }
+        self.applied_indexer_updates.lock().await.clear_epoch(epoch);
+        work_result?;
 
         // TODO: implement
         // self.claim(&registration_info).await?;
@@ -215,60 +916,172 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
         Ok(())
     }
 
+    /// Logs the queue depth, the eligibility of the forester if it is
+    /// registered, e.g. by another instance with the same key, and the
+    /// estimated number of transactions per tree once per light slot of the
+    /// active phase.
+    async fn observe_epoch(&self, epoch: u64) -> Result<()> {
+        let phases = get_epoch_phases(&self.protocol_config(), epoch);
+        {
+            let mut rpc = self.rpc_pool.get_connection().await?;
+            wait_until_slot_reached(&mut *rpc, &self.slot_tracker, phases.active.start).await?;
+        }
+        let trees = self.trees.lock().await.clone();
+        let epoch_info = {
+            let mut rpc = self.rpc_pool.get_connection().await?;
+            fetch_forester_epoch_info(
+                &mut *rpc,
+                &self.config.registry(),
+                &self.protocol_config(),
+                &self.signer.pubkey(),
+                epoch,
+                &trees,
+            )
+            .await
+        };
+        let schedules = match epoch_info {
+            Ok(epoch_info) => {
+                epoch_info.log_eligibility_summary();
+                epoch_info.trees
+            }
+            Err(e) => {
+                info!("Observing epoch {} without eligibility: {:?}", epoch, e);
+                trees.into_iter().map(TreeForesterSchedule::new).collect()
+            }
+        };
+
+        let interval = slot_duration() * self.protocol_config().slot_length as u32;
+        loop {
+            let current_slot = self.slot_tracker.estimated_current_slot();
+            if current_slot >= phases.active.end {
+                break;
+            }
+            let light_slot = current_slot.saturating_sub(phases.active.start)
+                / self.protocol_config().slot_length;
+            let work_items = {
+                let mut rpc = self.rpc_pool.get_connection().await?;
+                self.fetch_work_items(&mut *rpc, &schedules, current_slot)
+                    .await?
+            };
+            self.health.record_queue_update();
+            for tree in &schedules {
+                let queue_items = work_items
+                    .iter()
+                    .filter(|item| item.tree_account == tree.tree_accounts)
+                    .count();
+                let eligible = tree
+                    .slots
+                    .get(light_slot as usize)
+                    .map(|slot| slot.is_some());
+                info!(
+                    "Epoch {} light slot {} {:?} tree {}: {} queue items, ~{} transactions, eligible: {:?}",
+                    epoch,
+                    light_slot,
+                    tree.tree_accounts.tree_type,
+                    tree.tree_accounts.merkle_tree,
+                    queue_items,
+                    queue_items.div_ceil(self.config.transaction_batch_size.max(1)),
+                    eligible
+                );
+            }
+            sleep(interval).await;
+        }
+        Ok(())
+    }
+
     async fn get_current_slot_and_epoch(&self) -> Result<(u64, u64)> {
         let slot = self.slot_tracker.estimated_current_slot();
-        Ok((slot, self.protocol_config.get_current_epoch(slot)))
+        Ok((slot, self.protocol_config().get_current_epoch(slot)))
+    }
+
+    /// Slot used to check whether the registration window is still open.
+    /// With `force_register` the slot is fetched from the rpc instead of
+    /// using `estimated_slot`, which can drift on local validators.
+    async fn registration_check_slot(&self, rpc: &mut R, estimated_slot: u64) -> Result<u64> {
+        if !self.config.force_register {
+            return Ok(estimated_slot);
+        }
+        let slot = rpc.get_slot().await?;
+        self.slot_tracker.update(slot);
+        Ok(slot)
     }
 
     async fn register_for_epoch(&self, epoch: u64) -> Result<ForesterEpochInfo> {
+        let registration = get_epoch_phases(&self.protocol_config(), epoch).registration;
+        let offset = registration_offset(
+            &self.signer.pubkey(),
+            self.config.registration_jitter_slots,
+            registration.end.saturating_sub(registration.start),
+        );
+        if offset > 0 {
+            debug!(
+                "Waiting until slot {} to register for epoch {}",
+                registration.start + offset,
+                epoch
+            );
+            wait_until_estimated_slot_reached(&self.slot_tracker, registration.start + offset)
+                .await;
+        }
         info!("Registering for epoch: {}", epoch);
-        let mut rpc = self.rpc_pool.get_connection().await?;
+        // The previous epoch's work may still hold most pooled connections.
+        let mut rpc = self
+            .rpc_pool
+            .get_connection_with_retry(self.config.max_retries as u32, slot_duration())
+            .await?;
         let slot = rpc.get_slot().await?;
-        let phases = get_epoch_phases(&self.protocol_config, epoch);
+        let phases = get_epoch_phases(&self.protocol_config(), epoch);
 
         if slot < phases.registration.end {
-            // TODO: check if we're already registered
-            /*
-            let (forester_epoch_pda_pubkey, _) = Pubkey::find_program_address(
-                &[
-                    b"forester_epoch",
-                    &epoch.to_le_bytes(),
-                    &self.config.payer_keypair.pubkey().to_bytes(),
-                ],
-                &light_registry::id(),
-            );
-
-            let existing_registration = rpc_guard
+            let forester_epoch_pda_pubkey = self
+                .config
+                .registry()
+                .forester_epoch_pda(&self.signer.pubkey(), epoch);
+            let existing_registration = rpc
                 .get_anchor_account::<ForesterEpochPda>(&forester_epoch_pda_pubkey)
                 .await?;
-
-            if let Some(existing_pda) = existing_registration {
-                info!("Already registered for epoch {}. Recovering registration info.", epoch);
-                let registration_info = self.recover_registration_info(epoch, existing_pda).await?;
-                return Ok(registration_info);
+            if existing_registration.is_some() {
+                return Err(ForesterError::AlreadyRegistered);
             }
-             */
 
             let registration_info = {
                 debug!("Registering epoch {}", epoch);
-                let registered_epoch = match Epoch::register(
-                    &mut *rpc,
-                    &self.protocol_config,
-                    &self.config.payer_keypair,
-                )
-                .await
-                {
-                    Ok(Some(epoch)) => epoch,
-                    Ok(None) => {
-                        return Err(ForesterError::Custom(
-                            "Epoch::register returned None".into(),
-                        ))
-                    }
-                    Err(e) => {
-                        return Err(ForesterError::Custom(format!(
-                            "Epoch::register failed: {:?}",
-                            e
-                        )))
+                let mut retries = 0;
+                let registered_epoch = loop {
+                    match self.register_forester_epoch_pda(&mut *rpc, epoch).await {
+                        Ok(epoch) => break epoch,
+                        // A previous attempt may have landed despite failing.
+                        Err(ForesterError::RpcError(e)) if is_account_already_in_use(&e) => {
+                            return Err(ForesterError::AlreadyRegistered)
+                        }
+                        Err(ForesterError::RpcError(e))
+                            if is_transient(&e) && retries < self.config.max_retries =>
+                        {
+                            let delay = self.config.retry_policy.retry_delay(retries);
+                            let current_slot = self
+                                .registration_check_slot(
+                                    &mut *rpc,
+                                    self.slot_tracker.estimated_current_slot(),
+                                )
+                                .await?;
+                            let remaining_slots =
+                                phases.registration.end.saturating_sub(current_slot);
+                            let delay_slots =
+                                (delay.as_nanos() / slot_duration().as_nanos()) as u64;
+                            if remaining_slots <= delay_slots {
+                                warn!(
+                                    "Registration window of epoch {} closes in {} slots, not retrying: {:?}",
+                                    epoch, remaining_slots, e
+                                );
+                                return Err(ForesterError::RpcError(e));
+                            }
+                            retries += 1;
+                            warn!(
+                                "Failed to register for epoch {} (attempt {}), {} slots left in the registration window: {:?}",
+                                epoch, retries, remaining_slots, e
+                            );
+                            sleep(delay).await;
+                        }
+                        Err(e) => return Err(e),
                     }
                 };
 
@@ -304,65 +1117,170 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
                 "Too late to register for epoch {}. Current slot: {}, Registration end: {}",
                 epoch, slot, phases.registration.end
             );
-            Err(ForesterError::Custom(
-                "Too late to register for epoch".into(),
-            ))
+            Err(ForesterError::TooLateToRegister)
         }
     }
 
-    // TODO: implement
-    #[allow(dead_code)]
-    async fn recover_registration_info(
-        &self,
-        _epoch: u64,
-        _existing_pda: ForesterEpochPda,
-    ) -> Result<ForesterEpochInfo> {
-        unimplemented!()
-        // let rpc = self.rpc_pool.get_connection().await;
-        //
-        // let registration_info = ForesterEpochInfo {
-        //     epoch: ...,
-        //     epoch_pda: existing_pda,
-        //     trees: ...,
-        // };
-        // Ok(registration_info)
+    async fn register_forester_epoch_pda(&self, rpc: &mut R, epoch: u64) -> Result<Epoch> {
+        let authority = self.signer.pubkey();
+        let registry = self.config.registry();
+        let ix = registry.register_forester_epoch_pda_instruction(&authority, epoch);
+        let signature = self
+            .send_signed_transaction(rpc, "Register forester epoch pda", &[ix])
+            .await?
+            .ok_or(ForesterError::DryRun)?;
+        rpc.confirm_transaction(signature).await?;
+
+        let slot = rpc.get_slot().await?;
+        let phases = get_epoch_phases(&self.protocol_config(), epoch);
+        Ok(Epoch {
+            epoch,
+            epoch_pda: registry.epoch_pda(epoch),
+            forester_epoch_pda: registry.forester_epoch_pda(&authority, epoch),
+            state: phases.get_current_epoch_state(slot),
+            phases,
+            merkle_trees: Vec::new(),
+        })
     }
 
-    async fn wait_for_active_phase(
+    /// Sends a transaction paid for and signed by the forester signer, with
+    /// the same compute budget instructions as transaction batches, so that
+    /// registration and reporting transactions land under congestion too.
+    /// With `dry_run` the transaction is only simulated and `None` is
+    /// returned.
+    async fn send_signed_transaction(
         &self,
-        epoch_info: &ForesterEpochInfo,
-    ) -> Result<ForesterEpochInfo> {
-        info!(
-            "Waiting for active phase of epoch: {}",
-            epoch_info.epoch.epoch
-        );
+        rpc: &mut R,
+        context: &str,
+        instructions: &[Instruction],
+    ) -> Result<Option<Signature>> {
+        let recent_blockhash = rpc.get_latest_blockhash().await?;
+        let mut ixs = self.config.compute_budget_instructions();
+        ixs.extend_from_slice(instructions);
+        let mut transaction = Transaction::new_with_payer(&ixs, Some(&self.signer.pubkey()));
+        sign_transaction(&*self.signer, &mut transaction, &[], recent_blockhash).await?;
+        if self.config.dry_run {
+            let simulation = rpc.simulate_transaction(&transaction)?;
+            log_simulation_result(context, &transaction, &simulation);
+            return Ok(None);
+        }
+        Ok(Some(rpc.process_transaction(transaction).await?))
+    }
+
+    async fn recover_registration_info(&self, epoch: u64) -> Result<ForesterEpochInfo> {
+        let mut rpc = self.rpc_pool.get_connection().await?;
+        let registry = self.config.registry();
+        let forester_epoch_pda_pubkey = registry.forester_epoch_pda(&self.signer.pubkey(), epoch);
+        let existing_pda = rpc
+            .get_anchor_account::<ForesterEpochPda>(&forester_epoch_pda_pubkey)
+            .await?
+            .ok_or_else(|| ForesterError::Custom("Failed to get ForesterEpochPda".to_string()))?;
+
+        let slot = rpc.get_slot().await?;
+        let phases = get_epoch_phases(&self.protocol_config(), epoch);
+        let registered_epoch = Epoch {
+            epoch,
+            epoch_pda: registry.epoch_pda(epoch),
+            forester_epoch_pda: forester_epoch_pda_pubkey,
+            state: phases.get_current_epoch_state(slot),
+            phases,
+            merkle_trees: Vec::new(),
+        };
+
+        let registration_info = ForesterEpochInfo {
+            epoch: registered_epoch,
+            epoch_pda: existing_pda,
+            trees: Vec::new(),
+        };
+        debug!("Recovered Registration Info: {:?}", registration_info);
+        Ok(registration_info)
+    }
+
+    async fn wait_for_active_phase(
+        &self,
+        epoch_info: &ForesterEpochInfo,
+    ) -> Result<ForesterEpochInfo> {
+        info!(
+            "Waiting for active phase of epoch: {}",
+            epoch_info.epoch.epoch
+        );
         let mut rpc = self.rpc_pool.get_connection().await?;
         let active_phase_start_slot = epoch_info.epoch.phases.active.start;
         wait_until_slot_reached(&mut *rpc, &self.slot_tracker, active_phase_start_slot).await?;
 
         // TODO: we can put this ix into every tx of the first batch of the current active phase
-        let ix = create_finalize_registration_instruction(
-            &self.config.payer_keypair.pubkey(),
-            epoch_info.epoch.epoch,
-        );
-        rpc.create_and_send_transaction(
-            &[ix],
-            &self.config.payer_keypair.pubkey(),
-            &[&self.config.payer_keypair],
-        )
-        .await?;
-
         let mut epoch_info = (*epoch_info).clone();
-        epoch_info.epoch_pda = rpc
-            .get_anchor_account::<ForesterEpochPda>(&epoch_info.epoch.forester_epoch_pda)
-            .await?
-            .ok_or_else(|| ForesterError::Custom("Failed to get ForesterEpochPda".to_string()))?;
+        epoch_info.epoch_pda = self.finalize_registration(&mut *rpc, &epoch_info).await?;
 
         let slot = rpc.get_slot().await?;
-        epoch_info.add_trees_with_schedule(&self.trees, slot);
+        epoch_info.add_trees_with_schedule(&self.trees.lock().await, slot);
+        self.health.record_schedule(epoch_info.schedule_report());
+        if self.config.log_eligibility_summary {
+            epoch_info.log_eligibility_summary();
+        }
         Ok(epoch_info)
     }
 
+    /// Sends the finalize registration transaction until the forester epoch
+    /// PDA holds the total epoch weight, which the schedule is computed from.
+    /// A dropped transaction would otherwise leave the PDA unfinalized.
+    async fn finalize_registration(
+        &self,
+        rpc: &mut R,
+        epoch_info: &ForesterEpochInfo,
+    ) -> Result<ForesterEpochPda> {
+        const MAX_FINALIZE_ATTEMPTS: usize = 3;
+
+        let epoch = epoch_info.epoch.epoch;
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let ix = self
+                .config
+                .registry()
+                .finalize_registration_instruction(&self.signer.pubkey(), epoch);
+            let result = self
+                .send_signed_transaction(rpc, "Finalize registration", &[ix])
+                .await;
+            let epoch_pda = rpc
+                .get_anchor_account::<ForesterEpochPda>(&epoch_info.epoch.forester_epoch_pda)
+                .await?
+                .ok_or_else(|| {
+                    ForesterError::Custom("Failed to get ForesterEpochPda".to_string())
+                })?;
+            if epoch_pda.total_epoch_weight.is_some() {
+                if let Err(e) = result {
+                    debug!(
+                        "Finalize registration transaction for epoch {} failed, but registration is finalized: {:?}",
+                        epoch, e
+                    );
+                }
+                return Ok(epoch_pda);
+            }
+            if let Ok(None) = result {
+                return Err(ForesterError::DryRun);
+            }
+
+            let current_slot = rpc.get_slot().await?;
+            if attempt >= MAX_FINALIZE_ATTEMPTS
+                || current_slot >= epoch_info.epoch.phases.active.end
+            {
+                return Err(match result {
+                    Err(e) => e,
+                    Ok(signature) => ForesterError::Custom(format!(
+                        "Registration for epoch {} is not finalized after transaction {:?}",
+                        epoch, signature
+                    )),
+                });
+            }
+            warn!(
+                "Registration for epoch {} is not finalized (attempt {}), retrying: {:?}",
+                epoch, attempt, result
+            );
+            sleep(slot_duration()).await;
+        }
+    }
+
     async fn setup_pubsub_client(
         &self,
         queue_pubkeys: &std::collections::HashSet<Pubkey>,
@@ -370,12 +1288,39 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
         setup_pubsub_client(&self.config, queue_pubkeys.clone()).await
     }
 
+    #[instrument(
+        skip(self, epoch_info),
+        fields(epoch = epoch_info.epoch.epoch, forester_pubkey = %self.signer.pubkey())
+    )]
     async fn perform_active_work(&self, epoch_info: &ForesterEpochInfo) -> Result<()> {
-        info!(
-            "Forester {}. Performing active work for epoch: {}",
-            self.config.payer_keypair.pubkey(),
-            epoch_info.epoch.epoch
-        );
+        info!("Performing active work");
+        let current_slot = self.slot_tracker.estimated_current_slot();
+        if !self.is_in_active_phase(current_slot, epoch_info)? {
+            debug!("Not in active phase, skipping active work");
+            return Ok(());
+        }
+
+        if self.config.enable_work_processing {
+            self.process_queue_updates(epoch_info).await?;
+        }
+
+        if self.config.enable_rollover {
+            self.rollover_eligible_trees(epoch_info).await?;
+        } else {
+            info!("Rollover is disabled, skipping rollover");
+        }
+
+        info!("Completed active work");
+        Ok(())
+    }
+
+    /// Processes the queues once, then processes queue updates until shortly
+    /// before the end of the active phase, see [`QUEUE_DRAIN_SLOTS`].
+    #[instrument(
+        skip(self, epoch_info),
+        fields(epoch = epoch_info.epoch.epoch, forester_pubkey = %self.signer.pubkey())
+    )]
+    async fn process_queue_updates(&self, epoch_info: &ForesterEpochInfo) -> Result<()> {
         let queue_pubkeys: std::collections::HashSet<Pubkey> = epoch_info
             .trees
             .iter()
@@ -386,71 +1331,148 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
         let active_phase_end = epoch_info.epoch.phases.active.end;
 
         debug!(
-            "Forester {}. Estimated current slot: {}, active phase end: {}",
-            self.config.payer_keypair.pubkey(),
-            current_slot,
-            active_phase_end
+            "Estimated current slot: {}, active phase end: {}",
+            current_slot, active_phase_end
         );
-        if self.is_in_active_phase(current_slot, epoch_info)? {
-            debug!(
-                "Forester {}. In active phase, processing initial queues",
-                self.config.payer_keypair.pubkey()
-            );
-            if let Err(e) = self.process_queues(epoch_info).await {
-                error!("Error processing initial queues: {:?}", e);
+        debug!("In active phase, processing initial queues");
+        if let Err(e) = self.process_queues(epoch_info).await {
+            error!("Error processing initial queues: {:?}", e);
+        }
+        if self.config.catch_up
+            && current_slot
+                >= epoch_info.epoch.phases.active.start + self.protocol_config().slot_length
+        {
+            if let Err(e) = self.catch_up(epoch_info).await {
+                error!("Error catching up on queue backlog: {:?}", e);
             }
-        } else {
-            debug!(
-                "Forester {}. Not in active phase, skipping initial queue processing",
-                self.config.payer_keypair.pubkey()
-            );
-            return Ok(());
         }
 
-        let (mut update_rx, shutdown_tx) = self.setup_pubsub_client(&queue_pubkeys).await?;
+        let (update_rx, shutdown_tx) = self.setup_pubsub_client(&queue_pubkeys).await?;
+        self.health.set_pubsub_connected(true);
+        let result = self.process_updates_and_drain(epoch_info, update_rx).await;
+        shutdown_tx.send(()).await.ok();
+        self.health.set_pubsub_connected(false);
+        result
+    }
 
-        debug!(
-            "Forester {}. Processing updates",
-            self.config.payer_keypair.pubkey()
-        );
-        let forester_pubkey = self.config.payer_keypair.pubkey();
-        loop {
+    /// Processes queue updates until the drain slot is reached, then
+    /// processes the queues a final time so that items which arrived with the
+    /// last updates aren't left for the next epoch. The final pass runs
+    /// while the slot is still in the active phase, so that its batches can
+    /// be sent; they are still only sent if the forester is eligible.
+    #[instrument(
+        skip(self, epoch_info),
+        fields(epoch = epoch_info.epoch.epoch, forester_pubkey = %self.signer.pubkey())
+    )]
+    async fn process_updates_and_drain(
+        &self,
+        epoch_info: &ForesterEpochInfo,
+        mut update_rx: mpsc::Receiver<QueueUpdate>,
+    ) -> Result<()> {
+        let active_phase_end = epoch_info.epoch.phases.active.end;
+        let drain_slot = active_phase_end
+            .saturating_sub(self.config.phase_end_margin_slots)
+            .saturating_sub(QUEUE_DRAIN_SLOTS);
+        debug!("Processing updates");
+        let queue_tasks = Arc::new(Mutex::new(QueueTasks::default()));
+        let drain_slot_reached = loop {
             tokio::select! {
                 Some(update) = update_rx.recv() => {
-                    debug!("Forester {}. Received update for queue: {:?}", forester_pubkey, update.pubkey);
-                    if update.slot >= active_phase_end {
-                        break;
+                    debug!("Received update for queue: {:?}", update.pubkey);
+                    self.health.record_queue_update();
+                    if update.slot >= drain_slot {
+                        break true;
+                    }
+                    if !queue_tasks.lock().await.start(update.pubkey) {
+                        debug!(
+                            "Queue {:?} is already being processed, coalescing update",
+                            update.pubkey
+                        );
+                        continue;
                     }
+                    let permit = self
+                        .queue_task_semaphore
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .map_err(|e| ForesterError::Custom(e.to_string()))?;
                     let epoch_info_clone = epoch_info.clone();
                     let self_clone = self.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = self_clone.process_queue(&epoch_info_clone, update.pubkey).await {
-                            error!("Forester {}. Error processing queue: {:?}", forester_pubkey, e);
+                    let queue_tasks = queue_tasks.clone();
+                    self.spawn_work_task(async move {
+                        loop {
+                            if let Err(e) = self_clone.process_queue(&epoch_info_clone, update.pubkey).await {
+                                error!("Error processing queue: {:?}", e);
+                            }
+                            if !queue_tasks.lock().await.finish(update.pubkey) {
+                                break;
+                            }
                         }
+                        drop(permit);
                     });
                 }
                 else => {
-                    debug!("Forester {}. No more updates", forester_pubkey);
-                    break
+                    debug!("No more updates");
+                    break false
                 },
             }
             let estimated_slot = self.slot_tracker.estimated_current_slot();
-            log::debug!(
-                "Forester {}. Estimated current slot: {}, active phase end: {}",
-                forester_pubkey,
-                estimated_slot,
+            debug!(
+                "Estimated current slot: {}, drain slot: {}, active phase end: {}",
+                estimated_slot, drain_slot, active_phase_end
+            );
+            if estimated_slot >= drain_slot {
+                break true;
+            }
+        };
+
+        if drain_slot_reached {
+            debug!(
+                "Active phase ends at slot {}, processing remaining queue items",
                 active_phase_end
             );
-            if estimated_slot >= active_phase_end {
-                break;
+            if let Err(e) = self.process_queues(epoch_info).await {
+                error!("Error processing remaining queue items: {:?}", e);
             }
         }
+        Ok(())
+    }
 
-        shutdown_tx.send(()).await.ok();
+    /// Processes the queue shortly before the end of the active phase, unless
+    /// it is already scheduled.
+    fn schedule_deferred_queue(&self, epoch_info: &ForesterEpochInfo, queue_pubkey: Pubkey) {
+        let key = (epoch_info.epoch.epoch, queue_pubkey);
+        let self_clone = self.clone();
+        let epoch_info = epoch_info.clone();
+        self.spawn_work_task(async move {
+            if !self_clone.deferred_queues.lock().await.insert(key) {
+                return;
+            }
+            let deadline = epoch_info
+                .epoch
+                .phases
+                .active
+                .end
+                .saturating_sub(DEFERRAL_DEADLINE_SLOTS);
+            wait_until_estimated_slot_reached(&self_clone.slot_tracker, deadline).await;
+            self_clone.deferred_queues.lock().await.remove(&key);
+            if let Err(e) = self_clone.process_queue(&epoch_info, queue_pubkey).await {
+                error!(
+                    "Error processing deferred queue {:?}: {:?}",
+                    queue_pubkey, e
+                );
+            }
+        });
+    }
+
+    async fn rollover_eligible_trees(&self, epoch_info: &ForesterEpochInfo) -> Result<()> {
         info!(
             "Forester {}. Checking for rollover eligibility...",
-            self.config.payer_keypair.pubkey()
+            self.signer.pubkey()
         );
+        // A failed rollover doesn't keep the remaining trees from being
+        // rolled over, the first failure is returned once all were attempted.
+        let mut first_error = None;
         for tree in &epoch_info.trees {
             let mut rpc = self.rpc_pool.get_connection().await?;
             if is_tree_ready_for_rollover(
@@ -460,45 +1482,166 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             )
             .await?
             {
-                self.perform_rollover(&tree.tree_accounts).await?;
+                drop(rpc);
+                if let Err(e) = self.perform_rollover(&tree.tree_accounts).await {
+                    first_error.get_or_insert(e);
+                }
             }
         }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
 
-        info!(
-            "Forester {}. Completed active work for epoch: {}",
-            self.config.payer_keypair.pubkey(),
-            epoch_info.epoch.epoch
-        );
-        Ok(())
+    fn batch_semaphore(&self, tree_type: TreeType) -> Arc<Semaphore> {
+        match tree_type {
+            TreeType::Address => self.address_batch_semaphore.clone(),
+            TreeType::State => self.state_batch_semaphore.clone(),
+        }
+    }
+
+    fn is_serial_tree(&self, merkle_tree: &Pubkey) -> bool {
+        self.config.serial_trees.contains(merkle_tree)
+    }
+
+    async fn tree_semaphore(&self, merkle_tree: Pubkey) -> Option<Arc<Semaphore>> {
+        let max_batches = if self.is_serial_tree(&merkle_tree) {
+            1
+        } else {
+            self.config.max_concurrent_batches_per_tree?
+        };
+        Some(
+            self.tree_semaphores
+                .lock()
+                .await
+                .entry(merkle_tree)
+                .or_insert_with(|| Arc::new(Semaphore::new(max_batches)))
+                .clone(),
+        )
+    }
+
+    /// Solana slot at which the forester next becomes eligible for `tree`,
+    /// `current_slot` if it is eligible right now and `None` if it is not
+    /// eligible again in this active phase.
+    fn next_eligible_slot(
+        &self,
+        epoch_info: &ForesterEpochInfo,
+        tree: &TreeForesterSchedule,
+        current_slot: u64,
+    ) -> Option<u64> {
+        let light_slot = current_slot.checked_sub(epoch_info.epoch.phases.active.start)?
+            / self.protocol_config().slot_length;
+        tree.next_eligible_slot(light_slot)
+            .map(|slot| slot.start_solana_slot.max(current_slot))
     }
 
     fn is_in_active_phase(&self, slot: u64, epoch_info: &ForesterEpochInfo) -> Result<bool> {
-        let current_epoch = self.protocol_config.get_current_active_epoch(slot)?;
+        let current_epoch = self.protocol_config().get_current_active_epoch(slot)?;
         if current_epoch != epoch_info.epoch.epoch {
             return Ok(false);
         }
 
         Ok(self
-            .protocol_config
+            .protocol_config()
             .is_active_phase(slot, epoch_info.epoch.epoch)
             .is_ok())
     }
 
+    /// Processes the queues until they are empty, a pass makes no progress,
+    /// e.g. because the remaining items are in other foresters' slots, or the
+    /// active phase nears its end. A backlog which built up before the
+    /// forester started produces no queue updates, so it would otherwise
+    /// only be drained as new items arrive.
+    async fn catch_up(&self, epoch_info: &ForesterEpochInfo) -> Result<()> {
+        let queues: Vec<Pubkey> = epoch_info
+            .trees
+            .iter()
+            .map(|tree| tree.tree_accounts.queue)
+            .collect();
+        let mut remaining = self.count_queue_items(&queues).await?;
+        let mut passes = 0;
+        while remaining > 0 {
+            let estimated_slot = self.slot_tracker.estimated_current_slot();
+            if is_near_active_phase_end(
+                estimated_slot,
+                self.config.phase_end_margin_slots,
+                epoch_info.epoch.phases.active.end,
+            ) {
+                break;
+            }
+            self.process_queues(epoch_info).await?;
+            passes += 1;
+            let previous_remaining = remaining;
+            remaining = self.count_queue_items(&queues).await?;
+            if remaining >= previous_remaining {
+                break;
+            }
+        }
+        info!(
+            "Forester {}. Caught up on queue backlog of epoch {} in {} passes, {} items remaining",
+            self.signer.pubkey(),
+            epoch_info.epoch.epoch,
+            passes,
+            remaining
+        );
+        Ok(())
+    }
+
+    async fn count_queue_items(&self, queues: &[Pubkey]) -> Result<usize> {
+        let mut rpc = self.rpc_pool.get_connection().await?;
+        Ok(fetch_queue_data_multi(&mut *rpc, queues)
+            .await?
+            .values()
+            .map(|queue_data| queue_data.items.len())
+            .sum())
+    }
+
     async fn process_queues(&self, epoch_info: &ForesterEpochInfo) -> Result<()> {
-        for tree in &epoch_info.trees {
-            self.process_queue(epoch_info, tree.tree_accounts.queue)
-                .await?;
+        let mut queues: Vec<Pubkey> = epoch_info
+            .trees
+            .iter()
+            .map(|tree| tree.tree_accounts.queue)
+            .collect();
+        if self.config.prioritize_full_queues {
+            let mut rpc = self.rpc_pool.get_connection().await?;
+            let mut fill_ratios = Vec::with_capacity(queues.len());
+            for queue in queues {
+                let fill_ratio = fetch_queue_data(&mut *rpc, &queue).await?.fill_ratio();
+                fill_ratios.push((fill_ratio, queue));
+            }
+            queues = sort_by_fill_ratio(fill_ratios);
+        }
+        for queue in queues {
+            self.process_queue(epoch_info, queue).await?;
         }
         Ok(())
     }
 
+    #[instrument(
+        skip(self, epoch_info),
+        fields(
+            epoch = epoch_info.epoch.epoch,
+            tree = tracing::field::Empty,
+            forester_pubkey = %self.signer.pubkey()
+        )
+    )]
     async fn process_queue(
         &self,
         epoch_info: &ForesterEpochInfo,
         queue_pubkey: Pubkey,
     ) -> Result<()> {
+        if self.is_paused() {
+            debug!("Forester is paused, skipping queue {:?}", queue_pubkey);
+            return Ok(());
+        }
         let mut rpc = self.rpc_pool.get_connection().await?;
-        let current_slot = rpc.get_slot().await?;
+        // Batches check the actual slot before they are sent, the estimate
+        // is good enough to decide whether to fetch the queue.
+        let current_slot = self
+            .slot_tracker
+            .current_slot(&mut *rpc, SLOT_ESTIMATE_MAX_AGE)
+            .await?;
         if !self.is_in_active_phase(current_slot, epoch_info)? {
             debug!("Not in active phase, skipping queue processing");
             return Ok(());
@@ -508,71 +1651,123 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             .iter()
             .find(|t| t.tree_accounts.queue == queue_pubkey)
             .ok_or_else(|| ForesterError::Custom("Tree not found for queue".to_string()))?;
+        tracing::Span::current().record(
+            "tree",
+            tracing::field::display(tree.tree_accounts.merkle_tree),
+        );
 
-        let work_items = self.fetch_work_items(&mut *rpc, &[tree.clone()]).await?;
+        let work_items = self
+            .fetch_work_items(&mut *rpc, &[tree.clone()], current_slot)
+            .await?;
         if work_items.is_empty() {
             debug!("Queue {:?} is empty, skipping processing", queue_pubkey);
             return Ok(());
         }
+        if should_defer_queue(
+            work_items.len(),
+            self.config.min_queue_items,
+            current_slot,
+            epoch_info.epoch.phases.active.end,
+        ) {
+            debug!(
+                "Queue {:?} has {} items, fewer than {}, deferring processing",
+                queue_pubkey,
+                work_items.len(),
+                self.config.min_queue_items
+            );
+            self.schedule_deferred_queue(epoch_info, queue_pubkey);
+            return Ok(());
+        }
+        drop(rpc);
+        if self.config.wait_for_eligible_slot {
+            match self.next_eligible_slot(epoch_info, tree, current_slot) {
+                Some(slot) if slot > current_slot => {
+                    debug!(
+                        "Not eligible for queue {:?} in slot {}, waiting for slot {}",
+                        queue_pubkey, current_slot, slot
+                    );
+                    wait_until_estimated_slot_reached(&self.slot_tracker, slot).await;
+                    let mut rpc = self.rpc_pool.get_connection().await?;
+                    wait_until_slot_reached(&mut *rpc, &self.slot_tracker, slot).await?;
+                }
+                Some(_) => {}
+                None => {
+                    debug!(
+                        "Not eligible for queue {:?} for the rest of epoch {}, skipping processing",
+                        queue_pubkey, epoch_info.epoch.epoch
+                    );
+                    return Ok(());
+                }
+            }
+        }
 
         debug!(
-            "Forester {}. Processing {} work items for queue {:?}",
-            self.config.payer_keypair.pubkey(),
+            "Processing {} work items for queue {:?}",
             work_items.len(),
             tree.tree_accounts.queue
         );
 
-        let semaphore = Arc::new(Semaphore::new(self.config.indexer_max_concurrent_batches));
+        let batch_semaphore = self.batch_semaphore(tree.tree_accounts.tree_type);
+        let tree_semaphore = self.tree_semaphore(tree.tree_accounts.merkle_tree).await;
         let (tx, mut rx) = mpsc::channel(self.config.indexer_max_concurrent_batches);
 
-        for chunk in work_items.chunks(self.config.indexer_batch_size) {
-            debug!(
-                "Forester {}. Processing chunk of size: {}",
-                self.config.payer_keypair.pubkey(),
-                chunk.len()
-            );
-            let semaphore_clone = semaphore.clone();
+        // Serial trees fetch proofs per transaction, so that every
+        // transaction is built on the changelog its predecessor left.
+        let chunk_size = if self.is_serial_tree(&tree.tree_accounts.merkle_tree) {
+            self.config.transaction_batch_size
+        } else {
+            self.config.indexer_batch_size
+        };
+        for chunk in work_items.chunks(chunk_size) {
+            debug!("Processing chunk of size: {}", chunk.len());
+            let semaphore_clone = batch_semaphore.clone();
+            let tree_semaphore_clone = tree_semaphore.clone();
             let tx_clone = tx.clone();
             let epoch_info_clone = epoch_info.clone();
             let self_clone = self.clone();
             let chunk = chunk.to_vec();
 
-            debug!(
-                "Forester {}. Spawning task for chunk of size: {}",
-                self.config.payer_keypair.pubkey(),
-                chunk.len()
-            );
-            let forester_pubkey = self.config.payer_keypair.pubkey();
-            tokio::spawn(async move {
-                let permit = match semaphore_clone.acquire().await {
-                    Ok(permit) => {
-                        debug!("Forester {}. Acquired semaphore", forester_pubkey);
-                        permit
-                    }
-                    Err(e) => {
-                        error!(
-                            "Forester {}. Failed to acquire semaphore: {:?}",
-                            forester_pubkey, e
-                        );
-                        return;
+            debug!("Spawning task for chunk of size: {}", chunk.len());
+            self.spawn_work_task(
+                async move {
+                    // Acquire the tree permit first so that batches waiting
+                    // for their tree don't hold on to global permits.
+                    let tree_permit = match &tree_semaphore_clone {
+                        Some(tree_semaphore) => match tree_semaphore.acquire().await {
+                            Ok(permit) => Some(permit),
+                            Err(e) => {
+                                error!("Failed to acquire tree semaphore: {:?}", e);
+                                return;
+                            }
+                        },
+                        None => None,
+                    };
+                    let permit = match semaphore_clone.acquire().await {
+                        Ok(permit) => {
+                            debug!("Acquired semaphore");
+                            permit
+                        }
+                        Err(e) => {
+                            error!("Failed to acquire semaphore: {:?}", e);
+                            return;
+                        }
+                    };
+                    let start_time = Instant::now();
+                    debug!("Processing work items");
+                    let result = self_clone
+                        .process_work_items(&epoch_info_clone, &chunk)
+                        .await;
+                    debug!("Work items processed");
+                    let duration = start_time.elapsed();
+                    if let Err(e) = tx_clone.send((result, duration)).await {
+                        error!("Failed to send result through channel: {:?}", e);
                     }
-                };
-                let start_time = Instant::now();
-                debug!("Forester {}. Processing work items", forester_pubkey);
-                let result = self_clone
-                    .process_work_items(&epoch_info_clone, &chunk)
-                    .await;
-                debug!("Forester {}. Work items processed", forester_pubkey);
-                let duration = start_time.elapsed();
-                if let Err(e) = tx_clone.send((result, duration)).await {
-                    error!(
-                        "Forester {}. Failed to send result through channel: {:?}",
-                        forester_pubkey, e
-                    );
+                    drop(permit);
+                    drop(tree_permit);
+                    debug!("Dropped permit");
                 }
-                drop(permit);
-                debug!("Forester {}. Dropped permit", forester_pubkey);
-            });
+                .instrument(tracing::Span::current()),
+            );
         }
 
         drop(tx);
@@ -583,25 +1778,53 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             / self.config.indexer_batch_size;
         let mut total_transactions = 0;
         let mut total_duration = Duration::new(0, 0);
+        let mut summary = WorkItemSummary::default();
 
         while let Some((result, duration)) = rx.recv().await {
             debug!("Work item chunk processed");
             completed_chunks += 1;
             debug!("Completed {}/{} chunks", completed_chunks, total_chunks);
             match result {
-                Ok(signatures) => {
+                Ok(work_item_results) => {
+                    let mut signatures = HashSet::new();
+                    for WorkItemResult { work_item, result } in work_item_results.iter() {
+                        summary.record(result);
+                        let hash = bs58::encode(&work_item.queue_item_data.hash).into_string();
+                        match result {
+                            Ok(BatchOutcome::Processed(signature)) => {
+                                signatures.insert(*signature);
+                                debug!(
+                                    "Work item {} of tree {} processed in chunk {}: {:?}",
+                                    hash,
+                                    work_item.tree_account.merkle_tree,
+                                    completed_chunks,
+                                    signature
+                                );
+                            }
+                            Ok(BatchOutcome::SkippedNotEligible { light_slot }) => debug!(
+                                "Work item {} of tree {} skipped, not eligible in light slot {}",
+                                hash, work_item.tree_account.merkle_tree, light_slot
+                            ),
+                            Ok(BatchOutcome::Skipped) => debug!(
+                                "Work item {} of tree {} skipped",
+                                hash, work_item.tree_account.merkle_tree
+                            ),
+                            Ok(BatchOutcome::Simulated) => debug!(
+                                "Work item {} of tree {} simulated",
+                                hash, work_item.tree_account.merkle_tree
+                            ),
+                            Err(e) => error!(
+                                "Work item {} of tree {} failed: {:?}",
+                                hash, work_item.tree_account.merkle_tree, e
+                            ),
+                        }
+                    }
                     let num_transactions = signatures.len();
                     total_transactions += num_transactions;
                     total_duration += duration;
                     let chunk_tps = num_transactions as f64 / duration.as_secs_f64();
                     let avg_tps = total_transactions as f64 / total_duration.as_secs_f64();
 
-                    for (idx, signature) in signatures.iter().enumerate() {
-                        debug!(
-                            "Transaction {} in chunk {} processed: {:?}",
-                            idx, completed_chunks, signature
-                        );
-                    }
                     debug!(
                         "Chunk {} TPS: {:.2}, Average TPS: {:.2}",
                         completed_chunks, chunk_tps, avg_tps
@@ -618,6 +1841,15 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             let overall_avg_tps = total_transactions as f64 / total_duration.as_secs_f64();
             debug!("Overall average TPS: {:.2}", overall_avg_tps);
         }
+        info!(
+            "Queue {:?}: {} work items processed, {} skipped as not eligible, {} skipped otherwise, {} simulated, {} failed",
+            queue_pubkey,
+            summary.processed,
+            summary.skipped_not_eligible,
+            summary.skipped,
+            summary.simulated,
+            summary.failed
+        );
 
         Ok(())
     }
@@ -626,40 +1858,122 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
         &self,
         rpc: &mut R,
         trees: &[TreeForesterSchedule],
+        current_slot: u64,
     ) -> Result<Vec<WorkItem>> {
-        let mut work_items = Vec::new();
+        let mut work_items_per_queue = Vec::with_capacity(trees.len());
+        let queues: Vec<Pubkey> = trees.iter().map(|tree| tree.tree_accounts.queue).collect();
+        let mut queue_data_per_queue = fetch_queue_data_multi(rpc, &queues).await?;
 
         for tree in trees {
-            let queue_item_data = fetch_queue_item_data(rpc, &tree.tree_accounts.queue).await?;
-            for data in queue_item_data {
-                work_items.push(WorkItem {
+            let queue_data = queue_data_per_queue
+                .remove(&tree.tree_accounts.queue)
+                .ok_or_else(|| {
+                    ForesterError::Custom(format!("Queue {} not fetched", tree.tree_accounts.queue))
+                })?;
+            let fill_ratio = queue_data.fill_ratio();
+            let mut queue_item_data = queue_data.items;
+            let mut queue_item_ages = self.queue_item_ages.lock().await;
+            queue_item_ages.stamp(
+                &tree.tree_accounts.queue,
+                &mut queue_item_data,
+                current_slot,
+            );
+            if let Some(max_age_slots) = self.config.max_queue_item_age_slots {
+                let readmitted = queue_item_ages.readmit_after_cooldown(
+                    &tree.tree_accounts.queue,
+                    &mut queue_item_data,
+                    current_slot,
+                    max_age_slots,
+                );
+                if readmitted > 0 {
+                    debug!(
+                        "Re-admitting {} stale queue items in queue {}",
+                        readmitted, tree.tree_accounts.queue
+                    );
+                }
+                let total_items = queue_item_data.len();
+                queue_item_data.retain(|data| !data.is_stale(current_slot, max_age_slots));
+                let stale_items = total_items - queue_item_data.len();
+                if stale_items > 0 {
+                    debug!(
+                        "Skipping {} queue items older than {} slots in queue {}",
+                        stale_items, max_age_slots, tree.tree_accounts.queue
+                    );
+                    increment_stale_queue_items_skipped(
+                        &tree.tree_accounts.merkle_tree,
+                        stale_items,
+                    );
+                }
+            }
+            drop(queue_item_ages);
+            sort_by_insertion_order(&mut queue_item_data);
+            let work_items: Vec<WorkItem> = queue_item_data
+                .into_iter()
+                .map(|data| WorkItem {
                     tree_account: tree.tree_accounts,
                     queue_item_data: data,
-                });
-            }
+                })
+                .collect();
+            work_items_per_queue.push((fill_ratio, work_items));
         }
 
-        Ok(work_items)
+        if self.config.prioritize_full_queues {
+            Ok(sort_by_fill_ratio(work_items_per_queue)
+                .into_iter()
+                .flatten()
+                .collect())
+        } else {
+            Ok(work_items_per_queue
+                .into_iter()
+                .flat_map(|(_, work_items)| work_items)
+                .collect())
+        }
     }
 
+    #[instrument(
+        skip_all,
+        fields(
+            epoch = epoch_info.epoch.epoch,
+            tree = ?work_items.first().map(|item| item.tree_account.merkle_tree),
+            forester_pubkey = %self.signer.pubkey()
+        )
+    )]
     async fn process_work_items(
         &self,
         epoch_info: &ForesterEpochInfo,
         work_items: &[WorkItem],
-    ) -> Result<Vec<Signature>> {
+    ) -> Result<Vec<WorkItemResult>> {
         let mut results = Vec::new();
-        let semaphore = Arc::new(Semaphore::new(
-            self.config.transaction_max_concurrent_batches,
-        ));
+        let serial = work_items
+            .first()
+            .is_some_and(|work_item| self.is_serial_tree(&work_item.tree_account.merkle_tree));
+        let semaphore = Arc::new(Semaphore::new(if serial {
+            1
+        } else {
+            self.config.transaction_max_concurrent_batches
+        }));
 
         let total_start_time = Instant::now();
         let mut total_transactions = 0;
         let mut total_processing_time = Duration::new(0, 0);
+        let mut empty_chunks = 0;
 
-        for (chunk_index, indexer_chunk) in work_items
-            .chunks(self.config.transaction_batch_size)
-            .enumerate()
-        {
+        let chunk_size = match self.config.batch_sizing {
+            BatchSizing::Fixed => self.config.transaction_batch_size,
+            BatchSizing::PackToLimit => self.config.indexer_batch_size,
+        };
+        for (chunk_index, indexer_chunk) in work_items.chunks(chunk_size).enumerate() {
+            if self.is_paused() {
+                info!("Forester is paused, not dispatching further batches");
+                break;
+            }
+            if self.transaction_cap_reached(epoch_info.epoch.epoch).await {
+                warn!(
+                    "Reached the maximum of {:?} transactions for epoch {}, not dispatching further batches",
+                    self.config.max_transactions_per_epoch, epoch_info.epoch.epoch
+                );
+                break;
+            }
             let chunk_start_time = Instant::now();
             debug!(
                 "Processing chunk {} of size: {}",
@@ -670,76 +1984,149 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             let current_slot = rpc.get_slot().await?;
             if !self.is_in_active_phase(current_slot, epoch_info)? {
                 debug!("Not in active phase, skipping process_work_items");
-                return Err(ForesterError::Custom("Not in active phase".to_string()));
+                return Err(ForesterError::NotInActivePhase);
+            }
+            let estimated_slot = self.slot_tracker.estimated_current_slot();
+            if is_near_active_phase_end(
+                estimated_slot,
+                self.config.phase_end_margin_slots,
+                epoch_info.epoch.phases.active.end,
+            ) {
+                info!(
+                    "Slot {} is within {} slots of the end of the active phase of epoch {} (slot {}), not dispatching further batches",
+                    estimated_slot,
+                    self.config.phase_end_margin_slots,
+                    epoch_info.epoch.epoch,
+                    epoch_info.epoch.phases.active.end
+                );
+                break;
             }
 
-            let (proofs, all_instructions) = self
-                .fetch_proofs_and_create_instructions(epoch_info, indexer_chunk)
+            let indexer_chunk = &self
+                .remove_processed_work_items(&mut *rpc, indexer_chunk)
                 .await?;
+            drop(rpc);
+            if indexer_chunk.is_empty() {
+                debug!(
+                    "All items of chunk {} were already processed, skipping",
+                    chunk_index
+                );
+                empty_chunks += 1;
+                continue;
+            }
 
-            let (tx, mut rx) = mpsc::channel(self.config.transaction_max_concurrent_batches);
+            let (indexer_chunk, proofs, all_instructions) = match self
+                .fetch_proofs_and_create_instructions(epoch_info, indexer_chunk)
+                .await
+            {
+                Ok(result) => result,
+                Err(ForesterError::ProofTimeout(timeout)) => {
+                    warn!(
+                        "Indexer did not return proofs for chunk {} within {:?}, skipping it",
+                        chunk_index, timeout
+                    );
+                    empty_chunks += 1;
+                    continue;
+                }
+                // The items stay in the queue and are retried once indexed.
+                Err(ForesterError::MissingProofs(hashes)) => {
+                    warn!(
+                        "Indexer is missing {} proofs for chunk {}, skipping it",
+                        hashes.len(),
+                        chunk_index
+                    );
+                    empty_chunks += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
 
-            let batch_futures: Vec<_> = Zip::enumerate(
-                all_instructions
-                    .chunks(self.config.transaction_batch_size)
-                    .zip(proofs.chunks(self.config.transaction_batch_size)),
-            )
-            .map(|(_, (transaction_chunk, proof_chunk))| {
-                let epoch_info = epoch_info.clone();
-                let self_clone = self.clone();
-                let transaction_chunk = transaction_chunk.to_vec();
-                let proof_chunk = proof_chunk.to_vec();
-                let indexer_chunk = indexer_chunk.to_vec();
-                let semaphore_clone = semaphore.clone();
-                let tx_clone = tx.clone();
-
-                tokio::spawn(async move {
-                    let permit = match semaphore_clone.acquire().await {
-                        Ok(permit) => permit,
-                        Err(e) => {
-                            error!("Failed to acquire semaphore: {:?}", e);
-                            return;
-                        }
-                    };
+            let batch_sizes = self.transaction_batch_sizes(&all_instructions);
+            let batches = transaction_batches(&all_instructions, &proofs, &batch_sizes);
+            if batches.is_empty() {
+                debug!(
+                    "Chunk {} produced no instructions, skipping transaction processing",
+                    chunk_index
+                );
+                empty_chunks += 1;
+                continue;
+            }
 
-                    let start_time = Instant::now();
+            let (tx, mut rx) = mpsc::channel(self.config.transaction_max_concurrent_batches);
 
-                    let result = self_clone
-                        .process_transaction_batch_with_retry(
-                            &epoch_info,
-                            &transaction_chunk,
-                            &proof_chunk,
-                            &indexer_chunk,
-                        )
-                        .await;
+            let work_item_ranges = batch_sizes.iter().scan(0, |offset, size| {
+                let start = *offset;
+                *offset += size;
+                Some(start..*offset)
+            });
+            let batch_futures: Vec<_> = batches
+                .into_iter()
+                .zip(work_item_ranges)
+                .map(|((transaction_chunk, proof_chunk), work_item_range)| {
+                    let epoch_info = epoch_info.clone();
+                    let self_clone = self.clone();
+                    let indexer_chunk = indexer_chunk[work_item_range].to_vec();
+                    let semaphore_clone = semaphore.clone();
+                    let tx_clone = tx.clone();
 
-                    let duration = start_time.elapsed();
-                    if let Err(e) = tx_clone.send((result, duration)).await {
-                        error!("Failed to send result through channel: {:?}", e);
-                    }
-                    drop(permit);
+                    self.spawn_work_task(
+                        async move {
+                            let permit = match semaphore_clone.acquire().await {
+                                Ok(permit) => permit,
+                                Err(e) => {
+                                    error!("Failed to acquire semaphore: {:?}", e);
+                                    return;
+                                }
+                            };
+
+                            let start_time = Instant::now();
+
+                            let result = self_clone
+                                .process_transaction_batch_with_retry(
+                                    &epoch_info,
+                                    &transaction_chunk,
+                                    &proof_chunk,
+                                    &indexer_chunk,
+                                )
+                                .await;
+
+                            let duration = start_time.elapsed();
+                            if let Err(e) = tx_clone.send((indexer_chunk, result, duration)).await {
+                                error!("Failed to send result through channel: {:?}", e);
+                            }
+                            drop(permit);
+                        }
+                        .instrument(tracing::Span::current()),
+                    )
                 })
-            })
-            .collect();
+                .collect();
 
             drop(tx);
 
             let mut chunk_transactions = 0;
             let mut chunk_processing_time = Duration::new(0, 0);
 
-            while let Some((result, duration)) = rx.recv().await {
-                match result {
-                    Ok(signature) => {
-                        results.push(signature);
+            while let Some((batch_work_items, result, duration)) = rx.recv().await {
+                match &result {
+                    Ok(BatchOutcome::Processed(_)) => {
                         chunk_transactions += 1;
                         chunk_processing_time += duration;
                         let batch_tps = 1.0 / duration.as_secs_f64();
                         debug!("Batch processed successfully. TPS: {:.2}", batch_tps);
                     }
+                    Ok(_) => {}
                     Err(e) => {
                         error!("Error processing batch: {:?}", e);
                     }
                 }
+                results.extend(
+                    batch_work_items
+                        .into_iter()
+                        .map(|work_item| WorkItemResult {
+                            work_item,
+                            result: result.clone(),
+                        }),
+                );
             }
 
             join_all(batch_futures).await;
@@ -771,15 +2158,20 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             total_transactions as f64 / total_processing_time.as_secs_f64();
 
         debug!(
-            "Overall: {} transactions in {:.2?}",
-            total_transactions, total_duration
+            "Overall: {} transactions in {:.2?} ({} chunks without work)",
+            total_transactions, total_duration, empty_chunks
         );
         debug!(
             "Overall TPS: {:.2}, Processing TPS: {:.2}",
             overall_tps, overall_processing_tps
         );
+        if let Some(latency) = self.latency_percentiles().await {
+            debug!(
+                "Landing latency p50: {:.2?}, p95: {:.2?}, p99: {:.2?}",
+                latency.p50, latency.p95, latency.p99
+            );
+        }
 
-        let results = results.into_iter().flatten().collect();
         Ok(results)
     }
 
@@ -824,8 +2216,114 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
         if tree_schedule.is_eligible(light_slot) {
             Ok(())
         } else {
-            Err(ForesterError::NotEligible)
+            Err(ForesterError::NotEligible { light_slot })
+        }
+    }
+
+    async fn record_dead_letters(
+        &self,
+        epoch: u64,
+        work_items: &[WorkItem],
+        error: &ForesterError,
+    ) {
+        let Some(dead_letters) = &self.dead_letters else {
+            return;
+        };
+        let dead_letters_to_record: Vec<_> = work_items
+            .iter()
+            .map(|item| {
+                DeadLetter::new(
+                    epoch,
+                    &item.tree_account,
+                    &item.queue_item_data.hash,
+                    item.queue_item_data.index,
+                    error,
+                )
+            })
+            .collect();
+        if let Err(e) = dead_letters.record(&dead_letters_to_record).await {
+            error!("Failed to record dead letters: {:?}", e);
+        }
+    }
+
+    /// Re-submits the dead letters still pending in their queues in the
+    /// current epoch. The forester must be registered for the epoch and items
+    /// are only processed in its eligible light slots. Dead letters still
+    /// pending afterwards are kept in the store.
+    ///
+    /// Returns the number of replayed and remaining dead letters.
+    pub async fn replay_dead_letters(&self, store: &DeadLetterStore) -> Result<(usize, usize)> {
+        let pending = self.pending_dead_letters(store.read().await?).await?;
+        if pending.is_empty() {
+            store.replace(&[]).await?;
+            return Ok((0, 0));
+        }
+
+        let (_, epoch) = self.get_current_slot_and_epoch().await?;
+        let epoch_info = {
+            let mut rpc = self.rpc_pool.get_connection().await?;
+            let trees = self.trees.lock().await.clone();
+            fetch_forester_epoch_info(
+                &mut *rpc,
+                &self.config.registry(),
+                &self.protocol_config(),
+                &self.signer.pubkey(),
+                epoch,
+                &trees,
+            )
+            .await?
+        };
+        let (dead_letters, work_items): (Vec<DeadLetter>, Vec<WorkItem>) =
+            pending.into_iter().unzip();
+        info!(
+            "Replaying {} dead letters in epoch {}",
+            work_items.len(),
+            epoch
+        );
+        self.process_work_items(&epoch_info, &work_items).await?;
+
+        let remaining: Vec<DeadLetter> = self
+            .pending_dead_letters(dead_letters)
+            .await?
+            .into_iter()
+            .map(|(dead_letter, _)| dead_letter)
+            .collect();
+        store.replace(&remaining).await?;
+        Ok((work_items.len(), remaining.len()))
+    }
+
+    /// Dead letters whose items are still in their queues, with the work
+    /// items to process them.
+    async fn pending_dead_letters(
+        &self,
+        dead_letters: Vec<DeadLetter>,
+    ) -> Result<Vec<(DeadLetter, WorkItem)>> {
+        let mut rpc = self.rpc_pool.get_connection().await?;
+        let mut queue_items: HashMap<Pubkey, Vec<QueueItemData>> = HashMap::new();
+        let mut pending = Vec::new();
+        for dead_letter in dead_letters {
+            let (Some(tree_account), Some(hash)) =
+                (dead_letter.tree_accounts(), dead_letter.hash())
+            else {
+                warn!("Dropping invalid dead letter {:?}", dead_letter);
+                continue;
+            };
+            let items = match queue_items.entry(tree_account.queue) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(fetch_queue_item_data(&mut *rpc, &tree_account.queue).await?)
+                }
+            };
+            let queue_item_data = items.iter().find(|item| item.hash == hash);
+            if let Some(queue_item_data) = queue_item_data {
+                let work_item = WorkItem {
+                    tree_account,
+                    queue_item_data: queue_item_data.clone(),
+                };
+                pending.push((dead_letter, work_item));
+            }
         }
+        Ok(pending)
     }
 
     async fn process_transaction_batch_with_retry(
@@ -834,7 +2332,7 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
         transaction_chunk: &[Instruction],
         proof_chunk: &[Proof],
         indexer_chunk: &[WorkItem],
-    ) -> Result<Option<Signature>> {
+    ) -> Result<BatchOutcome> {
         let work_item = indexer_chunk
             .first()
             .ok_or_else(|| ForesterError::Custom("Empty indexer chunk".to_string()))?;
@@ -843,117 +2341,416 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             work_item.queue_item_data.hash,
             transaction_chunk.len()
         );
-        const BASE_RETRY_DELAY: Duration = Duration::from_millis(100);
+        if self.transaction_cap_reached(epoch_info.epoch.epoch).await {
+            debug!("Transaction cap for the epoch reached, skipping batch");
+            return Ok(BatchOutcome::Skipped);
+        }
+        if self.is_paused() {
+            debug!("Forester is paused, skipping batch");
+            return Ok(BatchOutcome::Skipped);
+        }
+
+        match self
+            .check_eligibility(epoch_info, &work_item.tree_account)
+            .await
+        {
+            Ok(_) => {}
+            Err(ForesterError::NotEligible { light_slot }) => {
+                debug!(
+                    "Forester not eligible in light slot {}, skipping batch",
+                    light_slot
+                );
+                return Ok(BatchOutcome::SkippedNotEligible { light_slot });
+            }
+            Err(e) => {
+                error!("Error checking eligibility: {:?}", e);
+                return Err(e);
+            }
+        }
 
         let mut retries = 0;
         loop {
             match self
-                .check_eligibility(epoch_info, &work_item.tree_account)
+                .process_transaction_batch(
+                    epoch_info,
+                    transaction_chunk,
+                    proof_chunk,
+                    indexer_chunk,
+                )
                 .await
             {
-                Ok(_) => {
+                Ok(BatchOutcome::Processed(signature)) => {
+                    debug!(
+                        "Work item {:?} processed successfully. Signature: {:?}",
+                        work_item.queue_item_data.hash, signature
+                    );
+                    self.increment_processed_items_count(epoch_info.epoch.epoch)
+                        .await;
+                    self.emit_event(ForesterEvent::BatchProcessed {
+                        epoch: epoch_info.epoch.epoch,
+                        tree: work_item.tree_account.merkle_tree,
+                        signature,
+                        instructions: transaction_chunk.len(),
+                    })
+                    .await;
+                    return Ok(BatchOutcome::Processed(signature));
+                }
+                Ok(outcome) => return Ok(outcome),
+                Err(ForesterError::StaleProofs) => {
+                    debug!(
+                        "All proofs for work item {:?} are stale, leaving it for the next queue fetch",
+                        work_item.queue_item_data.hash
+                    );
+                    return Ok(BatchOutcome::Skipped);
+                }
+                Err(ForesterError::TransactionTooLarge(size)) => {
+                    if self.config.batch_sizing == BatchSizing::PackToLimit {
+                        let max_instructions = transaction_chunk.len().saturating_sub(1).max(1);
+                        self.max_instructions_per_transaction
+                            .fetch_min(max_instructions, Ordering::Relaxed);
+                        warn!(
+                            "Transaction of {} bytes exceeds the size limit, packing at most {} instructions per transaction",
+                            size, max_instructions
+                        );
+                    }
+                    return Err(ForesterError::TransactionTooLarge(size));
+                }
+                Err(ForesterError::NotInActivePhase) => {
+                    debug!(
+                        "Active phase ended before work item {:?} was processed",
+                        work_item.queue_item_data.hash
+                    );
+                    return Err(ForesterError::NotInActivePhase);
+                }
+                Err(e) if !is_retryable(&e) => {
+                    error!(
+                        "Non-retryable error for work item {:?}, not retrying. Error: {:?}",
+                        work_item.queue_item_data.hash, e
+                    );
+                    self.record_dead_letters(epoch_info.epoch.epoch, indexer_chunk, &e)
+                        .await;
+                    return Err(e);
+                }
+                Err(e) => {
+                    if retries >= self.config.max_retries {
+                        error!(
+                            "Max retries reached for work item {:?}. Error: {:?}",
+                            work_item.queue_item_data.hash, e
+                        );
+                        self.record_dead_letters(epoch_info.epoch.epoch, indexer_chunk, &e)
+                            .await;
+                        return Err(e);
+                    }
+                    sleep(self.config.retry_policy.retry_delay(retries)).await;
+                    retries += 1;
+
+                    // The backoff may have crossed a light slot boundary, in which
+                    // case another forester is now eligible for this tree.
                     match self
-                        .process_transaction_batch(
-                            epoch_info,
-                            transaction_chunk,
-                            proof_chunk,
-                            indexer_chunk,
-                        )
+                        .check_eligibility(epoch_info, &work_item.tree_account)
                         .await
                     {
-                        Ok(signature) => {
-                            debug!(
-                                "Work item {:?} processed successfully. Signature: {:?}",
-                                work_item.queue_item_data.hash, signature
+                        Ok(_) => {}
+                        Err(ForesterError::NotEligible { light_slot }) => {
+                            info!(
+                                "Eligibility lost for work item {:?} after {} retries, abandoning batch",
+                                work_item.queue_item_data.hash, retries
                             );
-                            self.increment_processed_items_count(epoch_info.epoch.epoch)
-                                .await;
-                            return Ok(Some(signature));
+                            return Ok(BatchOutcome::SkippedNotEligible { light_slot });
                         }
                         Err(e) => {
-                            if retries >= self.config.max_retries {
-                                error!(
-                                    "Max retries reached for work item {:?}. Error: {:?}",
-                                    work_item.queue_item_data.hash, e
-                                );
-                                return Err(e);
-                            }
-                            let delay = BASE_RETRY_DELAY
-                                .saturating_mul(2u32.saturating_pow(retries as u32));
-                            let jitter = rand::thread_rng().gen_range(0..=50);
-                            sleep(delay + Duration::from_millis(jitter)).await;
-                            retries += 1;
-                            warn!(
-                                "Retrying work item {:?}. Attempt {}/{}",
-                                work_item.queue_item_data.hash, retries, self.config.max_retries
-                            );
+                            error!("Error checking eligibility: {:?}", e);
+                            return Err(e);
                         }
                     }
-                }
-                Err(ForesterError::NotEligible) => {
-                    debug!("Forester not eligible for this slot, skipping batch");
-                    return Ok(None);
-                }
-                Err(e) => {
-                    error!("Error checking eligibility: {:?}", e);
-                    return Err(e);
+                    warn!(
+                        "Retrying work item {:?}. Attempt {}/{}",
+                        work_item.queue_item_data.hash, retries, self.config.max_retries
+                    );
                 }
             }
         }
     }
 
+    #[instrument(
+        skip_all,
+        fields(
+            epoch = epoch_info.epoch.epoch,
+            tree = ?work_items.first().map(|item| item.tree_account.merkle_tree),
+            forester_pubkey = %self.signer.pubkey()
+        )
+    )]
     async fn process_transaction_batch(
         &self,
         epoch_info: &ForesterEpochInfo,
         instructions: &[Instruction],
         proofs: &[Proof],
         work_items: &[WorkItem],
-    ) -> Result<Signature> {
+    ) -> Result<BatchOutcome> {
         debug!(
             "Processing transaction batch with {} instructions",
             instructions.len()
         );
         let mut rpc = self.rpc_pool.get_connection().await?;
+        // Use the actual slot rather than the estimate, which may have
+        // drifted since the last reconciliation.
         let current_slot = rpc.get_slot().await?;
+        self.slot_tracker.update(current_slot);
         if !self.is_in_active_phase(current_slot, epoch_info)? {
             debug!("Not in active phase, skipping queue processing");
-            return Err(ForesterError::Custom("Not in active phase".to_string()));
+            return Err(ForesterError::NotInActivePhase);
         }
-        let recent_blockhash = rpc.get_latest_blockhash().await?;
 
-        let mut ixs = vec![ComputeBudgetInstruction::set_compute_unit_limit(
-            self.config.cu_limit,
-        )];
+        let (instructions, proofs, work_items) = if self.config.validate_proof_roots {
+            let sequence_numbers = self
+                .on_chain_sequence_numbers(&mut *rpc, current_slot, work_items)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Failed to read on-chain sequence numbers, not validating proof roots: {:?}",
+                        e
+                    );
+                    HashMap::new()
+                });
+            let batch = remove_stale_proofs(
+                instructions,
+                proofs,
+                work_items,
+                &sequence_numbers,
+                self.config.max_proof_root_seq_lag,
+            );
+            if !batch.stale_work_items.is_empty() {
+                info!(
+                    "Dropping {} items with stale proof roots, they will be re-fetched from the queue",
+                    batch.stale_work_items.len()
+                );
+            }
+            if batch.instructions.is_empty() {
+                return Err(ForesterError::StaleProofs);
+            }
+            (batch.instructions, batch.proofs, batch.work_items)
+        } else {
+            (instructions.to_vec(), proofs.to_vec(), work_items.to_vec())
+        };
+        let (instructions, proofs, work_items) = (&instructions[..], &proofs[..], &work_items[..]);
+
+        let _nonce_guard = match self.config.nonce_account {
+            Some(_) => Some(self.nonce_lock.lock().await),
+            None => None,
+        };
+        let recent_blockhash = self.recent_blockhash(&mut *rpc).await?;
+
+        let mut ixs = self.batch_prefix_instructions();
         ixs.extend_from_slice(instructions);
-        let mut transaction =
-            Transaction::new_with_payer(&ixs, Some(&self.config.payer_keypair.pubkey()));
-        transaction.sign(&[&self.config.payer_keypair], recent_blockhash);
+        let mut transaction = Transaction::new_with_payer(&ixs, Some(&self.signer.pubkey()));
+        let nonce_authority: Vec<&Keypair> = self.config.nonce_authority.iter().collect();
+        sign_transaction(
+            &*self.signer,
+            &mut transaction,
+            &nonce_authority,
+            recent_blockhash,
+        )
+        .await?;
+
+        let transaction_size = bincode::serialized_size(&transaction)? as usize;
+        if transaction_size > PACKET_DATA_SIZE {
+            return Err(ForesterError::TransactionTooLarge(transaction_size));
+        }
+
+        if self.config.dry_run {
+            let simulation = rpc.simulate_transaction(&transaction)?;
+            log_simulation_result(
+                &format!("Transaction batch ({} instructions)", instructions.len()),
+                &transaction,
+                &simulation,
+            );
+            return Ok(BatchOutcome::Simulated);
+        }
 
         // TODO: replace it with send, do not wait for confirmation and wait for confirmation on another thread
         // we need to introduce retry on timeout when confirmation is not received
-        let signature = rpc.process_transaction(transaction).await?;
+        // An expired blockhash says nothing about the batch itself, so it is
+        // re-signed with a fresh blockhash instead of using up a retry.
+        const MAX_BLOCKHASH_REFRESHES: usize = 3;
+        let mut blockhash_refreshes = 0;
+        let send_time = Instant::now();
+        let signature = loop {
+            // Resends with a fresh blockhash count towards `max_tps` as well.
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire().await;
+            }
+            match rpc
+                .process_transaction_with_commitment(
+                    transaction.clone(),
+                    self.config.work_commitment,
+                )
+                .await
+            {
+                Ok(signature) => break signature,
+                Err(e) if is_already_processed(&e) => {
+                    debug!("Transaction batch already landed: {:?}", e);
+                    break transaction.signatures[0];
+                }
+                Err(e)
+                    if is_blockhash_expired(&e)
+                        && blockhash_refreshes < MAX_BLOCKHASH_REFRESHES =>
+                {
+                    blockhash_refreshes += 1;
+                    debug!(
+                        "Blockhash expired, re-signing batch with a fresh blockhash (attempt {})",
+                        blockhash_refreshes
+                    );
+                    let recent_blockhash = self.recent_blockhash(&mut *rpc).await?;
+                    sign_transaction(
+                        &*self.signer,
+                        &mut transaction,
+                        &nonce_authority,
+                        recent_blockhash,
+                    )
+                    .await?;
+                }
+                Err(e) => {
+                    let failure = TransactionFailure::classify(&e);
+                    debug!("Transaction batch failed ({:?}): {:?}", failure, e);
+                    increment_transactions(failure.label());
+                    self.observe_transaction(
+                        epoch_info,
+                        &transaction,
+                        &ixs,
+                        work_items,
+                        Err(e.to_string()),
+                    );
+                    return Err(e.into());
+                }
+            }
+        };
         drop(rpc);
+        increment_transactions("success");
+        self.observe_transaction(epoch_info, &transaction, &ixs, work_items, Ok(()));
+        self.record_landing_latency(send_time.elapsed()).await;
 
-        self.update_indexer(work_items, proofs).await;
+        apply_indexer_updates(
+            &self.indexer,
+            &self.applied_indexer_updates,
+            epoch_info.epoch.epoch,
+            work_items,
+            proofs,
+        )
+        .await;
 
-        Ok(signature)
+        Ok(BatchOutcome::Processed(signature))
     }
 
-    async fn update_indexer(&self, work_items: &[WorkItem], proofs: &[Proof]) {
-        for (work_item, proof) in work_items.iter().zip(proofs.iter()) {
-            match proof {
-                Proof::AddressProof(address_proof) => {
-                    let mut indexer = self.indexer.lock().await;
-                    indexer.address_tree_updated(work_item.tree_account.merkle_tree, address_proof);
-                    drop(indexer);
-                }
-                Proof::StateProof(state_proof) => {
-                    let mut indexer = self.indexer.lock().await;
-                    indexer
-                        .account_nullified(work_item.tree_account.merkle_tree, &state_proof.hash);
-                    drop(indexer);
-                }
+    fn observe_transaction(
+        &self,
+        epoch_info: &ForesterEpochInfo,
+        transaction: &Transaction,
+        instructions: &[Instruction],
+        work_items: &[WorkItem],
+        result: std::result::Result<(), String>,
+    ) {
+        let Some(tx_observer) = &self.tx_observer else {
+            return;
+        };
+        let mut trees: Vec<Pubkey> = work_items
+            .iter()
+            .map(|work_item| work_item.tree_account.merkle_tree)
+            .collect();
+        trees.dedup();
+        tx_observer.on_transaction(&TransactionRecord {
+            signature: transaction.signatures[0],
+            epoch: epoch_info.epoch.epoch,
+            trees,
+            instructions: instructions.to_vec(),
+            result,
+        });
+    }
+
+    /// Reads the sequence numbers of the trees of `work_items`, reusing values
+    /// already read in the current slot. Only the tree header is read, not
+    /// the whole tree.
+    async fn on_chain_sequence_numbers(
+        &self,
+        rpc: &mut R,
+        current_slot: u64,
+        work_items: &[WorkItem],
+    ) -> Result<HashMap<Pubkey, u64>> {
+        let mut sequence_numbers = HashMap::new();
+        for work_item in work_items {
+            let tree = work_item.tree_account.merkle_tree;
+            if sequence_numbers.contains_key(&tree) {
+                continue;
             }
+            let cached = self.tree_sequence_numbers.lock().await.get(&tree).copied();
+            let sequence_number = match cached {
+                Some((slot, sequence_number)) if slot == current_slot => sequence_number,
+                _ => {
+                    let sequence_number =
+                        fetch_merkle_tree_header(rpc, tree, work_item.tree_account.tree_type)
+                            .await?
+                            .sequence_number as u64;
+                    self.tree_sequence_numbers
+                        .lock()
+                        .await
+                        .insert(tree, (current_slot, sequence_number));
+                    sequence_number
+                }
+            };
+            sequence_numbers.insert(tree, sequence_number);
+        }
+        Ok(sequence_numbers)
+    }
+
+    /// Instructions every transaction batch starts with: the advance nonce
+    /// instruction if a durable nonce is used, followed by the compute budget
+    /// instructions.
+    fn batch_prefix_instructions(&self) -> Vec<Instruction> {
+        let mut instructions = Vec::new();
+        if let Some(nonce_account) = self.config.nonce_account {
+            let nonce_authority = self
+                .config
+                .nonce_authority
+                .as_ref()
+                .map_or_else(|| self.signer.pubkey(), |keypair| keypair.pubkey());
+            instructions.push(system_instruction::advance_nonce_account(
+                &nonce_account,
+                &nonce_authority,
+            ));
+        }
+        instructions.extend(self.config.compute_budget_instructions());
+        instructions
+    }
+
+    /// The durable nonce stored in `nonce_account` if configured, the latest
+    /// blockhash otherwise.
+    async fn recent_blockhash(&self, rpc: &mut R) -> Result<Hash> {
+        let Some(nonce_account) = self.config.nonce_account else {
+            return Ok(rpc.get_latest_blockhash().await?);
+        };
+        let account = rpc.get_account(nonce_account).await?.ok_or_else(|| {
+            ForesterError::Custom(format!("Nonce account {} not found", nonce_account))
+        })?;
+        let data = nonce_utils::data_from_account(&account)
+            .map_err(|e| ForesterError::Custom(format!("Invalid nonce account: {}", e)))?;
+        Ok(data.blockhash())
+    }
+
+    fn transaction_batch_sizes(&self, instructions: &[Instruction]) -> Vec<usize> {
+        let prefix_instructions = self.batch_prefix_instructions();
+        match self.config.batch_sizing {
+            BatchSizing::Fixed => pack_instructions(
+                instructions,
+                &self.signer.pubkey(),
+                &prefix_instructions,
+                self.config.transaction_batch_size,
+            ),
+            BatchSizing::PackToLimit => pack_instructions(
+                instructions,
+                &self.signer.pubkey(),
+                &prefix_instructions,
+                self.max_instructions_per_transaction
+                    .load(Ordering::Relaxed),
+            ),
         }
     }
 
@@ -973,22 +2770,33 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
         info!("Reporting work for epoch: {}", epoch_info.epoch.epoch);
         let mut rpc = self.rpc_pool.get_connection().await?;
 
-        let ix = create_report_work_instruction(
-            &self.config.payer_keypair.pubkey(),
-            epoch_info.epoch.epoch,
-        );
-        rpc.create_and_send_transaction(
-            &[ix],
-            &self.config.payer_keypair.pubkey(),
-            &[&self.config.payer_keypair],
-        )
-        .await?;
+        let ix = self
+            .config
+            .registry()
+            .report_work_instruction(&self.signer.pubkey(), epoch_info.epoch.epoch);
+        if self
+            .send_signed_transaction(&mut *rpc, "Report work", &[ix])
+            .await?
+            .is_none()
+        {
+            info!(
+                "Work report for epoch {} only simulated",
+                epoch_info.epoch.epoch
+            );
+            return Ok(());
+        }
 
         let report = WorkReport {
             epoch: epoch_info.epoch.epoch,
             processed_items: self.get_processed_items_count(epoch_info.epoch.epoch).await,
         };
 
+        self.emit_event(ForesterEvent::WorkReported {
+            epoch: report.epoch,
+            processed_items: report.processed_items,
+        })
+        .await;
+
         self.work_report_sender
             .send(report)
             .await
@@ -997,11 +2805,45 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
         Ok(())
     }
 
+    /// Removes work items which are no longer pending in their queue, e.g.
+    /// because another forester already nullified the account or inserted
+    /// the address, so that no instructions are built for them.
+    async fn remove_processed_work_items(
+        &self,
+        rpc: &mut R,
+        work_items: &[WorkItem],
+    ) -> Result<Vec<WorkItem>> {
+        let queues: HashSet<Pubkey> = work_items
+            .iter()
+            .map(|item| item.tree_account.queue)
+            .collect();
+        let mut pending_items = HashMap::with_capacity(queues.len());
+        for queue in queues {
+            let queue_data = fetch_queue_data(rpc, &queue).await?;
+            let hashes: HashSet<[u8; 32]> = queue_data.items.iter().map(|item| item.hash).collect();
+            pending_items.insert(queue, hashes);
+        }
+
+        let pending_work_items = retain_pending_work_items(work_items, &pending_items);
+        let processed_items = work_items.len() - pending_work_items.len();
+        if processed_items > 0 {
+            debug!(
+                "Skipping {} work items which were already processed",
+                processed_items
+            );
+        }
+        Ok(pending_work_items)
+    }
+
     async fn fetch_proofs_and_create_instructions(
         &self,
         registration_info: &ForesterEpochInfo,
         work_items: &[WorkItem],
-    ) -> Result<(Vec<Proof>, Vec<Instruction>)> {
+    ) -> Result<(Vec<WorkItem>, Vec<Proof>, Vec<Instruction>)> {
+        let proof_fetch_timeout = Duration::from_secs(self.config.proof_fetch_timeout_seconds);
+        let proof_batch_size = self.config.proof_batch_size.max(1);
+        let registry = self.config.registry();
+        let mut ordered_items = Vec::with_capacity(work_items.len());
         let mut proofs = Vec::new();
         let mut instructions = vec![];
 
@@ -1021,16 +2863,39 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
                 .iter()
                 .map(|item| item.queue_item_data.hash)
                 .collect();
-            let indexer = self.indexer.lock().await;
-            let address_proofs = indexer
-                .get_multiple_new_address_proofs(merkle_tree, addresses)
-                .await?;
-            drop(indexer);
+            let mut address_proofs = Vec::with_capacity(addresses.len());
+            for addresses in addresses.chunks(proof_batch_size) {
+                let indexer = self.indexer.read().await;
+                let proofs = timeout(
+                    proof_fetch_timeout,
+                    indexer.get_multiple_new_address_proofs(merkle_tree, addresses.to_vec()),
+                )
+                .await
+                .map_err(|_| ForesterError::ProofTimeout(proof_fetch_timeout))??;
+                drop(indexer);
+                address_proofs.extend(proofs);
+            }
+            // Address proofs don't identify their address, so a partial
+            // response can't be matched to the requested items.
+            if address_proofs.len() != address_items.len() {
+                let addresses: Vec<String> = address_items
+                    .iter()
+                    .map(|item| bs58::encode(&item.queue_item_data.hash).into_string())
+                    .collect();
+                warn!(
+                    "Indexer returned {} proofs for {} addresses: {:?}",
+                    address_proofs.len(),
+                    address_items.len(),
+                    addresses
+                );
+                return Err(ForesterError::MissingProofs(addresses));
+            }
             for (item, proof) in address_items.iter().zip(address_proofs.into_iter()) {
+                ordered_items.push((*item).clone());
                 proofs.push(Proof::AddressProof(proof.clone()));
-                let instruction = create_update_address_merkle_tree_instruction(
+                let instruction = registry.update_address_merkle_tree_instruction(
                     UpdateAddressMerkleTreeInstructionInputs {
-                        authority: self.config.payer_keypair.pubkey(),
+                        authority: self.signer.pubkey(),
                         address_merkle_tree: item.tree_account.merkle_tree,
                         address_queue: item.tree_account.queue,
                         value: item.queue_item_data.index as u16,
@@ -1057,14 +2922,34 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
                 .iter()
                 .map(|item| bs58::encode(&item.queue_item_data.hash).into_string())
                 .collect();
-            let indexer = self.indexer.lock().await;
-            let state_proofs = indexer
-                .get_multiple_compressed_account_proofs(states)
-                .await?;
-            drop(indexer);
+            let mut state_proofs = Vec::with_capacity(states.len());
+            for states in states.chunks(proof_batch_size) {
+                let indexer = self.indexer.read().await;
+                let proofs = timeout(
+                    proof_fetch_timeout,
+                    indexer.get_multiple_compressed_account_proofs(states.to_vec()),
+                )
+                .await
+                .map_err(|_| ForesterError::ProofTimeout(proof_fetch_timeout))??;
+                drop(indexer);
+                state_proofs.extend(proofs);
+            }
+            let state_proofs = match match_state_proofs(&states, state_proofs) {
+                Ok(state_proofs) => state_proofs,
+                Err(missing) => {
+                    warn!(
+                        "Indexer returned no proofs for {} of {} accounts: {:?}",
+                        missing.len(),
+                        states.len(),
+                        missing
+                    );
+                    return Err(ForesterError::MissingProofs(missing));
+                }
+            };
             for (item, proof) in state_items.iter().zip(state_proofs.into_iter()) {
+                ordered_items.push((*item).clone());
                 proofs.push(Proof::StateProof(proof.clone()));
-                let instruction = create_nullify_instruction(
+                let instruction = registry.nullify_instruction(
                     CreateNullifyInstructionInputs {
                         nullifier_queue: item.tree_account.queue,
                         merkle_tree: item.tree_account.merkle_tree,
@@ -1072,8 +2957,8 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
                         leaves_queue_indices: vec![item.queue_item_data.index as u16],
                         indices: vec![proof.leaf_index],
                         proofs: vec![proof.proof.clone()],
-                        authority: self.config.payer_keypair.pubkey(),
-                        derivation: self.config.payer_keypair.pubkey(),
+                        authority: self.signer.pubkey(),
+                        derivation: self.signer.pubkey(),
                         is_metadata_forester: false,
                     },
                     registration_info.epoch.epoch,
@@ -1082,40 +2967,97 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
             }
         }
 
-        Ok((proofs, instructions))
-    }
-
-    async fn perform_rollover(&self, tree_account: &TreeAccounts) -> Result<()> {
-        let mut rpc = self.rpc_pool.get_connection().await?;
-        let result = match tree_account.tree_type {
-            TreeType::Address => {
-                rollover_address_merkle_tree(
-                    self.config.clone(),
-                    &mut *rpc,
-                    self.indexer.clone(),
-                    tree_account,
-                )
-                .await
-            }
-            TreeType::State => {
-                rollover_state_merkle_tree(
-                    self.config.clone(),
-                    &mut *rpc,
-                    self.indexer.clone(),
-                    tree_account,
-                )
+        let Some(max_lag) = self.config.max_proof_root_seq_lag else {
+            return Ok((ordered_items, proofs, instructions));
+        };
+        let sequence_numbers = {
+            let mut rpc = self.rpc_pool.get_connection().await?;
+            let current_slot = rpc.get_slot().await?;
+            self.on_chain_sequence_numbers(&mut *rpc, current_slot, &ordered_items)
                 .await
-            }
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Failed to read on-chain sequence numbers, not checking proof root lag: {:?}",
+                        e
+                    );
+                    HashMap::new()
+                })
         };
+        let batch = remove_stale_proofs(
+            &instructions,
+            &proofs,
+            &ordered_items,
+            &sequence_numbers,
+            Some(max_lag),
+        );
+        for item in batch.stale_work_items.iter() {
+            warn!(
+                "Dropping item {} of tree {}: proof root lags the on-chain sequence number by more than {}",
+                bs58::encode(&item.queue_item_data.hash).into_string(),
+                item.tree_account.merkle_tree,
+                max_lag
+            );
+        }
+        Ok((batch.work_items, batch.proofs, batch.instructions))
+    }
 
-        match result {
-            Ok(_) => debug!(
-                "{:?} tree rollover completed successfully",
-                tree_account.tree_type
+    async fn perform_rollover(&self, tree_account: &TreeAccounts) -> Result<RolloverOutcome> {
+        const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+        let result = rollover_with_retry(self.config.max_retries, INITIAL_RETRY_DELAY, || async {
+            let mut rpc = self.rpc_pool.get_connection().await?;
+            let new_tree_accounts = match tree_account.tree_type {
+                TreeType::Address => {
+                    rollover_address_merkle_tree(
+                        self.config.clone(),
+                        &mut *rpc,
+                        self.indexer.clone(),
+                        &*self.signer,
+                        tree_account,
+                    )
+                    .await
+                }
+                TreeType::State => {
+                    rollover_state_merkle_tree(
+                        self.config.clone(),
+                        &mut *rpc,
+                        self.indexer.clone(),
+                        &*self.signer,
+                        &self.protocol_config(),
+                        tree_account,
+                    )
+                    .await
+                }
+            };
+            new_tree_accounts
+        })
+        .await;
+
+        match &result {
+            Ok(RolloverOutcome::RolledOver(new_tree_accounts)) => {
+                info!(
+                    "{:?} tree {} rolled over. New tree: {}, new queue: {}",
+                    tree_account.tree_type,
+                    tree_account.merkle_tree,
+                    new_tree_accounts.merkle_tree,
+                    new_tree_accounts.queue
+                );
+                self.emit_event(ForesterEvent::RolloverPerformed {
+                    tree: tree_account.merkle_tree,
+                    tree_type: tree_account.tree_type,
+                })
+                .await;
+            }
+            Ok(RolloverOutcome::AlreadyRolledOver) => debug!(
+                "{:?} tree {} was already rolled over",
+                tree_account.tree_type, tree_account.merkle_tree
+            ),
+            Err(e) => warn!(
+                "{:?} tree {} rollover failed: {:?}",
+                tree_account.tree_type, tree_account.merkle_tree, e
             ),
-            Err(e) => warn!("{:?} tree rollover failed: {:?}", tree_account.tree_type, e),
         }
-        Ok(())
+        result
     }
 
     #[allow(dead_code)]
@@ -1124,13 +3066,461 @@ impl<R: RpcConnection, I: Indexer<R>> EpochManager<R, I> {
     }
 }
 
+fn retain_pending_work_items(
+    work_items: &[WorkItem],
+    pending_items: &HashMap<Pubkey, HashSet<[u8; 32]>>,
+) -> Vec<WorkItem> {
+    work_items
+        .iter()
+        .filter(|item| {
+            pending_items
+                .get(&item.tree_account.queue)
+                .is_some_and(|hashes| hashes.contains(&item.queue_item_data.hash))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Why a transaction batch failed, so that operators can tell whether to
+/// tune proofs, concurrency or funding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransactionFailure {
+    /// The proof did not match the tree, e.g. because the tree changed
+    /// since the proof was fetched.
+    ProofInvalid,
+    /// The items were already processed, e.g. by a concurrent batch.
+    AlreadyProcessed,
+    /// The transaction never landed.
+    Dropped,
+    InsufficientFunds,
+    Other,
+}
+
+impl TransactionFailure {
+    fn classify(error: &RpcError) -> Self {
+        let transaction_error = match error {
+            RpcError::TransactionError(e) => Some(e.clone()),
+            RpcError::ClientError(e) => e.get_transaction_error(),
+            RpcError::IoError(_) => return TransactionFailure::Dropped,
+            _ => None,
+        };
+        let Some(transaction_error) = transaction_error else {
+            return match error {
+                RpcError::ClientError(_) => TransactionFailure::Dropped,
+                _ => TransactionFailure::Other,
+            };
+        };
+        match transaction_error {
+            TransactionError::BlockhashNotFound => TransactionFailure::Dropped,
+            TransactionError::AlreadyProcessed => TransactionFailure::AlreadyProcessed,
+            TransactionError::InsufficientFundsForFee
+            | TransactionError::InsufficientFundsForRent { .. }
+            | TransactionError::InstructionError(_, InstructionError::InsufficientFunds) => {
+                TransactionFailure::InsufficientFunds
+            }
+            TransactionError::InstructionError(_, InstructionError::Custom(code)) => {
+                if code == u32::from(AccountCompressionErrorCode::InvalidMerkleProof)
+                    || code == u32::from(ConcurrentMerkleTreeError::InvalidProof([0; 32], [0; 32]))
+                    || code == u32::from(ConcurrentMerkleTreeError::InvalidProofLength(0, 0))
+                {
+                    TransactionFailure::ProofInvalid
+                } else if code == u32::from(AccountCompressionErrorCode::LeafNotFound)
+                    || code == u32::from(HashSetError::ElementDoesNotExist)
+                    || code == u32::from(IndexedMerkleTreeError::ElementAlreadyExists)
+                {
+                    TransactionFailure::AlreadyProcessed
+                } else {
+                    TransactionFailure::Other
+                }
+            }
+            _ => TransactionFailure::Other,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            TransactionFailure::ProofInvalid => "proof_invalid",
+            TransactionFailure::AlreadyProcessed => "already_processed",
+            TransactionFailure::Dropped => "dropped",
+            TransactionFailure::InsufficientFunds => "insufficient_funds",
+            TransactionFailure::Other => "other",
+        }
+    }
+}
+
+fn is_blockhash_expired(error: &RpcError) -> bool {
+    match error {
+        RpcError::TransactionError(e) => *e == TransactionError::BlockhashNotFound,
+        RpcError::ClientError(e) => {
+            e.get_transaction_error() == Some(TransactionError::BlockhashNotFound)
+        }
+        _ => false,
+    }
+}
+
+/// Whether creating an account failed because it already exists, e.g. the
+/// forester epoch PDA created by an earlier registration attempt.
+fn is_account_already_in_use(error: &RpcError) -> bool {
+    let transaction_error = match error {
+        RpcError::TransactionError(e) => Some(e.clone()),
+        RpcError::ClientError(e) => e.get_transaction_error(),
+        _ => None,
+    };
+    matches!(
+        transaction_error,
+        Some(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code)
+        )) if code == SystemError::AccountAlreadyInUse as u32
+    )
+}
+
+/// Whether the transaction was rejected because it already landed, e.g. in
+/// an attempt whose confirmation timed out.
+fn is_already_processed(error: &RpcError) -> bool {
+    match error {
+        RpcError::TransactionError(e) => *e == TransactionError::AlreadyProcessed,
+        RpcError::ClientError(e) => {
+            e.get_transaction_error() == Some(TransactionError::AlreadyProcessed)
+        }
+        _ => false,
+    }
+}
+
+/// Errors which may succeed when the request is sent again, i.e. failures to
+/// reach the RPC node or an expired blockhash, as opposed to a transaction
+/// rejected by the program.
+fn is_transient(error: &RpcError) -> bool {
+    match error {
+        RpcError::ClientError(e) => {
+            e.get_transaction_error().is_none() || is_blockhash_expired(error)
+        }
+        RpcError::IoError(_) => true,
+        _ => is_blockhash_expired(error),
+    }
+}
+
+/// Whether a failed transaction batch may succeed when it is sent again.
+/// Batches rejected by a program, e.g. for an invalid proof or a violated
+/// account constraint, or referencing invalid accounts fail the same way on
+/// every attempt, so retrying them only uses up the retry budget. Neither
+/// can a batch succeed once the active phase is over.
+fn is_retryable(error: &ForesterError) -> bool {
+    let error = match error {
+        ForesterError::RpcError(error) => error,
+        ForesterError::NotInActivePhase => return false,
+        _ => return true,
+    };
+    if is_transient(error) {
+        return true;
+    }
+    let transaction_error = match error {
+        RpcError::TransactionError(e) => Some(e.clone()),
+        RpcError::ClientError(e) => e.get_transaction_error(),
+        _ => None,
+    };
+    !matches!(
+        transaction_error,
+        Some(
+            TransactionError::InstructionError(..)
+                | TransactionError::AccountNotFound
+                | TransactionError::ProgramAccountNotFound
+                | TransactionError::InvalidAccountForFee
+                | TransactionError::InvalidAccountIndex
+                | TransactionError::InvalidProgramForExecution
+        )
+    )
+}
+
+/// Whether the parameters phases are computed from differ.
+fn phase_parameters_changed(current: &ProtocolConfig, new: &ProtocolConfig) -> bool {
+    current.genesis_slot != new.genesis_slot
+        || current.slot_length != new.slot_length
+        || current.registration_phase_length != new.registration_phase_length
+        || current.active_phase_length != new.active_phase_length
+        || current.report_work_phase_length != new.report_work_phase_length
+}
+
+/// Delay in slots after the registration window opens before `forester`
+/// registers, so that a fleet of foresters doesn't register all at once. The
+/// offset is derived from the pubkey, i.e. stable across epochs, and at most
+/// half the window so that enough of the window is left for retries.
+pub fn registration_offset(forester: &Pubkey, jitter_slots: u64, window_length: u64) -> u64 {
+    let max_offset = jitter_slots.min(window_length / 2);
+    if max_offset == 0 {
+        return 0;
+    }
+    let seed = u64::from_le_bytes(forester.to_bytes()[..8].try_into().unwrap());
+    seed % (max_offset + 1)
+}
+
+/// Whether a queue with `item_count` items should wait for more items to
+/// accumulate. Queues are always processed close to the end of the active
+/// phase so that no items are left behind.
+fn should_defer_queue(
+    item_count: usize,
+    min_queue_items: usize,
+    current_slot: u64,
+    active_phase_end: u64,
+) -> bool {
+    item_count < min_queue_items
+        && current_slot.saturating_add(DEFERRAL_DEADLINE_SLOTS) < active_phase_end
+}
+
+/// Whether a batch started at `current_slot` risks landing after the active
+/// phase ends, in which case it would revert.
+fn is_near_active_phase_end(current_slot: u64, margin_slots: u64, active_phase_end: u64) -> bool {
+    margin_slots > 0 && current_slot.saturating_add(margin_slots) > active_phase_end
+}
+
+/// Orders `proofs` like the `requested` hashes. Returns the hashes without a
+/// proof if the indexer did not return a proof for every requested hash.
+fn match_state_proofs(
+    requested: &[String],
+    proofs: Vec<MerkleProof>,
+) -> std::result::Result<Vec<MerkleProof>, Vec<String>> {
+    let mut proofs: HashMap<String, MerkleProof> = proofs
+        .into_iter()
+        .map(|proof| (proof.hash.clone(), proof))
+        .collect();
+    let mut matched = Vec::with_capacity(requested.len());
+    let mut missing = Vec::new();
+    for hash in requested {
+        match proofs.remove(hash) {
+            Some(proof) => matched.push(proof),
+            None => missing.push(hash.clone()),
+        }
+    }
+    if missing.is_empty() {
+        Ok(matched)
+    } else {
+        Err(missing)
+    }
+}
+
+/// Returns the trees in `new` but not in `old` and the trees in `old` but not
+/// in `new`.
+fn diff_trees(
+    old: &[TreeAccounts],
+    new: &[TreeAccounts],
+) -> (Vec<TreeAccounts>, Vec<TreeAccounts>) {
+    let contains = |trees: &[TreeAccounts], tree: &TreeAccounts| {
+        trees.iter().any(|t| t.merkle_tree == tree.merkle_tree)
+    };
+    let added = new
+        .iter()
+        .filter(|tree| !contains(old, tree))
+        .copied()
+        .collect();
+    let removed = old
+        .iter()
+        .filter(|tree| !contains(new, tree))
+        .copied()
+        .collect();
+    (added, removed)
+}
+
+/// Orders values by the fill ratio of their queue, fullest queue first.
+fn sort_by_fill_ratio<T>(mut values: Vec<(f64, T)>) -> Vec<T> {
+    values.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    values.into_iter().map(|(_, value)| value).collect()
+}
+
+async fn apply_indexer_updates<R: RpcConnection, I: Indexer<R>>(
+    indexer: &RwLock<I>,
+    applied_updates: &Mutex<AppliedIndexerUpdates>,
+    epoch: u64,
+    work_items: &[WorkItem],
+    proofs: &[Proof],
+) {
+    for (work_item, proof) in work_items.iter().zip(proofs.iter()) {
+        let merkle_tree = work_item.tree_account.merkle_tree;
+        if !applied_updates
+            .lock()
+            .await
+            .insert(epoch, merkle_tree, work_item.queue_item_data.hash)
+        {
+            debug!(
+                "Indexer update for {:?} already applied, skipping",
+                work_item.queue_item_data.hash
+            );
+            continue;
+        }
+        match proof {
+            Proof::AddressProof(address_proof) => {
+                let mut indexer = indexer.write().await;
+                indexer.address_tree_updated(merkle_tree, address_proof);
+                drop(indexer);
+            }
+            Proof::StateProof(state_proof) => {
+                let mut indexer = indexer.write().await;
+                indexer.account_nullified(merkle_tree, &state_proof.hash);
+                drop(indexer);
+            }
+        }
+    }
+}
+
+/// Separates items whose proofs can no longer be applied on chain, so that
+/// they are fetched again with a fresh proof instead of being submitted.
+fn remove_stale_proofs(
+    instructions: &[Instruction],
+    proofs: &[Proof],
+    work_items: &[WorkItem],
+    sequence_numbers: &HashMap<Pubkey, u64>,
+    max_lag: Option<u64>,
+) -> ValidatedBatch {
+    let mut batch = ValidatedBatch::default();
+    for ((instruction, proof), work_item) in instructions.iter().zip(proofs).zip(work_items) {
+        let is_stale = sequence_numbers
+            .get(&work_item.tree_account.merkle_tree)
+            .is_some_and(|sequence_number| proof.is_stale(*sequence_number, max_lag));
+        if is_stale {
+            batch.stale_work_items.push(work_item.clone());
+        } else {
+            batch.instructions.push(instruction.clone());
+            batch.proofs.push(proof.clone());
+            batch.work_items.push(work_item.clone());
+        }
+    }
+    batch
+}
+
+/// Splits instructions and their proofs into per-transaction batches of the
+/// given sizes. Returns no batches when there is nothing to send.
+fn transaction_batches(
+    instructions: &[Instruction],
+    proofs: &[Proof],
+    batch_sizes: &[usize],
+) -> Vec<(Vec<Instruction>, Vec<Proof>)> {
+    let mut offset = 0;
+    batch_sizes
+        .iter()
+        .map(|size| {
+            let end = offset + size;
+            let batch = (
+                instructions[offset..end].to_vec(),
+                proofs[offset..end].to_vec(),
+            );
+            offset = end;
+            batch
+        })
+        .collect()
+}
+
+/// Greedily groups instructions so that each transaction, including the
+/// `prefix_instructions` every batch starts with, stays within the packet
+/// size limit.
+fn pack_instructions(
+    instructions: &[Instruction],
+    payer: &Pubkey,
+    prefix_instructions: &[Instruction],
+    max_instructions: usize,
+) -> Vec<usize> {
+    let mut batch_sizes = Vec::new();
+    let mut batch = prefix_instructions.to_vec();
+    for instruction in instructions {
+        batch.push(instruction.clone());
+        let batch_len = batch.len() - prefix_instructions.len();
+        if batch_len > 1
+            && (batch_len > max_instructions || transaction_size(&batch, payer) > PACKET_DATA_SIZE)
+        {
+            batch_sizes.push(batch_len - 1);
+            batch = prefix_instructions.to_vec();
+            batch.push(instruction.clone());
+        }
+    }
+    if batch.len() > prefix_instructions.len() {
+        batch_sizes.push(batch.len() - prefix_instructions.len());
+    }
+    batch_sizes
+}
+
+fn transaction_size(instructions: &[Instruction], payer: &Pubkey) -> usize {
+    let transaction = Transaction::new_with_payer(instructions, Some(payer));
+    bincode::serialized_size(&transaction).unwrap_or(u64::MAX) as usize
+}
+
+/// Fetches the registration of `forester` for `epoch` and derives its
+/// schedule for `trees`, e.g. to forecast the workload of an upcoming epoch.
+pub async fn fetch_forester_epoch_info<R: RpcConnection>(
+    rpc: &mut R,
+    registry: &RegistryProgram,
+    protocol_config: &ProtocolConfig,
+    forester: &Pubkey,
+    epoch: u64,
+    trees: &[TreeAccounts],
+) -> Result<ForesterEpochInfo> {
+    let forester_epoch_pda = registry.forester_epoch_pda(forester, epoch);
+    let mut epoch_pda = rpc
+        .get_anchor_account::<ForesterEpochPda>(&forester_epoch_pda)
+        .await?
+        .ok_or_else(|| {
+            ForesterError::Custom(format!(
+                "Forester {} is not registered for epoch {}",
+                forester, epoch
+            ))
+        })?;
+    // The total weight is only set once registration is finalized, until then
+    // the weight registered so far is the best estimate.
+    if epoch_pda.total_epoch_weight.is_none() {
+        let registered_weight = rpc
+            .get_anchor_account::<EpochPda>(&registry.epoch_pda(epoch))
+            .await?
+            .ok_or_else(|| ForesterError::Custom(format!("Epoch {} account not found", epoch)))?
+            .registered_weight;
+        epoch_pda.total_epoch_weight = Some(registered_weight);
+    }
+
+    let slot = rpc.get_slot().await?;
+    let phases = get_epoch_phases(protocol_config, epoch);
+    let active_phase_start = phases.active.start;
+    let mut epoch_info = ForesterEpochInfo {
+        epoch: Epoch {
+            epoch,
+            epoch_pda: registry.epoch_pda(epoch),
+            forester_epoch_pda,
+            state: phases.get_current_epoch_state(slot),
+            phases,
+            merkle_trees: Vec::new(),
+        },
+        epoch_pda,
+        trees: Vec::new(),
+    };
+    epoch_info.add_trees_with_schedule(trees, active_phase_start);
+    Ok(epoch_info)
+}
+
+/// Tasks spawned by [`run_service`] next to the epoch manager, aborted when
+/// the service stops so that e.g. the metrics server releases its port.
+#[derive(Debug, Default)]
+struct ServiceTasks(Vec<AbortHandle>);
+
+impl ServiceTasks {
+    fn push<T>(&mut self, task: JoinHandle<T>) {
+        self.0.push(task.abort_handle());
+    }
+}
+
+impl Drop for ServiceTasks {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run_service<R: RpcConnection, I: Indexer<R>>(
     config: Arc<ForesterConfig>,
     protocol_config: Arc<ProtocolConfig>,
     rpc_pool: Arc<SolanaRpcPool<R>>,
-    indexer: Arc<Mutex<I>>,
+    indexer: Arc<RwLock<I>>,
+    signer: Arc<dyn ForesterSigner>,
     shutdown: oneshot::Receiver<()>,
     work_report_sender: mpsc::Sender<WorkReport>,
+    event_sender: Option<mpsc::Sender<ForesterEvent>>,
+    mut control_receiver: Option<mpsc::Receiver<ControlCommand>>,
     slot_tracker: Arc<SlotTracker>,
 ) -> Result<()> {
     const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
@@ -1139,11 +3529,43 @@ pub async fn run_service<R: RpcConnection, I: Indexer<R>>(
     let mut retry_count = 0;
     let mut retry_delay = INITIAL_RETRY_DELAY;
     let start_time = Instant::now();
+    let run_deadline = async {
+        match config.run_duration_seconds {
+            Some(seconds) => sleep_until(start_time + Duration::from_secs(seconds)).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(run_deadline);
 
     let trees = {
         let rpc = rpc_pool.get_connection().await?;
-        fetch_trees(&*rpc).await
+        match config.tree_type {
+            Some(tree_type) => fetch_trees_by_type(&*rpc, tree_type).await,
+            None => fetch_trees(&*rpc).await,
+        }
     };
+    let startup_summary = config.startup_summary(&trees);
+    info!("Forester startup summary: {}", startup_summary);
+    let mut service_tasks = ServiceTasks::default();
+    service_tasks.push(tokio::spawn(run_queue_fill_ratio_emitter(
+        rpc_pool.clone(),
+        trees.iter().map(|tree| tree.queue).collect(),
+        Duration::from_secs(config.queue_metrics_interval_seconds),
+    )));
+    let protocol_config = Arc::new(ArcSwap::new(protocol_config));
+    let health = Arc::new(HealthState::default());
+    health.record_startup_summary(startup_summary);
+    if let Some(port) = config.metrics_port {
+        service_tasks.push(tokio::spawn(run_metrics_server(
+            port,
+            Arc::new(HealthCheck::new(
+                health.clone(),
+                slot_tracker.clone(),
+                protocol_config.clone(),
+                Duration::from_secs(config.health_staleness_seconds),
+            )),
+        )));
+    }
 
     while retry_count < config.max_retries {
         debug!("Creating EpochManager (attempt {})", retry_count + 1);
@@ -1152,9 +3574,12 @@ pub async fn run_service<R: RpcConnection, I: Indexer<R>>(
             protocol_config.clone(),
             rpc_pool.clone(),
             indexer.clone(),
+            signer.clone(),
             work_report_sender.clone(),
+            event_sender.clone(),
             trees.clone(),
             slot_tracker.clone(),
+            health.clone(),
         )
         .await
         {
@@ -1164,11 +3589,30 @@ pub async fn run_service<R: RpcConnection, I: Indexer<R>>(
                     "Successfully created EpochManager after {} attempts",
                     retry_count + 1
                 );
+                if let Some(receiver) = control_receiver.take() {
+                    let epoch_manager = epoch_manager.clone();
+                    service_tasks.push(tokio::spawn(async move {
+                        epoch_manager.handle_control_commands(receiver).await
+                    }));
+                }
 
                 return tokio::select! {
-                    result = epoch_manager.run() => result,
+                    result = epoch_manager.clone().run() => result,
                     _ = shutdown => {
                         info!("Received shutdown signal. Stopping the service.");
+                        epoch_manager
+                            .drain(Duration::from_secs(config.shutdown_grace_period_seconds))
+                            .await;
+                        Ok(())
+                    }
+                    _ = &mut run_deadline => {
+                        info!(
+                            "Run duration elapsed after {:?}. Stopping the service.",
+                            start_time.elapsed()
+                        );
+                        epoch_manager
+                            .drain(Duration::from_secs(config.shutdown_grace_period_seconds))
+                            .await;
                         Ok(())
                     }
                 };
@@ -1203,3 +3647,1052 @@ pub async fn run_service<R: RpcConnection, I: Indexer<R>>(
         "Unexpected error: Retry loop exited without returning".to_string(),
     ))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::{test_config, MockRpc, MockRpcState};
+    use account_compression::{
+        queue_from_bytes_zero_copy_init, QueueAccount, StateMerkleTreeAccount,
+    };
+    use anchor_lang::AnchorSerialize;
+    use light_test_utils::forester_epoch::ForesterSlot;
+    use light_test_utils::indexer::IndexerError;
+    use light_test_utils::rpc::SolanaRpcConnection;
+    use num_bigint::BigUint;
+    use solana_sdk::account::Account;
+    use solana_sdk::commitment_config::CommitmentConfig;
+    use solana_sdk::compute_budget::ComputeBudgetInstruction;
+    use std::mem;
+
+    #[derive(Debug, Default)]
+    struct MockIndexer {
+        state_proofs: Vec<MerkleProof>,
+        nullified: Vec<(Pubkey, String)>,
+    }
+
+    impl<R: RpcConnection> Indexer<R> for MockIndexer {
+        async fn get_multiple_compressed_account_proofs(
+            &self,
+            _hashes: Vec<String>,
+        ) -> std::result::Result<Vec<MerkleProof>, IndexerError> {
+            Ok(self.state_proofs.clone())
+        }
+
+        async fn get_rpc_compressed_accounts_by_owner(
+            &self,
+            _owner: &Pubkey,
+        ) -> std::result::Result<Vec<String>, IndexerError> {
+            unimplemented!()
+        }
+
+        async fn get_multiple_new_address_proofs(
+            &self,
+            _merkle_tree_pubkey: [u8; 32],
+            _addresses: Vec<[u8; 32]>,
+        ) -> std::result::Result<Vec<NewAddressProofWithContext>, IndexerError> {
+            unimplemented!()
+        }
+
+        fn account_nullified(&mut self, merkle_tree_pubkey: Pubkey, account_hash: &str) {
+            self.nullified
+                .push((merkle_tree_pubkey, account_hash.to_string()));
+        }
+    }
+
+    fn state_proof() -> Proof {
+        state_proof_with_root_seq(0)
+    }
+
+    fn state_proof_with_root_seq(root_seq: u64) -> Proof {
+        Proof::StateProof(MerkleProof {
+            hash: String::new(),
+            leaf_index: 0,
+            merkle_tree: String::new(),
+            proof: vec![],
+            root_seq,
+        })
+    }
+
+    fn state_work_item(tree_account: TreeAccounts, hash: u8) -> WorkItem {
+        WorkItem {
+            tree_account,
+            queue_item_data: QueueItemData {
+                hash: [hash; 32],
+                index: hash as usize,
+                insertion_slot: None,
+            },
+        }
+    }
+
+    /// Queue account containing an item with value `[hash; 32]` for each of
+    /// `hashes`.
+    fn queue_account(hashes: &[u8]) -> Account {
+        const CAPACITY: usize = 100;
+        let mut data = vec![
+            0;
+            8 + mem::size_of::<QueueAccount>()
+                + light_hash_set::HashSet::size_in_account(CAPACITY)
+        ];
+        {
+            let mut queue =
+                unsafe { queue_from_bytes_zero_copy_init(&mut data, CAPACITY, 0).unwrap() };
+            for hash in hashes {
+                queue
+                    .insert(&BigUint::from_bytes_be(&[*hash; 32]), 0)
+                    .unwrap();
+            }
+        }
+        Account {
+            lamports: 1,
+            data,
+            owner: account_compression::ID,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    /// Proof the mock indexer returns for the queue item `[hash; 32]`.
+    fn merkle_proof(tree: &TreeAccounts, hash: u8, root_seq: u64) -> MerkleProof {
+        MerkleProof {
+            hash: bs58::encode(&[hash; 32]).into_string(),
+            leaf_index: 0,
+            merkle_tree: tree.merkle_tree.to_string(),
+            proof: vec![],
+            root_seq,
+        }
+    }
+
+    /// State tree account whose header has `sequence_number`.
+    fn state_tree_account(sequence_number: usize) -> Account {
+        let mut data = vec![0; 8 + mem::size_of::<StateMerkleTreeAccount>()];
+        for field in [26, 10, 0, sequence_number] {
+            data.extend_from_slice(&field.to_ne_bytes());
+        }
+        Account {
+            lamports: 1,
+            data,
+            owner: account_compression::ID,
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn instruction(data_len: usize) -> Instruction {
+        Instruction::new_with_bytes(Pubkey::new_unique(), &vec![0; data_len], vec![])
+    }
+
+    /// Forester registered for epoch 0 of the default protocol config and
+    /// eligible in every light slot of `tree`, backed by a [`MockRpc`] whose
+    /// slot is in the active phase. Slots only advance through
+    /// [`TestForester::advance_to`].
+    struct TestForester {
+        epoch_manager: EpochManager<MockRpc, MockIndexer>,
+        epoch_info: ForesterEpochInfo,
+        rpc: Arc<MockRpcState>,
+        work_reports: mpsc::Receiver<WorkReport>,
+        events: mpsc::Receiver<ForesterEvent>,
+    }
+
+    impl TestForester {
+        fn advance_to(&self, slot: u64) {
+            self.rpc.slot.store(slot, Ordering::Relaxed);
+            self.epoch_manager.slot_tracker.update(slot);
+        }
+    }
+
+    async fn test_forester(mut config: ForesterConfig, tree: TreeAccounts) -> TestForester {
+        let protocol_config = ProtocolConfig::default();
+        let phases = get_epoch_phases(&protocol_config, 0);
+        let active_phase_start = phases.active.start;
+        let (url, rpc) = MockRpcState::register();
+        rpc.slot.store(active_phase_start + 1, Ordering::Relaxed);
+        config.external_services.rpc_url = url.clone();
+        config.max_retries = 0;
+
+        let signer = Keypair::from_bytes(&config.payer_keypair.to_bytes()).unwrap();
+        let registry = config.registry();
+        let epoch_pda = ForesterEpochPda {
+            authority: signer.pubkey(),
+            epoch: 0,
+            weight: 1,
+            epoch_active_phase_start_slot: active_phase_start,
+            total_epoch_weight: Some(1),
+            protocol_config,
+            ..Default::default()
+        };
+        let forester_epoch_pda = registry.forester_epoch_pda(&signer.pubkey(), 0);
+        let mut data = vec![0; 8];
+        epoch_pda.serialize(&mut data).unwrap();
+        rpc.accounts.lock().unwrap().insert(
+            forester_epoch_pda,
+            Account {
+                lamports: 1,
+                data,
+                owner: registry.program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+
+        let light_slots = protocol_config.active_phase_length / protocol_config.slot_length;
+        let epoch_info = ForesterEpochInfo {
+            epoch: Epoch {
+                epoch: 0,
+                epoch_pda: registry.epoch_pda(0),
+                forester_epoch_pda,
+                state: phases.get_current_epoch_state(active_phase_start + 1),
+                phases,
+                merkle_trees: Vec::new(),
+            },
+            epoch_pda,
+            trees: vec![TreeForesterSchedule {
+                tree_accounts: tree,
+                slots: (0..light_slots)
+                    .map(|slot| {
+                        Some(ForesterSlot {
+                            slot,
+                            start_solana_slot: active_phase_start
+                                + slot * protocol_config.slot_length,
+                            end_solana_slot: active_phase_start
+                                + (slot + 1) * protocol_config.slot_length,
+                            forester_index: 0,
+                        })
+                    })
+                    .collect(),
+            }],
+        };
+
+        let (work_report_sender, work_reports) = mpsc::channel(10);
+        let (event_sender, events) = mpsc::channel(100);
+        let rpc_pool = SolanaRpcPool::<MockRpc>::new(url, CommitmentConfig::confirmed(), 2)
+            .await
+            .unwrap();
+        let epoch_manager = EpochManager::new(
+            Arc::new(config),
+            Arc::new(ArcSwap::from_pointee(protocol_config)),
+            Arc::new(rpc_pool),
+            Arc::new(RwLock::new(MockIndexer::default())),
+            Arc::new(signer),
+            work_report_sender,
+            Some(event_sender),
+            vec![tree],
+            Arc::new(SlotTracker::new(
+                active_phase_start + 1,
+                Duration::from_secs(10),
+            )),
+            Arc::new(HealthState::default()),
+        )
+        .await
+        .unwrap();
+        TestForester {
+            epoch_manager,
+            epoch_info,
+            rpc,
+            work_reports,
+            events,
+        }
+    }
+
+    #[test]
+    fn test_classify_transaction_failure() {
+        let instruction_error = |error: InstructionError| {
+            RpcError::TransactionError(TransactionError::InstructionError(1, error))
+        };
+        assert_eq!(
+            TransactionFailure::classify(&instruction_error(InstructionError::Custom(
+                AccountCompressionErrorCode::InvalidMerkleProof.into()
+            ))),
+            TransactionFailure::ProofInvalid
+        );
+        assert_eq!(
+            TransactionFailure::classify(&instruction_error(InstructionError::Custom(
+                ConcurrentMerkleTreeError::InvalidProof([1; 32], [2; 32]).into()
+            ))),
+            TransactionFailure::ProofInvalid
+        );
+        assert_eq!(
+            TransactionFailure::classify(&instruction_error(InstructionError::Custom(
+                AccountCompressionErrorCode::LeafNotFound.into()
+            ))),
+            TransactionFailure::AlreadyProcessed
+        );
+        assert_eq!(
+            TransactionFailure::classify(&RpcError::ClientError(
+                TransactionError::InsufficientFundsForFee.into()
+            )),
+            TransactionFailure::InsufficientFunds
+        );
+        assert_eq!(
+            TransactionFailure::classify(&RpcError::TransactionError(
+                TransactionError::BlockhashNotFound
+            )),
+            TransactionFailure::Dropped
+        );
+        assert_eq!(
+            TransactionFailure::classify(&RpcError::IoError(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "timed out"
+            ))),
+            TransactionFailure::Dropped
+        );
+        assert_eq!(
+            TransactionFailure::classify(&instruction_error(InstructionError::Custom(1))),
+            TransactionFailure::Other
+        );
+    }
+
+    #[test]
+    fn test_is_blockhash_expired() {
+        assert!(is_blockhash_expired(&RpcError::TransactionError(
+            TransactionError::BlockhashNotFound
+        )));
+        assert!(is_blockhash_expired(&RpcError::ClientError(
+            TransactionError::BlockhashNotFound.into()
+        )));
+        assert!(!is_blockhash_expired(&RpcError::TransactionError(
+            TransactionError::AccountNotFound
+        )));
+        assert!(!is_blockhash_expired(&RpcError::CustomError(
+            "connection reset".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&RpcError::IoError(std::io::Error::from(
+            std::io::ErrorKind::ConnectionReset
+        ))));
+        assert!(is_transient(&RpcError::ClientError(
+            std::io::Error::from(std::io::ErrorKind::TimedOut).into()
+        )));
+        assert!(is_transient(&RpcError::TransactionError(
+            TransactionError::BlockhashNotFound
+        )));
+        assert!(!is_transient(&RpcError::ClientError(
+            TransactionError::AccountNotFound.into()
+        )));
+        assert!(!is_transient(&RpcError::TransactionError(
+            TransactionError::AccountNotFound
+        )));
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        let rpc_error = |error: RpcError| ForesterError::RpcError(error);
+        // Blockhash expired, RPC timeout and a node which is behind.
+        assert!(is_retryable(&rpc_error(RpcError::TransactionError(
+            TransactionError::BlockhashNotFound
+        ))));
+        assert!(is_retryable(&rpc_error(RpcError::ClientError(
+            std::io::Error::from(std::io::ErrorKind::TimedOut).into()
+        ))));
+        assert!(is_retryable(&rpc_error(RpcError::ClientError(
+            solana_client::rpc_request::RpcError::RpcResponseError {
+                code: -32005,
+                message: "Node is behind".to_string(),
+                data: solana_client::rpc_request::RpcResponseErrorData::NodeUnhealthy {
+                    num_slots_behind: Some(100),
+                },
+            }
+            .into()
+        ))));
+        assert!(is_retryable(&ForesterError::PoolExhausted(
+            Duration::from_secs(1)
+        )));
+
+        // Program errors and invalid accounts.
+        assert!(!is_retryable(&rpc_error(RpcError::TransactionError(
+            TransactionError::InstructionError(
+                1,
+                InstructionError::Custom(AccountCompressionErrorCode::InvalidMerkleProof.into())
+            )
+        ))));
+        assert!(!is_retryable(&rpc_error(RpcError::ClientError(
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(anchor_lang::error::ErrorCode::ConstraintHasOne.into())
+            )
+            .into()
+        ))));
+        assert!(!is_retryable(&rpc_error(RpcError::TransactionError(
+            TransactionError::AccountNotFound
+        ))));
+        assert!(!is_retryable(&rpc_error(RpcError::ClientError(
+            TransactionError::InvalidAccountForFee.into()
+        ))));
+        assert!(!is_retryable(&ForesterError::NotInActivePhase));
+
+        // A transaction which already landed is a success, not a failure.
+        assert!(is_already_processed(&RpcError::TransactionError(
+            TransactionError::AlreadyProcessed
+        )));
+        assert!(is_already_processed(&RpcError::ClientError(
+            TransactionError::AlreadyProcessed.into()
+        )));
+        assert!(!is_already_processed(&RpcError::TransactionError(
+            TransactionError::AccountNotFound
+        )));
+    }
+
+    #[test]
+    fn test_is_account_already_in_use() {
+        let already_in_use = TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(SystemError::AccountAlreadyInUse as u32),
+        );
+        assert!(is_account_already_in_use(&RpcError::TransactionError(
+            already_in_use.clone()
+        )));
+        assert!(is_account_already_in_use(&RpcError::ClientError(
+            already_in_use.into()
+        )));
+        assert!(!is_account_already_in_use(&RpcError::TransactionError(
+            TransactionError::InstructionError(
+                0,
+                InstructionError::Custom(SystemError::ResultWithNegativeLamports as u32)
+            )
+        )));
+        assert!(!is_account_already_in_use(&RpcError::CustomError(
+            "account already in use".to_string()
+        )));
+    }
+
+    #[test]
+    fn test_retain_pending_work_items() {
+        let tree_account = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let work_items: Vec<_> = (1..=4)
+            .map(|hash| state_work_item(tree_account, hash))
+            .collect();
+        let pending_items =
+            HashMap::from([(tree_account.queue, HashSet::from([[2; 32], [4; 32]]))]);
+
+        let pending_work_items = retain_pending_work_items(&work_items, &pending_items);
+        let hashes: Vec<_> = pending_work_items
+            .iter()
+            .map(|item| item.queue_item_data.hash[0])
+            .collect();
+        assert_eq!(hashes, vec![2, 4]);
+    }
+
+    #[test]
+    fn test_should_defer_queue() {
+        assert!(should_defer_queue(2, 10, 0, 1000));
+        assert!(!should_defer_queue(10, 10, 0, 1000));
+        assert!(!should_defer_queue(2, 0, 0, 1000));
+        // Close to the end of the active phase queues are always processed.
+        assert!(!should_defer_queue(
+            2,
+            10,
+            1000 - DEFERRAL_DEADLINE_SLOTS,
+            1000
+        ));
+    }
+
+    #[test]
+    fn test_registration_offset() {
+        let forester = Pubkey::new_unique();
+        assert_eq!(registration_offset(&forester, 0, 1000), 0);
+        assert_eq!(registration_offset(&forester, 100, 0), 0);
+        let offset = registration_offset(&forester, 100, 1000);
+        assert!(offset <= 100);
+        assert_eq!(registration_offset(&forester, 100, 1000), offset);
+        // Bounded by half the registration window.
+        assert!(registration_offset(&forester, 1000, 50) <= 25);
+
+        let offsets: HashSet<u64> = (0..20)
+            .map(|_| registration_offset(&Pubkey::new_unique(), 100, 1000))
+            .collect();
+        assert!(offsets.len() > 1);
+    }
+
+    #[test]
+    fn test_queue_tasks_coalesce_updates() {
+        let mut queue_tasks = QueueTasks::default();
+        let queue = Pubkey::new_unique();
+        assert!(queue_tasks.start(queue));
+        // Updates while the task is in flight lead to a single re-run.
+        assert!(!queue_tasks.start(queue));
+        assert!(!queue_tasks.start(queue));
+        assert!(queue_tasks.start(Pubkey::new_unique()));
+        assert!(queue_tasks.finish(queue));
+        assert!(!queue_tasks.finish(queue));
+        assert!(queue_tasks.start(queue));
+    }
+
+    #[test]
+    fn test_phase_parameters_changed() {
+        let current = ProtocolConfig::default();
+        let fee_changed = ProtocolConfig {
+            network_fee: current.network_fee + 1,
+            ..current
+        };
+        assert!(!phase_parameters_changed(&current, &fee_changed));
+        let active_phase_changed = ProtocolConfig {
+            active_phase_length: current.active_phase_length * 2,
+            ..current
+        };
+        assert!(phase_parameters_changed(&current, &active_phase_changed));
+    }
+
+    #[test]
+    fn test_is_near_active_phase_end() {
+        assert!(!is_near_active_phase_end(900, 0, 1000));
+        assert!(!is_near_active_phase_end(900, 100, 1000));
+        assert!(is_near_active_phase_end(901, 100, 1000));
+        assert!(is_near_active_phase_end(1000, 10, 1000));
+    }
+
+    #[test]
+    fn test_work_item_summary() {
+        let mut summary = WorkItemSummary::default();
+        summary.record(&Ok(BatchOutcome::Processed(Signature::default())));
+        summary.record(&Ok(BatchOutcome::Processed(Signature::default())));
+        summary.record(&Ok(BatchOutcome::SkippedNotEligible { light_slot: 3 }));
+        summary.record(&Ok(BatchOutcome::Skipped));
+        summary.record(&Ok(BatchOutcome::Simulated));
+        summary.record(&Err(ForesterError::StaleProofs));
+        assert_eq!(
+            summary,
+            WorkItemSummary {
+                processed: 2,
+                skipped_not_eligible: 1,
+                skipped: 1,
+                simulated: 1,
+                failed: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_match_state_proofs() {
+        let proof = |hash: &str| MerkleProof {
+            hash: hash.to_string(),
+            leaf_index: 0,
+            merkle_tree: String::new(),
+            proof: vec![],
+            root_seq: 0,
+        };
+        let requested = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+
+        let reordered = vec![proof("c"), proof("a"), proof("b")];
+        let matched: Vec<_> = match_state_proofs(&requested, reordered)
+            .unwrap()
+            .into_iter()
+            .map(|proof| proof.hash)
+            .collect();
+        assert_eq!(matched, requested);
+
+        let partial = vec![proof("a"), proof("c")];
+        assert_eq!(
+            match_state_proofs(&requested, partial).unwrap_err(),
+            vec!["b".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_diff_trees() {
+        let tree = |tree_type| {
+            TreeAccounts::new(Pubkey::new_unique(), Pubkey::new_unique(), tree_type, false)
+        };
+        let kept = tree(TreeType::State);
+        let removed = tree(TreeType::Address);
+        let added = tree(TreeType::State);
+        let mut rolled_over = kept;
+        rolled_over.is_rolledover = true;
+
+        assert_eq!(
+            diff_trees(&[kept, removed], &[rolled_over, added]),
+            (vec![added], vec![removed])
+        );
+        assert_eq!(diff_trees(&[kept], &[kept]), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_sort_by_fill_ratio() {
+        let tree_account = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let work_items_per_queue = vec![
+            (0.1, vec![state_work_item(tree_account, 1)]),
+            (0.9, vec![state_work_item(tree_account, 2)]),
+            (0.5, vec![state_work_item(tree_account, 3)]),
+            (0.9, vec![state_work_item(tree_account, 4)]),
+        ];
+        let work_items: Vec<_> = sort_by_fill_ratio(work_items_per_queue)
+            .into_iter()
+            .flatten()
+            .map(|item| item.queue_item_data.index)
+            .collect();
+        assert_eq!(work_items, vec![2, 4, 3, 1]);
+    }
+
+    #[test]
+    fn test_transaction_batches_all_items_skipped() {
+        let batch_sizes = pack_instructions(&[], &Pubkey::new_unique(), &[], usize::MAX);
+        assert!(batch_sizes.is_empty());
+        let batches = transaction_batches(&[], &[], &batch_sizes);
+        assert!(batches.is_empty());
+    }
+
+    #[test]
+    fn test_transaction_batches() {
+        let instructions = vec![instruction(0); 5];
+        let proofs = vec![state_proof(); 5];
+        let batches = transaction_batches(&instructions, &proofs, &[2, 2, 1]);
+        let batch_sizes: Vec<_> = batches
+            .iter()
+            .map(|(instructions, proofs)| (instructions.len(), proofs.len()))
+            .collect();
+        assert_eq!(batch_sizes, vec![(2, 2), (2, 2), (1, 1)]);
+    }
+
+    #[test]
+    fn test_pack_instructions_to_limit() {
+        let payer = Pubkey::new_unique();
+        let instructions: Vec<_> = (0..10).map(|_| instruction(300)).collect();
+
+        let compute_budget_instructions = vec![
+            ComputeBudgetInstruction::set_compute_unit_limit(1_000_000),
+            ComputeBudgetInstruction::set_compute_unit_price(1),
+        ];
+        let batch_sizes = pack_instructions(
+            &instructions,
+            &payer,
+            &compute_budget_instructions,
+            usize::MAX,
+        );
+        assert_eq!(batch_sizes.iter().sum::<usize>(), instructions.len());
+        assert!(batch_sizes.len() > 1);
+
+        let mut offset = 0;
+        for size in &batch_sizes {
+            let mut batch = compute_budget_instructions.clone();
+            batch.extend_from_slice(&instructions[offset..offset + size]);
+            assert!(transaction_size(&batch, &payer) <= PACKET_DATA_SIZE);
+            if offset + size < instructions.len() {
+                batch.push(instructions[offset + size].clone());
+                assert!(transaction_size(&batch, &payer) > PACKET_DATA_SIZE);
+            }
+            offset += size;
+        }
+    }
+
+    #[test]
+    fn test_pack_instructions_respects_max_instructions() {
+        let payer = Pubkey::new_unique();
+        let compute_budget_instructions =
+            vec![ComputeBudgetInstruction::set_compute_unit_limit(1_000_000)];
+        let instructions = vec![instruction(0); 5];
+        let batch_sizes = pack_instructions(&instructions, &payer, &compute_budget_instructions, 2);
+        assert_eq!(batch_sizes, vec![2, 2, 1]);
+
+        // Batches are split further if they exceed the packet size limit.
+        let instructions: Vec<_> = (0..8).map(|_| instruction(600)).collect();
+        let batch_sizes = pack_instructions(&instructions, &payer, &compute_budget_instructions, 4);
+        assert_eq!(batch_sizes.iter().sum::<usize>(), instructions.len());
+        assert!(batch_sizes.len() > 2);
+        assert!(batch_sizes.iter().all(|size| *size <= 4));
+    }
+
+    #[tokio::test]
+    async fn test_retried_batch_does_not_duplicate_indexer_update() {
+        let indexer = RwLock::new(MockIndexer::default());
+        let applied_updates = Mutex::new(AppliedIndexerUpdates::default());
+        let work_items = vec![WorkItem {
+            tree_account: TreeAccounts::new(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                TreeType::State,
+                false,
+            ),
+            queue_item_data: QueueItemData {
+                hash: [1; 32],
+                index: 0,
+                insertion_slot: None,
+            },
+        }];
+        let proofs = vec![state_proof()];
+
+        // The first attempt timed out but landed, the retry succeeded as well.
+        for _ in 0..2 {
+            apply_indexer_updates::<SolanaRpcConnection, _>(
+                &indexer,
+                &applied_updates,
+                0,
+                &work_items,
+                &proofs,
+            )
+            .await;
+        }
+        assert_eq!(indexer.read().await.nullified.len(), 1);
+
+        applied_updates.lock().await.clear_epoch(0);
+        apply_indexer_updates::<SolanaRpcConnection, _>(
+            &indexer,
+            &applied_updates,
+            0,
+            &work_items,
+            &proofs,
+        )
+        .await;
+        assert_eq!(indexer.read().await.nullified.len(), 2);
+    }
+
+    #[test]
+    fn test_stale_proof_is_requeued_instead_of_submitted() {
+        let tree_account = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let sequence_number = 2 * STATE_MERKLE_TREE_CHANGELOG;
+        let instructions = vec![instruction(1), instruction(2)];
+        let proofs = vec![
+            state_proof_with_root_seq(sequence_number - 1),
+            state_proof_with_root_seq(sequence_number - STATE_MERKLE_TREE_CHANGELOG),
+        ];
+        let work_items = vec![
+            state_work_item(tree_account, 1),
+            state_work_item(tree_account, 2),
+        ];
+        let sequence_numbers = HashMap::from([(tree_account.merkle_tree, sequence_number)]);
+
+        let batch =
+            remove_stale_proofs(&instructions, &proofs, &work_items, &sequence_numbers, None);
+        assert_eq!(batch.instructions, vec![instructions[0].clone()]);
+        assert_eq!(batch.proofs.len(), 1);
+        assert_eq!(batch.work_items.len(), 1);
+        assert_eq!(batch.work_items[0].queue_item_data.hash, [1; 32]);
+        assert_eq!(batch.stale_work_items.len(), 1);
+        assert_eq!(batch.stale_work_items[0].queue_item_data.hash, [2; 32]);
+    }
+
+    #[test]
+    fn test_proof_stale_beyond_configured_lag() {
+        let proof = state_proof_with_root_seq(100);
+        assert!(!proof.is_stale(110, Some(10)));
+        assert!(proof.is_stale(111, Some(10)));
+        // Without a tolerance the changelog size is the limit.
+        assert!(!proof.is_stale(99 + STATE_MERKLE_TREE_CHANGELOG, None));
+        assert!(proof.is_stale(100 + STATE_MERKLE_TREE_CHANGELOG, None));
+        // A tolerance cannot exceed what the changelog can patch.
+        assert!(proof.is_stale(100 + STATE_MERKLE_TREE_CHANGELOG, Some(u64::MAX)));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_sends_no_transactions() {
+        let mut config = test_config();
+        config.dry_run = true;
+        let tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let mut forester = test_forester(config, tree).await;
+        let epoch_manager = &forester.epoch_manager;
+        let work_items = vec![state_work_item(tree, 1)];
+
+        let outcome = epoch_manager
+            .process_transaction_batch_with_retry(
+                &forester.epoch_info,
+                &[instruction(10)],
+                &[state_proof()],
+                &work_items,
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome, BatchOutcome::Simulated);
+        assert_eq!(epoch_manager.get_processed_items_count(0).await, 0);
+
+        let mut rpc = epoch_manager.rpc_pool.get_connection().await.unwrap();
+        assert!(matches!(
+            epoch_manager
+                .register_forester_epoch_pda(&mut *rpc, 1)
+                .await,
+            Err(ForesterError::DryRun)
+        ));
+        assert!(matches!(
+            epoch_manager
+                .finalize_registration(&mut *rpc, &forester.epoch_info)
+                .await,
+            Ok(_)
+        ));
+        drop(rpc);
+        epoch_manager
+            .report_work(&forester.epoch_info)
+            .await
+            .unwrap();
+
+        assert_eq!(forester.rpc.sent_transactions(), 0);
+        assert_eq!(
+            forester.rpc.simulated_transactions.load(Ordering::Relaxed),
+            4
+        );
+        assert!(forester.events.try_recv().is_err());
+        assert!(forester.work_reports.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_already_processed_batch_counts_as_processed() {
+        let tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let forester = test_forester(test_config(), tree).await;
+        forester
+            .rpc
+            .send_errors
+            .lock()
+            .unwrap()
+            .push_back(RpcError::TransactionError(
+                TransactionError::AlreadyProcessed,
+            ));
+        let epoch_manager = &forester.epoch_manager;
+
+        let outcome = epoch_manager
+            .process_transaction_batch_with_retry(
+                &forester.epoch_info,
+                &[instruction(10)],
+                &[state_proof()],
+                &[state_work_item(tree, 1)],
+            )
+            .await
+            .unwrap();
+        assert!(matches!(outcome, BatchOutcome::Processed(_)));
+        assert_eq!(epoch_manager.get_processed_items_count(0).await, 1);
+        assert_eq!(epoch_manager.indexer.read().await.nullified.len(), 1);
+        assert_eq!(forester.rpc.sent_transactions(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_process_epoch_from_registration_to_work_report() {
+        let mut config = test_config();
+        config.enable_rollover = false;
+        // Nothing listens, so processing queue updates ends right after the
+        // initial pass over the queues.
+        config.external_services.ws_rpc_url = "ws://127.0.0.1:1".to_string();
+        let tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let mut forester = test_forester(config, tree).await;
+        let phases = forester.epoch_info.epoch.phases.clone();
+        {
+            // The forester epoch PDA is created by the registration.
+            let forester_epoch_pda = forester.epoch_info.epoch.forester_epoch_pda;
+            let mut accounts = forester.rpc.accounts.lock().unwrap();
+            let epoch_pda_account = accounts.remove(&forester_epoch_pda).unwrap();
+            forester
+                .rpc
+                .created_accounts
+                .lock()
+                .unwrap()
+                .push((forester_epoch_pda, epoch_pda_account));
+            accounts.insert(tree.queue, queue_account(&[1]));
+        }
+        forester.epoch_manager.indexer.write().await.state_proofs = vec![merkle_proof(&tree, 1, 0)];
+        forester.advance_to(phases.registration.start + 1);
+
+        let epoch_manager = forester.epoch_manager.clone();
+        let process_epoch = tokio::spawn(async move { epoch_manager.process_epoch(0).await });
+        let mut events = Vec::new();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Some(event) = forester.events.recv().await {
+                match event {
+                    ForesterEvent::Registered { .. } => forester.advance_to(phases.active.start),
+                    ForesterEvent::BatchProcessed { .. } => {
+                        forester.advance_to(phases.report_work.start)
+                    }
+                    _ => {}
+                }
+                let reported = matches!(event, ForesterEvent::WorkReported { .. });
+                events.push(event);
+                if reported {
+                    break;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        process_epoch.await.unwrap().unwrap();
+
+        assert!(matches!(
+            events[..],
+            [
+                ForesterEvent::Registered { epoch: 0 },
+                ForesterEvent::ActivePhaseStarted { epoch: 0 },
+                ForesterEvent::BatchProcessed { epoch: 0, .. },
+                ForesterEvent::WorkReported {
+                    epoch: 0,
+                    processed_items: 1,
+                },
+            ]
+        ));
+        let report = forester.work_reports.try_recv().unwrap();
+        assert_eq!(report.epoch, 0);
+        assert_eq!(report.processed_items, 1);
+        // Registration, finalization, the batch and the work report.
+        assert_eq!(forester.rpc.sent_transactions(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_blockhash_refresh_is_rate_limited() {
+        let mut config = test_config();
+        config.max_tps = Some(1);
+        let tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let forester = test_forester(config, tree).await;
+        forester
+            .rpc
+            .send_errors
+            .lock()
+            .unwrap()
+            .push_back(RpcError::TransactionError(
+                TransactionError::BlockhashNotFound,
+            ));
+
+        let start = Instant::now();
+        let outcome = forester
+            .epoch_manager
+            .process_transaction_batch_with_retry(
+                &forester.epoch_info,
+                &[instruction(10)],
+                &[state_proof()],
+                &[state_work_item(tree, 1)],
+            )
+            .await
+            .unwrap();
+        assert!(matches!(outcome, BatchOutcome::Processed(_)));
+        // The initial send takes the only token, the resend waits for the next.
+        assert!(start.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[tokio::test]
+    async fn test_work_items_without_instructions_spawn_no_batches() {
+        let mut config = test_config();
+        config.max_proof_root_seq_lag = Some(0);
+        let tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let forester = test_forester(config, tree).await;
+        {
+            let mut accounts = forester.rpc.accounts.lock().unwrap();
+            accounts.insert(tree.queue, queue_account(&[1]));
+            accounts.insert(tree.merkle_tree, state_tree_account(10));
+        }
+        let epoch_manager = &forester.epoch_manager;
+        // The proof lags the on-chain sequence number, so the item is
+        // skipped and the chunk produces no instructions.
+        epoch_manager.indexer.write().await.state_proofs = vec![merkle_proof(&tree, 1, 0)];
+
+        let results = epoch_manager
+            .process_work_items(&forester.epoch_info, &[state_work_item(tree, 1)])
+            .await
+            .unwrap();
+        assert!(results.is_empty());
+        assert!(epoch_manager.work_tasks.lock().unwrap().is_empty());
+        assert_eq!(forester.rpc.sent_transactions(), 0);
+        assert_eq!(epoch_manager.get_processed_items_count(0).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_items_queued_before_phase_end_are_drained() {
+        let tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let forester = test_forester(test_config(), tree).await;
+        forester
+            .rpc
+            .accounts
+            .lock()
+            .unwrap()
+            .insert(tree.queue, queue_account(&[1]));
+        let epoch_manager = &forester.epoch_manager;
+        epoch_manager.indexer.write().await.state_proofs = vec![merkle_proof(&tree, 1, 0)];
+
+        // The last update arrives at the drain slot, the item is only
+        // processed by the final pass.
+        let drain_slot = forester.epoch_info.epoch.phases.active.end - QUEUE_DRAIN_SLOTS;
+        forester.advance_to(drain_slot);
+        let (update_tx, update_rx) = mpsc::channel(1);
+        update_tx
+            .send(QueueUpdate {
+                pubkey: tree.queue,
+                slot: drain_slot,
+            })
+            .await
+            .unwrap();
+        epoch_manager
+            .process_updates_and_drain(&forester.epoch_info, update_rx)
+            .await
+            .unwrap();
+
+        assert_eq!(forester.rpc.sent_transactions(), 1);
+        assert_eq!(epoch_manager.get_processed_items_count(0).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_drain_aborts_monitor_and_work_tasks() {
+        let tree = TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        );
+        let forester = test_forester(test_config(), tree).await;
+        let epoch_manager = &forester.epoch_manager;
+        let monitor_task = tokio::spawn(std::future::pending::<()>());
+        epoch_manager
+            .monitor_tasks
+            .lock()
+            .await
+            .push(monitor_task.abort_handle());
+        let work_task = epoch_manager.spawn_work_task(std::future::pending());
+        let epoch_task = tokio::spawn(sleep(Duration::from_millis(10)));
+        epoch_manager.epoch_tasks.lock().await.push(epoch_task);
+
+        epoch_manager.drain(Duration::from_secs(1)).await;
+
+        assert!(monitor_task.await.unwrap_err().is_cancelled());
+        assert!(work_task.await.unwrap_err().is_cancelled());
+        assert!(epoch_manager.epoch_tasks.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_service_tasks_are_aborted_on_drop() {
+        let task = tokio::spawn(std::future::pending::<()>());
+        let abort_handle = task.abort_handle();
+        let mut service_tasks = ServiceTasks::default();
+        service_tasks.push(task);
+        assert!(!abort_handle.is_finished());
+
+        drop(service_tasks);
+        sleep(Duration::from_millis(10)).await;
+        assert!(abort_handle.is_finished());
+    }
+}
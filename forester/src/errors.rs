@@ -5,6 +5,8 @@ use light_test_utils::indexer::IndexerError;
 use light_test_utils::rpc::errors::RpcError;
 use photon_api::apis::{default_api::GetCompressedAccountProofPostError, Error as PhotonApiError};
 use solana_client::pubsub_client::PubsubClientError;
+use solana_sdk::signature::Signature;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::oneshot::error::RecvError;
@@ -12,8 +14,8 @@ use tokio::task::JoinError;
 
 #[derive(Error, Debug)]
 pub enum ForesterError {
-    #[error("Element is not eligible for foresting")]
-    NotEligible,
+    #[error("Element is not eligible for foresting in light slot {light_slot}")]
+    NotEligible { light_slot: u64 },
     #[error("RPC Error: {0}")]
     RpcError(#[from] RpcError),
     #[error("failed to deserialize account data")]
@@ -48,6 +50,37 @@ pub enum ForesterError {
     SubscriptionTimeout,
     #[error("Unexpected message: {0}")]
     UnexpectedMessage(String),
+    #[error("Forester is already registered for this epoch")]
+    AlreadyRegistered,
+    #[error("Too late to register for epoch")]
+    TooLateToRegister,
+    #[error("Epoch::register returned None")]
+    RegisterReturnedNone,
+    #[error("Transaction too large: {0} bytes")]
+    TransactionTooLarge(usize),
+    #[error("All proofs in the batch are stale")]
+    StaleProofs,
+    #[error("Indexer did not return proofs within {0:?}")]
+    ProofTimeout(Duration),
+    #[error("Tree is no longer ready for rollover")]
+    TreeAlreadyRolledOver,
+    #[error("Signer error: {0}")]
+    SignerError(String),
+    #[error("Indexer returned no proofs for {0:?}")]
+    MissingProofs(Vec<String>),
+    #[error("Rollover could not be verified on chain: {0}")]
+    RolloverNotVerified(String),
+    #[error("Rollover transaction {signature} was not confirmed within {timeout:?}")]
+    RolloverConfirmationTimeout {
+        signature: Signature,
+        timeout: Duration,
+    },
+    #[error("No pooled RPC connection became available within {0:?}")]
+    PoolExhausted(Duration),
+    #[error("Transaction was only simulated (dry run)")]
+    DryRun,
+    #[error("Not in active phase")]
+    NotInActivePhase,
     #[error("error: {0:?}")]
     Custom(String),
     #[error("unknown error")]
@@ -57,7 +90,9 @@ pub enum ForesterError {
 impl Clone for ForesterError {
     fn clone(&self) -> Self {
         match self {
-            ForesterError::NotEligible => ForesterError::NotEligible,
+            ForesterError::NotEligible { light_slot } => ForesterError::NotEligible {
+                light_slot: *light_slot,
+            },
             ForesterError::RpcError(_) => ForesterError::Custom("RPC Error".to_string()),
             ForesterError::DeserializeError(e) => ForesterError::DeserializeError(e.clone()),
             ForesterError::CopyMerkleTreeError(_) => {
@@ -83,6 +118,25 @@ impl Clone for ForesterError {
             ForesterError::ChannelDisconnected => ForesterError::ChannelDisconnected,
             ForesterError::SubscriptionTimeout => ForesterError::SubscriptionTimeout,
             ForesterError::UnexpectedMessage(s) => ForesterError::UnexpectedMessage(s.clone()),
+            ForesterError::AlreadyRegistered => ForesterError::AlreadyRegistered,
+            ForesterError::TooLateToRegister => ForesterError::TooLateToRegister,
+            ForesterError::RegisterReturnedNone => ForesterError::RegisterReturnedNone,
+            ForesterError::TransactionTooLarge(size) => ForesterError::TransactionTooLarge(*size),
+            ForesterError::StaleProofs => ForesterError::StaleProofs,
+            ForesterError::ProofTimeout(timeout) => ForesterError::ProofTimeout(*timeout),
+            ForesterError::TreeAlreadyRolledOver => ForesterError::TreeAlreadyRolledOver,
+            ForesterError::SignerError(e) => ForesterError::SignerError(e.clone()),
+            ForesterError::MissingProofs(hashes) => ForesterError::MissingProofs(hashes.clone()),
+            ForesterError::RolloverNotVerified(e) => ForesterError::RolloverNotVerified(e.clone()),
+            ForesterError::PoolExhausted(timeout) => ForesterError::PoolExhausted(*timeout),
+            ForesterError::DryRun => ForesterError::DryRun,
+            ForesterError::NotInActivePhase => ForesterError::NotInActivePhase,
+            ForesterError::RolloverConfirmationTimeout { signature, timeout } => {
+                ForesterError::RolloverConfirmationTimeout {
+                    signature: *signature,
+                    timeout: *timeout,
+                }
+            }
             ForesterError::Custom(s) => ForesterError::Custom(s.clone()),
             ForesterError::Unknown => ForesterError::Unknown,
         }
@@ -92,7 +146,9 @@ impl Clone for ForesterError {
 impl ForesterError {
     pub fn to_owned(&self) -> Self {
         match self {
-            ForesterError::NotEligible => ForesterError::NotEligible,
+            ForesterError::NotEligible { light_slot } => ForesterError::NotEligible {
+                light_slot: *light_slot,
+            },
             ForesterError::RpcError(e) => ForesterError::Custom(format!("RPC Error: {:?}", e)),
             ForesterError::DeserializeError(e) => {
                 ForesterError::Custom(format!("Deserialize Error: {:?}", e))
@@ -127,6 +183,25 @@ impl ForesterError {
             ForesterError::ChannelDisconnected => ForesterError::ChannelDisconnected,
             ForesterError::SubscriptionTimeout => ForesterError::SubscriptionTimeout,
             ForesterError::UnexpectedMessage(e) => ForesterError::UnexpectedMessage(e.clone()),
+            ForesterError::AlreadyRegistered => ForesterError::AlreadyRegistered,
+            ForesterError::TooLateToRegister => ForesterError::TooLateToRegister,
+            ForesterError::RegisterReturnedNone => ForesterError::RegisterReturnedNone,
+            ForesterError::TransactionTooLarge(size) => ForesterError::TransactionTooLarge(*size),
+            ForesterError::StaleProofs => ForesterError::StaleProofs,
+            ForesterError::ProofTimeout(timeout) => ForesterError::ProofTimeout(*timeout),
+            ForesterError::TreeAlreadyRolledOver => ForesterError::TreeAlreadyRolledOver,
+            ForesterError::SignerError(e) => ForesterError::SignerError(e.clone()),
+            ForesterError::MissingProofs(hashes) => ForesterError::MissingProofs(hashes.clone()),
+            ForesterError::RolloverNotVerified(e) => ForesterError::RolloverNotVerified(e.clone()),
+            ForesterError::PoolExhausted(timeout) => ForesterError::PoolExhausted(*timeout),
+            ForesterError::DryRun => ForesterError::DryRun,
+            ForesterError::NotInActivePhase => ForesterError::NotInActivePhase,
+            ForesterError::RolloverConfirmationTimeout { signature, timeout } => {
+                ForesterError::RolloverConfirmationTimeout {
+                    signature: *signature,
+                    timeout: *timeout,
+                }
+            }
         }
     }
 }
@@ -165,6 +240,9 @@ impl From<JoinError> for ForesterError {
 
 impl From<PoolError> for ForesterError {
     fn from(err: PoolError) -> Self {
-        ForesterError::Custom(err.to_string())
+        match err {
+            PoolError::Exhausted(timeout) => ForesterError::PoolExhausted(timeout),
+            err => ForesterError::Custom(err.to_string()),
+        }
     }
 }
@@ -0,0 +1,192 @@
+use crate::config::{ScheduleReport, StartupSummary};
+use crate::slot_tracker::{slot_duration, SlotTracker};
+use arc_swap::ArcSwap;
+use light_registry::protocol_config::state::{EpochState, ProtocolConfig};
+use light_test_utils::forester_epoch::get_epoch_phases;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Progress of the forester's work, updated by the epoch manager.
+#[derive(Debug, Default)]
+pub struct HealthState {
+    /// Unix timestamps in milliseconds, 0 if nothing happened yet.
+    last_processed_item: AtomicU64,
+    last_queue_update: AtomicU64,
+    pubsub_connected: AtomicBool,
+    /// Schedule of the most recent epoch whose active phase started.
+    schedule: Mutex<Option<ScheduleReport>>,
+    startup_summary: Mutex<Option<StartupSummary>>,
+}
+
+impl HealthState {
+    pub fn record_processed_item(&self) {
+        self.last_processed_item
+            .store(now_millis(), Ordering::Release);
+    }
+
+    pub fn record_queue_update(&self) {
+        self.last_queue_update
+            .store(now_millis(), Ordering::Release);
+    }
+
+    pub fn set_pubsub_connected(&self, connected: bool) {
+        self.pubsub_connected.store(connected, Ordering::Release);
+    }
+
+    /// Ignores schedules of epochs older than the recorded one, which may
+    /// still be processed concurrently.
+    pub fn record_schedule(&self, report: ScheduleReport) {
+        let mut schedule = self.schedule.lock().unwrap();
+        match &*schedule {
+            Some(schedule) if schedule.epoch > report.epoch => {}
+            _ => *schedule = Some(report),
+        }
+    }
+
+    pub fn schedule(&self) -> Option<ScheduleReport> {
+        self.schedule.lock().unwrap().clone()
+    }
+
+    pub fn record_startup_summary(&self, summary: StartupSummary) {
+        *self.startup_summary.lock().unwrap() = Some(summary);
+    }
+
+    pub fn startup_summary(&self) -> Option<StartupSummary> {
+        self.startup_summary.lock().unwrap().clone()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub epoch: u64,
+    pub phase: String,
+    pub slot: u64,
+    pub slot_tracker_drift: i64,
+    /// Unix timestamp in milliseconds.
+    pub last_processed_item: Option<u64>,
+    /// Unix timestamp in milliseconds.
+    pub last_queue_update: Option<u64>,
+    pub pubsub_connected: bool,
+}
+
+/// Reports the forester as unhealthy if, during an active phase, neither an
+/// item was processed nor a queue update received within `staleness`.
+#[derive(Debug)]
+pub struct HealthCheck {
+    state: Arc<HealthState>,
+    slot_tracker: Arc<SlotTracker>,
+    protocol_config: Arc<ArcSwap<ProtocolConfig>>,
+    staleness: Duration,
+}
+
+impl HealthCheck {
+    pub fn new(
+        state: Arc<HealthState>,
+        slot_tracker: Arc<SlotTracker>,
+        protocol_config: Arc<ArcSwap<ProtocolConfig>>,
+        staleness: Duration,
+    ) -> Self {
+        Self {
+            state,
+            slot_tracker,
+            protocol_config,
+            staleness,
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let slot = self.slot_tracker.estimated_current_slot();
+        let protocol_config = self.protocol_config.load();
+        let epoch = protocol_config.get_current_epoch(slot);
+        let phases = get_epoch_phases(&protocol_config, epoch);
+        let phase = phases.get_current_epoch_state(slot);
+        let last_processed_item = timestamp(&self.state.last_processed_item);
+        let last_queue_update = timestamp(&self.state.last_queue_update);
+
+        let now = now_millis();
+        let since_last_activity = last_processed_item
+            .max(last_queue_update)
+            .map(|last_activity| Duration::from_millis(now.saturating_sub(last_activity)));
+        let active_for = slot_duration() * slot.saturating_sub(phases.active.start) as u32;
+
+        HealthReport {
+            healthy: !is_stale(&phase, active_for, since_last_activity, self.staleness),
+            epoch,
+            phase: format!("{:?}", phase),
+            slot,
+            slot_tracker_drift: self.slot_tracker.drift(),
+            last_processed_item,
+            last_queue_update,
+            pubsub_connected: self.state.pubsub_connected.load(Ordering::Acquire),
+        }
+    }
+
+    pub fn schedule(&self) -> Option<ScheduleReport> {
+        self.state.schedule()
+    }
+
+    pub fn startup_summary(&self) -> Option<StartupSummary> {
+        self.state.startup_summary()
+    }
+}
+
+/// Whether the forester made no progress within `staleness` during an active
+/// phase. The active phase must have lasted `staleness` before it is stale,
+/// so that a forester isn't reported unhealthy right after the phase starts.
+fn is_stale(
+    phase: &EpochState,
+    active_for: Duration,
+    since_last_activity: Option<Duration>,
+    staleness: Duration,
+) -> bool {
+    if *phase != EpochState::Active || active_for <= staleness {
+        return false;
+    }
+    match since_last_activity {
+        Some(since_last_activity) => since_last_activity > staleness,
+        None => true,
+    }
+}
+
+fn timestamp(value: &AtomicU64) -> Option<u64> {
+    match value.load(Ordering::Acquire) {
+        0 => None,
+        timestamp => Some(timestamp),
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_stale() {
+        let staleness = Duration::from_secs(60);
+        let long = Duration::from_secs(120);
+        let short = Duration::from_secs(10);
+
+        assert!(is_stale(&EpochState::Active, long, None, staleness));
+        assert!(is_stale(&EpochState::Active, long, Some(long), staleness));
+        assert!(!is_stale(&EpochState::Active, long, Some(short), staleness));
+        // Grace period at the start of the active phase.
+        assert!(!is_stale(&EpochState::Active, short, None, staleness));
+        // Only the active phase requires progress.
+        assert!(!is_stale(&EpochState::ReportWork, long, None, staleness));
+        assert!(!is_stale(
+            &EpochState::Registration,
+            long,
+            Some(long),
+            staleness
+        ));
+    }
+}
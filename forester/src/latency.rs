@@ -0,0 +1,73 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Number of most recent samples percentiles are computed over.
+const MAX_SAMPLES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+/// Records transaction landing latencies, i.e. the time from just before a
+/// transaction is sent until it is confirmed. Only the most recent
+/// `MAX_SAMPLES` samples are kept so that percentiles follow current
+/// network conditions.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyRecorder {
+    samples: VecDeque<Duration>,
+}
+
+impl LatencyRecorder {
+    pub fn record(&mut self, latency: Duration) {
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+    }
+
+    /// Returns `None` until the first sample is recorded.
+    pub fn percentiles(&self) -> Option<LatencyPercentiles> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        let percentile = |p: usize| sorted[(sorted.len() * p).div_ceil(100).max(1) - 1];
+        Some(LatencyPercentiles {
+            p50: percentile(50),
+            p95: percentile(95),
+            p99: percentile(99),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_latency_percentiles() {
+        let mut recorder = LatencyRecorder::default();
+        assert_eq!(recorder.percentiles(), None);
+
+        for ms in (1..=100).rev() {
+            recorder.record(Duration::from_millis(ms));
+        }
+        let percentiles = recorder.percentiles().unwrap();
+        assert_eq!(percentiles.p50, Duration::from_millis(50));
+        assert_eq!(percentiles.p95, Duration::from_millis(95));
+        assert_eq!(percentiles.p99, Duration::from_millis(99));
+
+        // Old samples are dropped once the window is full.
+        for _ in 0..MAX_SAMPLES {
+            recorder.record(Duration::from_millis(7));
+        }
+        assert_eq!(
+            recorder.percentiles().unwrap().p99,
+            Duration::from_millis(7)
+        );
+    }
+}
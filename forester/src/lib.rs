@@ -2,24 +2,42 @@ pub type Result<T> = std::result::Result<T, ForesterError>;
 
 pub mod cli;
 pub mod config;
+pub mod dead_letter;
 pub mod epoch_manager;
 pub mod errors;
+pub mod health;
+pub mod latency;
+pub mod metrics;
 pub mod photon_indexer;
 pub mod pubsub_client;
 pub mod queue_helpers;
+pub mod rate_limiter;
+pub mod registry;
 pub mod rollover;
 pub mod rpc_pool;
 pub mod settings;
+pub mod signer;
 mod slot_tracker;
+#[cfg(test)]
+mod test_utils;
 pub mod tree_data_sync;
+pub mod tree_helpers;
+pub mod tx_observer;
 pub mod utils;
+pub mod ws_indexer;
 
-use crate::epoch_manager::{run_service, WorkReport};
+use crate::dead_letter::DeadLetterStore;
+use crate::epoch_manager::{run_service, ControlCommand, EpochManager, ForesterEvent, WorkReport};
 use crate::errors::ForesterError;
+use crate::health::HealthState;
+use crate::metrics::register_metrics;
 use crate::queue_helpers::fetch_queue_item_data;
 use crate::rpc_pool::SolanaRpcPool;
+use crate::signer::create_signer;
 use crate::slot_tracker::SlotTracker;
+use crate::tree_data_sync::fetch_trees;
 use crate::utils::get_protocol_config;
+use arc_swap::ArcSwap;
 pub use config::{ForesterConfig, ForesterEpochInfo};
 use env_logger::Env;
 use light_test_utils::forester_epoch::{TreeAccounts, TreeType};
@@ -30,14 +48,26 @@ use log::info;
 pub use settings::init_config;
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::native_token::LAMPORTS_PER_SOL;
-use solana_sdk::signature::Signer;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::{mpsc, oneshot, RwLock};
 
+/// Initializes logging. Set `FORESTER_TRACING=true` to use a `tracing`
+/// subscriber, which prints span fields such as epoch and tree with every
+/// line; otherwise `env_logger` is used.
 pub fn setup_logger() {
-    let env = Env::new().filter_or("RUST_LOG", "info,forester=debug");
-    env_logger::Builder::from_env(env).init();
+    const DEFAULT_FILTER: &str = "info,forester=debug";
+    let tracing_enabled = std::env::var("FORESTER_TRACING")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false);
+    if tracing_enabled {
+        let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(DEFAULT_FILTER));
+        tracing_subscriber::fmt().with_env_filter(filter).init();
+    } else {
+        let env = Env::new().filter_or("RUST_LOG", DEFAULT_FILTER);
+        env_logger::Builder::from_env(env).init();
+    }
 }
 
 pub async fn run_queue_info(
@@ -66,31 +96,60 @@ pub async fn run_queue_info(
 
 pub async fn run_pipeline<R: RpcConnection, I: Indexer<R>>(
     config: Arc<ForesterConfig>,
-    indexer: Arc<Mutex<I>>,
+    indexer: Arc<RwLock<I>>,
+    shutdown: oneshot::Receiver<()>,
+    work_report_sender: mpsc::Sender<WorkReport>,
+) -> Result<()> {
+    run_pipeline_with_events::<R, I>(config, indexer, shutdown, work_report_sender, None, None)
+        .await
+}
+
+/// Same as [`run_pipeline`], additionally sending lifecycle events to
+/// `event_sender` and accepting operator commands from `control_receiver`
+/// when given.
+pub async fn run_pipeline_with_events<R: RpcConnection, I: Indexer<R>>(
+    config: Arc<ForesterConfig>,
+    indexer: Arc<RwLock<I>>,
     shutdown: oneshot::Receiver<()>,
     work_report_sender: mpsc::Sender<WorkReport>,
+    event_sender: Option<mpsc::Sender<ForesterEvent>>,
+    control_receiver: Option<mpsc::Receiver<ControlCommand>>,
 ) -> Result<()> {
-    let rpc_pool = SolanaRpcPool::<R>::new(
-        config.external_services.rpc_url.to_string(),
+    register_metrics();
+
+    let rpc_pool = SolanaRpcPool::<R>::new_with_endpoints(
+        config.external_services.rpc_urls(),
         CommitmentConfig::confirmed(),
         config.rpc_pool_size as u32,
+        Duration::from_millis(config.rpc_max_latency_ms),
+        Duration::from_secs(config.rpc_endpoint_cooldown_seconds),
+        Duration::from_millis(config.rpc_pool_acquire_timeout_ms),
     )
-    .await
-    .map_err(|e| ForesterError::Custom(e.to_string()))?;
+    .await?;
 
+    let registry = config.registry();
     {
         let mut rpc = rpc_pool.get_connection().await?;
-        rpc.airdrop_lamports(&config.payer_keypair.pubkey(), LAMPORTS_PER_SOL * 100_000)
+        registry.validate(&mut *rpc).await?;
+    }
+
+    let signer = create_signer(&config).await?;
+    if !config.observer && !config.dry_run {
+        let mut rpc = rpc_pool.get_connection().await?;
+        rpc.airdrop_lamports(&signer.pubkey(), LAMPORTS_PER_SOL * 100_000)
             .await?;
     }
 
     let protocol_config = {
         let mut rpc = rpc_pool.get_connection().await?;
-        get_protocol_config(&mut *rpc).await
+        get_protocol_config(&mut *rpc, &registry).await
     };
 
     let arc_pool = Arc::new(rpc_pool);
     let arc_pool_clone = Arc::clone(&arc_pool);
+    Arc::clone(&arc_pool).spawn_health_check(Duration::from_secs(
+        config.rpc_health_check_interval_seconds,
+    ));
 
     let slot = {
         let mut rpc = arc_pool.get_connection().await?;
@@ -102,13 +161,7 @@ pub async fn run_pipeline<R: RpcConnection, I: Indexer<R>>(
     );
     let arc_slot_tracker = Arc::new(slot_tracker);
     let arc_slot_tracker_clone = arc_slot_tracker.clone();
-    tokio::spawn(async move {
-        let mut rpc = arc_pool_clone
-            .get_connection()
-            .await
-            .expect("Failed to get RPC connection");
-        SlotTracker::run(arc_slot_tracker_clone, &mut *rpc).await;
-    });
+    tokio::spawn(SlotTracker::run(arc_slot_tracker_clone, arc_pool_clone));
 
     info!("Starting Forester pipeline");
     run_service(
@@ -116,10 +169,72 @@ pub async fn run_pipeline<R: RpcConnection, I: Indexer<R>>(
         Arc::new(protocol_config),
         arc_pool,
         indexer,
+        signer,
         shutdown,
         work_report_sender,
+        event_sender,
+        control_receiver,
         arc_slot_tracker,
     )
     .await?;
     Ok(())
 }
+
+/// Re-submits the items recorded in the dead-letter store at `path`, see
+/// [`EpochManager::replay_dead_letters`].
+pub async fn run_replay_dead_letters<R: RpcConnection, I: Indexer<R>>(
+    config: Arc<ForesterConfig>,
+    indexer: Arc<RwLock<I>>,
+    path: &str,
+) -> Result<()> {
+    let rpc_pool = Arc::new(
+        SolanaRpcPool::<R>::new_with_endpoints(
+            config.external_services.rpc_urls(),
+            CommitmentConfig::confirmed(),
+            config.rpc_pool_size as u32,
+            Duration::from_millis(config.rpc_max_latency_ms),
+            Duration::from_secs(config.rpc_endpoint_cooldown_seconds),
+            Duration::from_millis(config.rpc_pool_acquire_timeout_ms),
+        )
+        .await?,
+    );
+    let signer = create_signer(&config).await?;
+    let (protocol_config, slot, trees) = {
+        let mut rpc = rpc_pool.get_connection().await?;
+        let protocol_config = get_protocol_config(&mut *rpc, &config.registry()).await;
+        let slot = rpc.get_slot().await?;
+        (protocol_config, slot, fetch_trees(&*rpc).await)
+    };
+    let slot_tracker = Arc::new(SlotTracker::new(
+        slot,
+        Duration::from_secs(config.slot_update_interval_seconds),
+    ));
+    tokio::spawn(SlotTracker::run(slot_tracker.clone(), rpc_pool.clone()));
+
+    // Items failing again are kept in the store by the replay, they must
+    // not be recorded a second time.
+    let mut replay_config = (*config).clone();
+    replay_config.dead_letter_path = None;
+    let (work_report_sender, _) = mpsc::channel(1);
+    let epoch_manager = EpochManager::new(
+        Arc::new(replay_config),
+        Arc::new(ArcSwap::from_pointee(protocol_config)),
+        rpc_pool,
+        indexer,
+        signer,
+        work_report_sender,
+        None,
+        trees,
+        slot_tracker,
+        Arc::new(HealthState::default()),
+    )
+    .await?;
+    let (replayed, remaining) = epoch_manager
+        .replay_dead_letters(&DeadLetterStore::new(path))
+        .await?;
+    info!(
+        "Replayed {} dead letters, {} still pending",
+        replayed, remaining
+    );
+    Ok(())
+}
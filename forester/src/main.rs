@@ -1,15 +1,28 @@
 use clap::Parser;
 use forester::cli::{Cli, Commands};
+use forester::config::simulate_schedule;
+use forester::epoch_manager::{fetch_forester_epoch_info, ControlCommand};
 use forester::errors::ForesterError;
 use forester::photon_indexer::PhotonIndexer;
+use forester::rollover::{
+    is_tree_ready_for_rollover, rollover_address_merkle_tree, rollover_state_merkle_tree,
+    tree_type_of,
+};
+use forester::signer::create_signer;
 use forester::tree_data_sync::fetch_trees;
-use forester::{init_config, run_pipeline, run_queue_info, setup_logger, ForesterConfig};
+use forester::utils::get_protocol_config;
+use forester::ws_indexer::WsPhotonIndexer;
+use forester::{
+    init_config, run_pipeline_with_events, run_queue_info, run_replay_dead_letters, setup_logger,
+    ForesterConfig,
+};
 use light_test_utils::forester_epoch::TreeType;
 pub use light_test_utils::rpc::rpc_connection::RpcConnection;
 use light_test_utils::rpc::SolanaRpcConnection;
 use log::{debug, info, warn};
 use std::sync::Arc;
 use tokio::signal::ctrl_c;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::{mpsc, oneshot};
 
 #[tokio::main]
@@ -35,15 +48,46 @@ async fn main() -> Result<(), ForesterError> {
                     debug!("Work Report: {:?}", report);
                 }
             });
-            let indexer_rpc =
-                SolanaRpcConnection::new(config.external_services.rpc_url.to_string(), None);
-            let indexer = Arc::new(tokio::sync::Mutex::new(PhotonIndexer::new(
-                config.external_services.indexer_url.to_string(),
-                config.external_services.photon_api_key.clone(),
-                indexer_rpc,
-            )));
 
-            run_pipeline(config, indexer, shutdown_receiver, work_report_sender).await?
+            let (control_sender, control_receiver) = mpsc::channel(10);
+            tokio::spawn(forward_control_signals(control_sender));
+
+            match config.external_services.indexer_ws_url.clone() {
+                Some(indexer_ws_url) => {
+                    let indexer: WsPhotonIndexer<SolanaRpcConnection> =
+                        WsPhotonIndexer::new(indexer_ws_url);
+                    let indexer = Arc::new(tokio::sync::RwLock::new(indexer));
+                    run_pipeline_with_events(
+                        config,
+                        indexer,
+                        shutdown_receiver,
+                        work_report_sender,
+                        None,
+                        Some(control_receiver),
+                    )
+                    .await?
+                }
+                None => {
+                    let indexer_rpc = SolanaRpcConnection::new(
+                        config.external_services.rpc_url.to_string(),
+                        None,
+                    );
+                    let indexer = Arc::new(tokio::sync::RwLock::new(PhotonIndexer::new(
+                        config.external_services.indexer_url.to_string(),
+                        config.external_services.photon_api_key.clone(),
+                        indexer_rpc,
+                    )));
+                    run_pipeline_with_events(
+                        config,
+                        indexer,
+                        shutdown_receiver,
+                        work_report_sender,
+                        None,
+                        Some(control_receiver),
+                    )
+                    .await?
+                }
+            }
         }
         Some(Commands::Status) => {
             info!("Fetching trees...");
@@ -55,7 +99,123 @@ async fn main() -> Result<(), ForesterError> {
             run_queue_info(config.clone(), trees.clone(), TreeType::State).await;
             run_queue_info(config.clone(), trees.clone(), TreeType::Address).await;
         }
+        Some(Commands::Rollover { tree, tree_type }) => {
+            let mut rpc =
+                SolanaRpcConnection::new(config.external_services.rpc_url.to_string(), None);
+            let tree_type = match tree_type {
+                Some(tree_type) => TreeType::from(*tree_type),
+                None => tree_type_of(&mut rpc, *tree).await?,
+            };
+            let tree_accounts = fetch_trees(&rpc)
+                .await
+                .into_iter()
+                .find(|t| t.merkle_tree == *tree && t.tree_type == tree_type)
+                .ok_or_else(|| {
+                    ForesterError::Custom(format!("{:?} tree {} not found", tree_type, tree))
+                })?;
+            if !is_tree_ready_for_rollover(&mut rpc, *tree, tree_type).await? {
+                warn!("{:?} tree {} is not ready for rollover", tree_type, tree);
+                return Ok(());
+            }
+
+            let indexer_rpc =
+                SolanaRpcConnection::new(config.external_services.rpc_url.to_string(), None);
+            let indexer = Arc::new(tokio::sync::RwLock::new(PhotonIndexer::new(
+                config.external_services.indexer_url.to_string(),
+                config.external_services.photon_api_key.clone(),
+                indexer_rpc,
+            )));
+            let signer = create_signer(&config).await?;
+            let new_tree_accounts = match tree_type {
+                TreeType::State => {
+                    let protocol_config = get_protocol_config(&mut rpc, &config.registry()).await;
+                    rollover_state_merkle_tree(
+                        config.clone(),
+                        &mut rpc,
+                        indexer,
+                        &*signer,
+                        &protocol_config,
+                        &tree_accounts,
+                    )
+                    .await?
+                }
+                TreeType::Address => {
+                    rollover_address_merkle_tree(
+                        config.clone(),
+                        &mut rpc,
+                        indexer,
+                        &*signer,
+                        &tree_accounts,
+                    )
+                    .await?
+                }
+            };
+            info!(
+                "Rolled over {:?} tree {}. New tree: {}, new queue: {}",
+                tree_type, tree, new_tree_accounts.merkle_tree, new_tree_accounts.queue
+            );
+        }
+        Some(Commands::Schedule { epoch }) => {
+            let mut rpc =
+                SolanaRpcConnection::new(config.external_services.rpc_url.to_string(), None);
+            let registry = config.registry();
+            let protocol_config = get_protocol_config(&mut rpc, &registry).await;
+            let epoch = match epoch {
+                Some(epoch) => *epoch,
+                None => protocol_config.get_current_epoch(rpc.get_slot().await?),
+            };
+            let forester = create_signer(&config).await?.pubkey();
+            let trees = fetch_trees(&rpc).await;
+            let epoch_info = fetch_forester_epoch_info(
+                &mut rpc,
+                &registry,
+                &protocol_config,
+                &forester,
+                epoch,
+                &trees,
+            )
+            .await?;
+            for (tree, light_slots) in simulate_schedule(&epoch_info, &forester) {
+                info!(
+                    "Epoch {} tree {}: {} eligible light slots {:?}",
+                    epoch,
+                    tree,
+                    light_slots.len(),
+                    light_slots
+                );
+            }
+        }
+        Some(Commands::ReplayDeadLetters) => {
+            let path = config.dead_letter_path.clone().ok_or_else(|| {
+                ForesterError::Custom("DEAD_LETTER_PATH is not configured".to_string())
+            })?;
+            let indexer_rpc =
+                SolanaRpcConnection::new(config.external_services.rpc_url.to_string(), None);
+            let indexer = Arc::new(tokio::sync::RwLock::new(PhotonIndexer::new(
+                config.external_services.indexer_url.to_string(),
+                config.external_services.photon_api_key.clone(),
+                indexer_rpc,
+            )));
+            run_replay_dead_letters::<SolanaRpcConnection, _>(config.clone(), indexer, &path)
+                .await?;
+        }
         None => {}
     }
     Ok(())
 }
+
+/// Pauses the forester on SIGUSR1 and resumes it on SIGUSR2, e.g. for
+/// maintenance without restarting the process.
+async fn forward_control_signals(control_sender: mpsc::Sender<ControlCommand>) {
+    let mut pause = signal(SignalKind::user_defined1()).expect("Failed to listen for SIGUSR1");
+    let mut resume = signal(SignalKind::user_defined2()).expect("Failed to listen for SIGUSR2");
+    loop {
+        let command = tokio::select! {
+            _ = pause.recv() => ControlCommand::Pause,
+            _ = resume.recv() => ControlCommand::Resume,
+        };
+        if control_sender.send(command).await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use crate::health::HealthCheck;
+use crate::latency::LatencyPercentiles;
+use crate::queue_helpers::get_queue_fill_ratio;
+use crate::rpc_pool::SolanaRpcPool;
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use lazy_static::lazy_static;
+use light_test_utils::rpc::rpc_connection::RpcConnection;
+use log::{error, info, warn};
+use prometheus::{
+    Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use solana_sdk::pubkey::Pubkey;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Once};
+use std::time::Duration;
+use tokio::time::sleep;
+
+lazy_static! {
+    pub static ref REGISTRY: Registry = Registry::new();
+    pub static ref STALE_QUEUE_ITEMS_SKIPPED: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "forester_stale_queue_items_skipped",
+            "Queue items skipped because they exceeded the maximum age"
+        ),
+        &["tree"]
+    )
+    .expect("metric can be created");
+    pub static ref SLOT_TRACKER_DRIFT: IntGauge = IntGauge::new(
+        "forester_slot_tracker_drift",
+        "Difference in slots between the estimated and the actual slot at the last reconciliation"
+    )
+    .expect("metric can be created");
+    pub static ref QUEUE_FILL_RATIO: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "forester_queue_fill_ratio",
+            "Share of the queue capacity taken by unprocessed items"
+        ),
+        &["queue"]
+    )
+    .expect("metric can be created");
+    pub static ref RPC_POOL_WAIT: Histogram = Histogram::with_opts(HistogramOpts::new(
+        "forester_rpc_pool_wait_seconds",
+        "Time spent waiting for a pooled RPC connection"
+    ))
+    .expect("metric can be created");
+    pub static ref RPC_POOL_EXHAUSTED: IntCounter = IntCounter::new(
+        "forester_rpc_pool_exhausted",
+        "Requests for a pooled RPC connection which timed out because all connections were in use"
+    )
+    .expect("metric can be created");
+    pub static ref TRANSACTIONS: IntCounterVec = IntCounterVec::new(
+        Opts::new(
+            "forester_transactions",
+            "Transaction batches sent, by result: success or the category of the failure"
+        ),
+        &["result"]
+    )
+    .expect("metric can be created");
+    pub static ref PAYER_BALANCE: IntGauge = IntGauge::new(
+        "forester_payer_balance_lamports",
+        "Balance of the forester payer, only updated if min_payer_balance is set"
+    )
+    .expect("metric can be created");
+    pub static ref TRANSACTION_LANDING_LATENCY: GaugeVec = GaugeVec::new(
+        Opts::new(
+            "forester_transaction_landing_latency_seconds",
+            "Percentiles of the time from sending a transaction until it is confirmed"
+        ),
+        &["quantile"]
+    )
+    .expect("metric can be created");
+}
+
+static INIT: Once = Once::new();
+
+pub fn register_metrics() {
+    INIT.call_once(|| {
+        REGISTRY
+            .register(Box::new(STALE_QUEUE_ITEMS_SKIPPED.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(SLOT_TRACKER_DRIFT.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(QUEUE_FILL_RATIO.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(TRANSACTION_LANDING_LATENCY.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(TRANSACTIONS.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(RPC_POOL_WAIT.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(RPC_POOL_EXHAUSTED.clone()))
+            .expect("collector can be registered");
+        REGISTRY
+            .register(Box::new(PAYER_BALANCE.clone()))
+            .expect("collector can be registered");
+    });
+}
+
+pub fn increment_stale_queue_items_skipped(tree: &Pubkey, count: usize) {
+    STALE_QUEUE_ITEMS_SKIPPED
+        .with_label_values(&[&tree.to_string()])
+        .inc_by(count as u64);
+}
+
+pub fn set_slot_tracker_drift(drift: i64) {
+    SLOT_TRACKER_DRIFT.set(drift);
+}
+
+pub fn set_queue_fill_ratio(queue: &Pubkey, fill_ratio: f64) {
+    QUEUE_FILL_RATIO
+        .with_label_values(&[&queue.to_string()])
+        .set(fill_ratio);
+}
+
+pub fn observe_rpc_pool_wait(wait: Duration) {
+    RPC_POOL_WAIT.observe(wait.as_secs_f64());
+}
+
+pub fn increment_rpc_pool_exhausted() {
+    RPC_POOL_EXHAUSTED.inc();
+}
+
+pub fn set_payer_balance(lamports: u64) {
+    PAYER_BALANCE.set(lamports as i64);
+}
+
+pub fn increment_transactions(result: &str) {
+    TRANSACTIONS.with_label_values(&[result]).inc();
+}
+
+pub fn set_transaction_landing_latency(percentiles: &LatencyPercentiles) {
+    for (quantile, latency) in [
+        ("0.5", percentiles.p50),
+        ("0.95", percentiles.p95),
+        ("0.99", percentiles.p99),
+    ] {
+        TRANSACTION_LANDING_LATENCY
+            .with_label_values(&[quantile])
+            .set(latency.as_secs_f64());
+    }
+}
+
+/// Updates the fill ratio of every queue in `queues` each `interval`.
+pub async fn run_queue_fill_ratio_emitter<R: RpcConnection>(
+    rpc_pool: Arc<SolanaRpcPool<R>>,
+    queues: Vec<Pubkey>,
+    interval: Duration,
+) {
+    loop {
+        match rpc_pool.get_connection().await {
+            Ok(mut rpc) => {
+                for queue in queues.iter() {
+                    match get_queue_fill_ratio(&mut *rpc, queue).await {
+                        Ok(fill_ratio) => set_queue_fill_ratio(queue, fill_ratio),
+                        Err(e) => warn!("Failed to fetch fill ratio of queue {}: {:?}", queue, e),
+                    }
+                }
+            }
+            Err(e) => warn!("Failed to get RPC connection for queue metrics: {:?}", e),
+        }
+        sleep(interval).await;
+    }
+}
+
+/// Serves `/metrics` in the Prometheus text format, `/health`, which
+/// responds with 503 while `health_check` reports the forester unhealthy,
+/// `/schedule` and `/status`, which returns the startup summary.
+pub async fn run_metrics_server(port: u16, health_check: Arc<HealthCheck>) {
+    let address = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_| {
+        let health_check = health_check.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let health_check = health_check.clone();
+                async move { Ok::<_, Infallible>(handle_request(request, &health_check)) }
+            }))
+        }
+    });
+    let server = match Server::try_bind(&address) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!("Failed to bind metrics server to {}: {:?}", address, e);
+            return;
+        }
+    };
+    info!("Serving metrics and health on {}", address);
+    if let Err(e) = server.await {
+        error!("Metrics server error: {:?}", e);
+    }
+}
+
+fn handle_request(request: Request<Body>, health_check: &HealthCheck) -> Response<Body> {
+    match request.uri().path() {
+        "/metrics" => {
+            let encoder = TextEncoder::new();
+            let mut buffer = Vec::new();
+            if let Err(e) = encoder.encode(&REGISTRY.gather(), &mut buffer) {
+                return response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "text/plain",
+                    e.to_string(),
+                );
+            }
+            response(StatusCode::OK, encoder.format_type(), buffer)
+        }
+        "/health" => {
+            let report = health_check.report();
+            let status = if report.healthy {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            let body = serde_json::to_string(&report).unwrap_or_default();
+            response(status, "application/json", body)
+        }
+        "/schedule" => match health_check.schedule() {
+            Some(schedule) => {
+                let body = serde_json::to_string(&schedule).unwrap_or_default();
+                response(StatusCode::OK, "application/json", body)
+            }
+            None => response(StatusCode::NOT_FOUND, "text/plain", "No schedule yet"),
+        },
+        "/status" => match health_check.startup_summary() {
+            Some(summary) => {
+                let body = serde_json::to_string(&summary).unwrap_or_default();
+                response(StatusCode::OK, "application/json", body)
+            }
+            None => response(StatusCode::NOT_FOUND, "text/plain", "Not started yet"),
+        },
+        _ => response(StatusCode::NOT_FOUND, "text/plain", "Not found"),
+    }
+}
+
+fn response(status: StatusCode, content_type: &str, body: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    if let Ok(content_type) = content_type.parse() {
+        response.headers_mut().insert(CONTENT_TYPE, content_type);
+    }
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::health::HealthState;
+    use crate::slot_tracker::SlotTracker;
+    use crate::test_utils::test_config;
+    use arc_swap::ArcSwap;
+    use light_registry::protocol_config::state::ProtocolConfig;
+
+    #[test]
+    fn test_status_serves_startup_summary() {
+        let state = Arc::new(HealthState::default());
+        let health_check = HealthCheck::new(
+            state.clone(),
+            Arc::new(SlotTracker::new(0, Duration::from_secs(1))),
+            Arc::new(ArcSwap::from_pointee(ProtocolConfig::default())),
+            Duration::from_secs(60),
+        );
+        let status = || {
+            handle_request(
+                Request::get("/status").body(Body::empty()).unwrap(),
+                &health_check,
+            )
+        };
+        assert_eq!(status().status(), StatusCode::NOT_FOUND);
+
+        let summary = test_config().startup_summary(&[]);
+        state.record_startup_summary(summary.clone());
+        let response = status();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body =
+            futures::executor::block_on(hyper::body::to_bytes(response.into_body())).unwrap();
+        assert_eq!(body, serde_json::to_vec(&summary).unwrap());
+    }
+}
@@ -4,7 +4,10 @@ use light_test_utils::indexer::{Indexer, IndexerError, MerkleProof, NewAddressPr
 use light_test_utils::rpc::rpc_connection::RpcConnection;
 use log::{debug, info};
 use photon_api::apis::configuration::{ApiKey, Configuration};
-use photon_api::models::GetCompressedAccountsByOwnerPostRequestParams;
+use photon_api::models::{
+    GetCompressedAccountsByOwnerPostRequestParams, MerkleContextWithNewAddressProof,
+    MerkleProofWithContext,
+};
 use solana_sdk::bs58;
 use std::fmt::Debug;
 
@@ -55,35 +58,13 @@ impl<R: RpcConnection> Indexer<R> for PhotonIndexer<R> {
         .await;
 
         match result {
-            Ok(response) => {
-                match response.result {
-                    Some(result) => {
-                        let proofs = result
-                            .value
-                            .iter()
-                            .map(|x| {
-                                let mut proof_result_value = x.proof.clone();
-                                proof_result_value.truncate(proof_result_value.len() - 10); // Remove canopy
-                                let proof: Vec<[u8; 32]> =
-                                    proof_result_value.iter().map(|x| decode_hash(x)).collect();
-                                MerkleProof {
-                                    hash: x.hash.clone(),
-                                    leaf_index: x.leaf_index,
-                                    merkle_tree: x.merkle_tree.clone(),
-                                    proof,
-                                    root_seq: x.root_seq,
-                                }
-                            })
-                            .collect();
-
-                        Ok(proofs)
-                    }
-                    None => {
-                        let error = response.error.unwrap();
-                        Err(IndexerError::Custom(error.message.unwrap()))
-                    }
+            Ok(response) => match response.result {
+                Some(result) => Ok(merkle_proofs_from_photon(&result.value)),
+                None => {
+                    let error = response.error.unwrap();
+                    Err(IndexerError::Custom(error.message.unwrap()))
                 }
-            }
+            },
             Err(e) => Err(IndexerError::Custom(e.to_string())),
         }
     }
@@ -145,37 +126,60 @@ impl<R: RpcConnection> Indexer<R> for PhotonIndexer<R> {
         }
 
         let photon_proofs = result.unwrap().result.unwrap().value;
-        let mut proofs: Vec<NewAddressProofWithContext> = Vec::new();
-        for photon_proof in photon_proofs {
-            let tree_pubkey = decode_hash(&photon_proof.merkle_tree);
-            let low_address_value = decode_hash(&photon_proof.lower_range_address);
-            let next_address_value = decode_hash(&photon_proof.higher_range_address);
-            let proof = NewAddressProofWithContext {
-                merkle_tree: tree_pubkey,
-                low_address_index: photon_proof.low_element_leaf_index as u64,
-                low_address_value,
-                low_address_next_index: photon_proof.next_index as u64,
-                low_address_next_value: next_address_value,
-                low_address_proof: {
-                    let mut proof_vec: Vec<[u8; 32]> = photon_proof
-                        .proof
-                        .iter()
-                        .map(|x: &String| decode_hash(x))
-                        .collect();
-                    proof_vec.truncate(proof_vec.len() - 10); // Remove canopy
-                    let mut proof_arr = [[0u8; 32]; 16];
-                    proof_arr.copy_from_slice(&proof_vec);
-                    proof_arr
-                },
-                root: decode_hash(&photon_proof.root),
-                root_seq: photon_proof.root_seq,
-                new_low_element: None,
-                new_element: None,
-                new_element_next_value: None,
-            };
-            proofs.push(proof);
-        }
+        Ok(new_address_proofs_from_photon(&photon_proofs))
+    }
+}
 
-        Ok(proofs)
+pub(crate) fn merkle_proofs_from_photon(proofs: &[MerkleProofWithContext]) -> Vec<MerkleProof> {
+    proofs
+        .iter()
+        .map(|x| {
+            let mut proof_result_value = x.proof.clone();
+            proof_result_value.truncate(proof_result_value.len() - 10); // Remove canopy
+            let proof: Vec<[u8; 32]> = proof_result_value.iter().map(|x| decode_hash(x)).collect();
+            MerkleProof {
+                hash: x.hash.clone(),
+                leaf_index: x.leaf_index,
+                merkle_tree: x.merkle_tree.clone(),
+                proof,
+                root_seq: x.root_seq,
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn new_address_proofs_from_photon(
+    photon_proofs: &[MerkleContextWithNewAddressProof],
+) -> Vec<NewAddressProofWithContext> {
+    let mut proofs: Vec<NewAddressProofWithContext> = Vec::new();
+    for photon_proof in photon_proofs {
+        let tree_pubkey = decode_hash(&photon_proof.merkle_tree);
+        let low_address_value = decode_hash(&photon_proof.lower_range_address);
+        let next_address_value = decode_hash(&photon_proof.higher_range_address);
+        let proof = NewAddressProofWithContext {
+            merkle_tree: tree_pubkey,
+            low_address_index: photon_proof.low_element_leaf_index as u64,
+            low_address_value,
+            low_address_next_index: photon_proof.next_index as u64,
+            low_address_next_value: next_address_value,
+            low_address_proof: {
+                let mut proof_vec: Vec<[u8; 32]> = photon_proof
+                    .proof
+                    .iter()
+                    .map(|x: &String| decode_hash(x))
+                    .collect();
+                proof_vec.truncate(proof_vec.len() - 10); // Remove canopy
+                let mut proof_arr = [[0u8; 32]; 16];
+                proof_arr.copy_from_slice(&proof_vec);
+                proof_arr
+            },
+            root: decode_hash(&photon_proof.root),
+            root_seq: photon_proof.root_seq,
+            new_low_element: None,
+            new_element: None,
+            new_element_next_value: None,
+        };
+        proofs.push(proof);
     }
+    proofs
 }
@@ -1,44 +1,180 @@
 use crate::errors::ForesterError;
 use account_compression::initialize_address_merkle_tree::Pubkey;
 use account_compression::QueueAccount;
-use light_hash_set::HashSet;
+use light_hash_set::{HashSet, HashSetError};
 use light_test_utils::rpc::rpc_connection::RpcConnection;
 use log::debug;
+use solana_sdk::account::Account;
+use std::collections::HashMap;
 use std::mem;
 
 #[derive(Debug, Clone)]
 pub struct QueueItemData {
     pub hash: [u8; 32],
     pub index: usize,
+    /// Slot at which the item entered the queue, if known. Queue accounts
+    /// don't store it, so it is set to the slot the item was first observed.
+    pub insertion_slot: Option<u64>,
+}
+
+impl QueueItemData {
+    pub fn is_stale(&self, current_slot: u64, max_age_slots: u64) -> bool {
+        self.insertion_slot
+            .is_some_and(|slot| current_slot.saturating_sub(slot) > max_age_slots)
+    }
+}
+
+/// Sorts items by the slot they were first observed at, oldest first, and by
+/// queue index within a slot. Queue cells don't record when they were
+/// inserted, so items which entered the queue between two fetches can't be
+/// ordered relative to each other.
+pub fn sort_by_insertion_order(items: &mut [QueueItemData]) {
+    items.sort_by_key(|item| (item.insertion_slot.unwrap_or(u64::MAX), item.index));
+}
+
+/// Remembers the slot at which each queue item was first observed.
+#[derive(Debug, Default)]
+pub struct QueueItemAgeTracker {
+    first_seen: HashMap<Pubkey, HashMap<[u8; 32], u64>>,
+}
+
+impl QueueItemAgeTracker {
+    /// Sets `insertion_slot` of every item to the slot it was first observed
+    /// at, and forgets items which are no longer in the queue.
+    pub fn stamp(&mut self, queue_pubkey: &Pubkey, items: &mut [QueueItemData], current_slot: u64) {
+        let previous = self.first_seen.remove(queue_pubkey).unwrap_or_default();
+        let mut first_seen = HashMap::with_capacity(items.len());
+        for item in items.iter_mut() {
+            let slot = previous.get(&item.hash).copied().unwrap_or(current_slot);
+            item.insertion_slot = Some(slot);
+            first_seen.insert(item.hash, slot);
+        }
+        self.first_seen.insert(*queue_pubkey, first_seen);
+    }
+
+    /// Restarts the age of items which have already been skipped as stale
+    /// for `max_age_slots`, so that an item isn't skipped for as long as it
+    /// stays in the queue, e.g. because the forester missed it while it was
+    /// fresh. Returns the number of re-admitted items.
+    pub fn readmit_after_cooldown(
+        &mut self,
+        queue_pubkey: &Pubkey,
+        items: &mut [QueueItemData],
+        current_slot: u64,
+        max_age_slots: u64,
+    ) -> usize {
+        let Some(first_seen) = self.first_seen.get_mut(queue_pubkey) else {
+            return 0;
+        };
+        let mut readmitted = 0;
+        for item in items.iter_mut() {
+            if item.is_stale(current_slot, max_age_slots.saturating_mul(2)) {
+                item.insertion_slot = Some(current_slot);
+                first_seen.insert(item.hash, current_slot);
+                readmitted += 1;
+            }
+        }
+        readmitted
+    }
+}
+
+/// Unprocessed items of a queue together with the queue capacity.
+#[derive(Debug, Clone)]
+pub struct QueueData {
+    pub items: Vec<QueueItemData>,
+    pub capacity: usize,
+}
+
+impl QueueData {
+    /// Share of the queue capacity taken by unprocessed items.
+    pub fn fill_ratio(&self) -> f64 {
+        if self.capacity == 0 {
+            return 0.0;
+        }
+        self.items.len() as f64 / self.capacity as f64
+    }
 }
 
 pub async fn fetch_queue_item_data<R: RpcConnection>(
     rpc: &mut R,
     queue_pubkey: &Pubkey,
 ) -> crate::Result<Vec<QueueItemData>> {
+    Ok(fetch_queue_data(rpc, queue_pubkey).await?.items)
+}
+
+/// Share of the queue capacity taken by unprocessed items, between 0.0 and
+/// 1.0. The queue overflows once it reaches 1.0.
+pub async fn get_queue_fill_ratio<R: RpcConnection>(
+    rpc: &mut R,
+    queue_pubkey: &Pubkey,
+) -> crate::Result<f64> {
+    Ok(fetch_queue_data(rpc, queue_pubkey).await?.fill_ratio())
+}
+
+pub async fn fetch_queue_data<R: RpcConnection>(
+    rpc: &mut R,
+    queue_pubkey: &Pubkey,
+) -> crate::Result<QueueData> {
     debug!("Fetching queue data for {:?}", queue_pubkey);
-    let mut account = rpc
+    let account = rpc
         .get_account(*queue_pubkey)
         .await?
         .ok_or_else(|| ForesterError::Custom("Queue account not found".to_string()))?;
+    Ok(parse_queue_data(account)?)
+}
 
+/// Fetches all queue accounts with a single `getMultipleAccounts` request
+/// (chunked by the RPC connection if there are many queues).
+pub async fn fetch_queue_item_data_multi<R: RpcConnection>(
+    rpc: &mut R,
+    queue_pubkeys: &[Pubkey],
+) -> crate::Result<HashMap<Pubkey, Vec<QueueItemData>>> {
+    Ok(fetch_queue_data_multi(rpc, queue_pubkeys)
+        .await?
+        .into_iter()
+        .map(|(queue_pubkey, queue_data)| (queue_pubkey, queue_data.items))
+        .collect())
+}
+
+pub async fn fetch_queue_data_multi<R: RpcConnection>(
+    rpc: &mut R,
+    queue_pubkeys: &[Pubkey],
+) -> crate::Result<HashMap<Pubkey, QueueData>> {
+    debug!("Fetching queue data for {} queues", queue_pubkeys.len());
+    let accounts = rpc.get_multiple_accounts(queue_pubkeys).await?;
+    let mut queue_data = HashMap::with_capacity(queue_pubkeys.len());
+    for (queue_pubkey, account) in queue_pubkeys.iter().zip(accounts) {
+        let account = account.ok_or_else(|| {
+            ForesterError::Custom(format!("Queue account {} not found", queue_pubkey))
+        })?;
+        queue_data.insert(*queue_pubkey, parse_queue_data(account)?);
+    }
+    Ok(queue_data)
+}
+
+fn parse_queue_data(mut account: Account) -> Result<QueueData, HashSetError> {
     let nullifier_queue: HashSet = unsafe {
         HashSet::from_bytes_copy(&mut account.data[8 + mem::size_of::<QueueAccount>()..])?
     };
 
-    Ok(nullifier_queue
+    let items = nullifier_queue
         .iter()
         .filter_map(|(index, cell)| {
             if cell.sequence_number.is_none() {
                 Some(QueueItemData {
                     hash: cell.value_bytes(),
                     index,
+                    insertion_slot: None,
                 })
             } else {
                 None
             }
         })
-        .collect())
+        .collect();
+    Ok(QueueData {
+        items,
+        capacity: nullifier_queue.capacity,
+    })
 }
 
 #[derive(Debug)]
@@ -46,3 +182,86 @@ pub struct QueueUpdate {
     pub(crate) pubkey: Pubkey,
     pub(crate) slot: u64,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn queue_item(hash: u8) -> QueueItemData {
+        QueueItemData {
+            hash: [hash; 32],
+            index: hash as usize,
+            insertion_slot: None,
+        }
+    }
+
+    #[test]
+    fn test_aged_queue_item_is_stale() {
+        let queue_pubkey = Pubkey::new_unique();
+        let mut tracker = QueueItemAgeTracker::default();
+
+        let mut items = vec![queue_item(1)];
+        tracker.stamp(&queue_pubkey, &mut items, 100);
+        assert_eq!(items[0].insertion_slot, Some(100));
+
+        let mut items = vec![queue_item(1), queue_item(2)];
+        tracker.stamp(&queue_pubkey, &mut items, 200);
+        assert_eq!(items[0].insertion_slot, Some(100));
+        assert_eq!(items[1].insertion_slot, Some(200));
+
+        assert!(items[0].is_stale(200, 50));
+        assert!(!items[0].is_stale(200, 100));
+        assert!(!items[1].is_stale(200, 50));
+        assert!(!queue_item(3).is_stale(200, 50));
+
+        // Items which left the queue are forgotten.
+        let mut items = vec![queue_item(2)];
+        tracker.stamp(&queue_pubkey, &mut items, 300);
+        let mut items = vec![queue_item(1)];
+        tracker.stamp(&queue_pubkey, &mut items, 400);
+        assert_eq!(items[0].insertion_slot, Some(400));
+    }
+
+    #[test]
+    fn test_stale_queue_item_is_readmitted_after_cooldown() {
+        let queue_pubkey = Pubkey::new_unique();
+        let mut tracker = QueueItemAgeTracker::default();
+        let mut items = vec![queue_item(1)];
+        tracker.stamp(&queue_pubkey, &mut items, 100);
+
+        // Stale, but not yet skipped for another `max_age_slots`.
+        let mut items = vec![queue_item(1)];
+        tracker.stamp(&queue_pubkey, &mut items, 200);
+        assert_eq!(
+            tracker.readmit_after_cooldown(&queue_pubkey, &mut items, 200, 50),
+            0
+        );
+        assert!(items[0].is_stale(200, 50));
+
+        let mut items = vec![queue_item(1)];
+        tracker.stamp(&queue_pubkey, &mut items, 201);
+        assert_eq!(
+            tracker.readmit_after_cooldown(&queue_pubkey, &mut items, 201, 50),
+            1
+        );
+        assert_eq!(items[0].insertion_slot, Some(201));
+        assert!(!items[0].is_stale(201, 50));
+
+        // The restarted age is remembered.
+        let mut items = vec![queue_item(1)];
+        tracker.stamp(&queue_pubkey, &mut items, 240);
+        assert_eq!(items[0].insertion_slot, Some(201));
+    }
+
+    #[test]
+    fn test_sort_by_insertion_order() {
+        let mut items = vec![queue_item(3), queue_item(1), queue_item(2), queue_item(4)];
+        items[0].insertion_slot = Some(100);
+        items[1].insertion_slot = Some(200);
+        items[2].insertion_slot = Some(100);
+
+        sort_by_insertion_order(&mut items);
+        let indices: Vec<usize> = items.iter().map(|item| item.index).collect();
+        assert_eq!(indices, vec![2, 3, 1, 4]);
+    }
+}
@@ -0,0 +1,80 @@
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket limiting the rate of outbound transactions. A single
+/// limiter is shared by all concurrent batch tasks so that the total send
+/// rate stays below `max_tps`, with bursts of at most `max_tps`
+/// transactions.
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_tps: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(max_tps: u32) -> Self {
+        let max_tps = max_tps.max(1) as f64;
+        Self {
+            max_tps,
+            bucket: Mutex::new(Bucket {
+                tokens: max_tps,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a token is available and takes it.
+    pub async fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock().await;
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.max_tps).min(self.max_tps);
+                bucket.last_refill = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.max_tps)
+            };
+            sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_rate_limiter_shared_across_tasks() {
+        let rate_limiter = Arc::new(RateLimiter::new(100));
+        let start = Instant::now();
+        let tasks: Vec<_> = (0..3)
+            .map(|_| {
+                let rate_limiter = rate_limiter.clone();
+                tokio::spawn(async move {
+                    for _ in 0..50 {
+                        rate_limiter.acquire().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        // The first 100 acquisitions use the initial burst, the remaining 50
+        // are limited to 100 per second.
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(490), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+}
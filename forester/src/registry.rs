@@ -0,0 +1,381 @@
+use crate::errors::ForesterError;
+use crate::Result;
+use account_compression::utils::constants::{CPI_AUTHORITY_PDA_SEED, NOOP_PUBKEY};
+use anchor_lang::{InstructionData, ToAccountMetas};
+use light_registry::account_compression_cpi::sdk::{
+    get_registered_program_pda, CreateNullifyInstructionInputs,
+    CreateRolloverMerkleTreeInstructionInputs, UpdateAddressMerkleTreeInstructionInputs,
+};
+use light_registry::constants::{FORESTER_EPOCH_SEED, FORESTER_SEED, PROTOCOL_CONFIG_PDA_SEED};
+use light_test_utils::rpc::rpc_connection::RpcConnection;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+
+/// Registry program deployment a forester registers with and reports work
+/// to. The instructions and PDAs of `light_registry::sdk` are bound to
+/// `light_registry::ID`; these are derived from `program_id` instead, so that
+/// foresters of several registry deployments can run in one process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegistryProgram {
+    pub program_id: Pubkey,
+}
+
+impl RegistryProgram {
+    pub fn new(program_id: Pubkey) -> Self {
+        Self { program_id }
+    }
+
+    /// Checks that the program is deployed and has a protocol config, so
+    /// that a misconfigured program id fails at startup rather than at
+    /// registration.
+    pub async fn validate<R: RpcConnection>(&self, rpc: &mut R) -> Result<()> {
+        let program = rpc.get_account(self.program_id).await?.ok_or_else(|| {
+            ForesterError::Custom(format!("Registry program {} not found", self.program_id))
+        })?;
+        if !program.executable {
+            return Err(ForesterError::Custom(format!(
+                "Registry program {} is not executable",
+                self.program_id
+            )));
+        }
+        if rpc.get_account(self.protocol_config_pda()).await?.is_none() {
+            return Err(ForesterError::Custom(format!(
+                "Registry program {} has no protocol config",
+                self.program_id
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn protocol_config_pda(&self) -> Pubkey {
+        Pubkey::find_program_address(&[PROTOCOL_CONFIG_PDA_SEED], &self.program_id).0
+    }
+
+    pub fn epoch_pda(&self, epoch: u64) -> Pubkey {
+        Pubkey::find_program_address(&[&epoch.to_le_bytes()], &self.program_id).0
+    }
+
+    pub fn forester_pda(&self, authority: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[FORESTER_SEED, authority.as_ref()], &self.program_id).0
+    }
+
+    pub fn forester_epoch_pda(&self, authority: &Pubkey, epoch: u64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                FORESTER_EPOCH_SEED,
+                self.forester_pda(authority).as_ref(),
+                epoch.to_le_bytes().as_slice(),
+            ],
+            &self.program_id,
+        )
+        .0
+    }
+
+    fn cpi_authority_pda(&self) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[CPI_AUTHORITY_PDA_SEED], &self.program_id)
+    }
+
+    fn registered_forester_pda(
+        &self,
+        authority: &Pubkey,
+        epoch: u64,
+        is_metadata_forester: bool,
+    ) -> Option<Pubkey> {
+        if is_metadata_forester {
+            None
+        } else {
+            Some(self.forester_epoch_pda(authority, epoch))
+        }
+    }
+
+    fn instruction(
+        &self,
+        accounts: impl ToAccountMetas,
+        data: impl InstructionData,
+    ) -> Instruction {
+        Instruction {
+            program_id: self.program_id,
+            accounts: accounts.to_account_metas(Some(true)),
+            data: data.data(),
+        }
+    }
+
+    pub fn register_forester_epoch_pda_instruction(
+        &self,
+        authority: &Pubkey,
+        epoch: u64,
+    ) -> Instruction {
+        self.instruction(
+            light_registry::accounts::RegisterForesterEpoch {
+                fee_payer: *authority,
+                forester_epoch_pda: self.forester_epoch_pda(authority, epoch),
+                forester_pda: self.forester_pda(authority),
+                authority: *authority,
+                epoch_pda: self.epoch_pda(epoch),
+                protocol_config: self.protocol_config_pda(),
+                system_program: solana_sdk::system_program::id(),
+            },
+            light_registry::instruction::RegisterForesterEpoch { epoch },
+        )
+    }
+
+    pub fn finalize_registration_instruction(&self, authority: &Pubkey, epoch: u64) -> Instruction {
+        self.instruction(
+            light_registry::accounts::FinalizeRegistration {
+                forester_epoch_pda: self.forester_epoch_pda(authority, epoch),
+                authority: *authority,
+                epoch_pda: self.epoch_pda(epoch),
+            },
+            light_registry::instruction::FinalizeRegistration {},
+        )
+    }
+
+    pub fn report_work_instruction(&self, authority: &Pubkey, epoch: u64) -> Instruction {
+        self.instruction(
+            light_registry::accounts::ReportWork {
+                authority: *authority,
+                forester_epoch_pda: self.forester_epoch_pda(authority, epoch),
+                epoch_pda: self.epoch_pda(epoch),
+            },
+            light_registry::instruction::ReportWork {},
+        )
+    }
+
+    pub fn nullify_instruction(
+        &self,
+        inputs: CreateNullifyInstructionInputs,
+        epoch: u64,
+    ) -> Instruction {
+        let (cpi_authority, bump) = self.cpi_authority_pda();
+        self.instruction(
+            light_registry::accounts::NullifyLeaves {
+                authority: inputs.authority,
+                registered_forester_pda: self.registered_forester_pda(
+                    &inputs.authority,
+                    epoch,
+                    inputs.is_metadata_forester,
+                ),
+                registered_program_pda: get_registered_program_pda(&self.program_id),
+                nullifier_queue: inputs.nullifier_queue,
+                merkle_tree: inputs.merkle_tree,
+                log_wrapper: NOOP_PUBKEY.into(),
+                cpi_authority,
+                account_compression_program: account_compression::ID,
+            },
+            light_registry::instruction::Nullify {
+                bump,
+                change_log_indices: inputs.change_log_indices,
+                leaves_queue_indices: inputs.leaves_queue_indices,
+                indices: inputs.indices,
+                proofs: inputs.proofs,
+            },
+        )
+    }
+
+    pub fn update_address_merkle_tree_instruction(
+        &self,
+        inputs: UpdateAddressMerkleTreeInstructionInputs,
+        epoch: u64,
+    ) -> Instruction {
+        let (cpi_authority, bump) = self.cpi_authority_pda();
+        self.instruction(
+            light_registry::accounts::UpdateAddressMerkleTree {
+                authority: inputs.authority,
+                registered_forester_pda: self.registered_forester_pda(
+                    &inputs.authority,
+                    epoch,
+                    inputs.is_metadata_forester,
+                ),
+                registered_program_pda: get_registered_program_pda(&self.program_id),
+                merkle_tree: inputs.address_merkle_tree,
+                queue: inputs.address_queue,
+                log_wrapper: NOOP_PUBKEY.into(),
+                cpi_authority,
+                account_compression_program: account_compression::ID,
+            },
+            light_registry::instruction::UpdateAddressMerkleTree {
+                bump,
+                changelog_index: inputs.changelog_index,
+                indexed_changelog_index: inputs.indexed_changelog_index,
+                value: inputs.value,
+                low_address_index: inputs.low_address_index,
+                low_address_value: inputs.low_address_value,
+                low_address_next_index: inputs.low_address_next_index,
+                low_address_next_value: inputs.low_address_next_value,
+                low_address_proof: inputs.low_address_proof,
+            },
+        )
+    }
+
+    pub fn rollover_address_merkle_tree_instruction(
+        &self,
+        inputs: CreateRolloverMerkleTreeInstructionInputs,
+        epoch: u64,
+    ) -> Instruction {
+        let (cpi_authority, bump) = self.cpi_authority_pda();
+        self.instruction(
+            light_registry::accounts::RolloverAddressMerkleTreeAndQueue {
+                account_compression_program: account_compression::ID,
+                registered_forester_pda: self.registered_forester_pda(
+                    &inputs.authority,
+                    epoch,
+                    inputs.is_metadata_forester,
+                ),
+                cpi_authority,
+                authority: inputs.authority,
+                registered_program_pda: get_registered_program_pda(&self.program_id),
+                new_merkle_tree: inputs.new_merkle_tree,
+                new_queue: inputs.new_queue,
+                old_merkle_tree: inputs.old_merkle_tree,
+                old_queue: inputs.old_queue,
+            },
+            light_registry::instruction::RolloverAddressMerkleTreeAndQueue { bump },
+        )
+    }
+
+    /// `None` if `inputs` has no cpi context account.
+    pub fn rollover_state_merkle_tree_instruction(
+        &self,
+        inputs: CreateRolloverMerkleTreeInstructionInputs,
+        epoch: u64,
+    ) -> Option<Instruction> {
+        let (cpi_authority, bump) = self.cpi_authority_pda();
+        Some(self.instruction(
+            light_registry::accounts::RolloverStateMerkleTreeAndQueue {
+                account_compression_program: account_compression::ID,
+                registered_forester_pda: self.registered_forester_pda(
+                    &inputs.authority,
+                    epoch,
+                    inputs.is_metadata_forester,
+                ),
+                cpi_authority,
+                authority: inputs.authority,
+                registered_program_pda: get_registered_program_pda(&self.program_id),
+                new_merkle_tree: inputs.new_merkle_tree,
+                new_queue: inputs.new_queue,
+                old_merkle_tree: inputs.old_merkle_tree,
+                old_queue: inputs.old_queue,
+                cpi_context_account: inputs.cpi_context_account?,
+                light_system_program: light_system_program::ID,
+                protocol_config_pda: self.protocol_config_pda(),
+            },
+            light_registry::instruction::RolloverStateMerkleTreeAndQueue { bump },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use light_registry::account_compression_cpi::sdk::{
+        create_nullify_instruction, create_rollover_address_merkle_tree_instruction,
+        create_rollover_state_merkle_tree_instruction,
+        create_update_address_merkle_tree_instruction,
+    };
+    use light_registry::sdk::{
+        create_finalize_registration_instruction, create_register_forester_epoch_pda_instruction,
+        create_report_work_instruction,
+    };
+    use light_registry::utils::{
+        get_epoch_pda_address, get_forester_epoch_pda_from_authority,
+        get_protocol_config_pda_address,
+    };
+
+    #[test]
+    fn test_default_registry_matches_sdk() {
+        let registry = RegistryProgram::new(light_registry::ID);
+        let authority = Pubkey::new_unique();
+        let epoch = 3;
+
+        assert_eq!(
+            registry.protocol_config_pda(),
+            get_protocol_config_pda_address().0
+        );
+        assert_eq!(registry.epoch_pda(epoch), get_epoch_pda_address(epoch));
+        assert_eq!(
+            registry.forester_epoch_pda(&authority, epoch),
+            get_forester_epoch_pda_from_authority(&authority, epoch).0
+        );
+        assert_eq!(
+            registry.register_forester_epoch_pda_instruction(&authority, epoch),
+            create_register_forester_epoch_pda_instruction(&authority, epoch)
+        );
+        assert_eq!(
+            registry.finalize_registration_instruction(&authority, epoch),
+            create_finalize_registration_instruction(&authority, epoch)
+        );
+        assert_eq!(
+            registry.report_work_instruction(&authority, epoch),
+            create_report_work_instruction(&authority, epoch)
+        );
+
+        let nullify_inputs = || CreateNullifyInstructionInputs {
+            authority,
+            nullifier_queue: Pubkey::new_from_array([1; 32]),
+            merkle_tree: Pubkey::new_from_array([2; 32]),
+            change_log_indices: vec![1],
+            leaves_queue_indices: vec![2],
+            indices: vec![3],
+            proofs: vec![vec![[4; 32]; 26]],
+            derivation: authority,
+            is_metadata_forester: false,
+        };
+        assert_eq!(
+            registry.nullify_instruction(nullify_inputs(), epoch),
+            create_nullify_instruction(nullify_inputs(), epoch)
+        );
+
+        let update_inputs = || UpdateAddressMerkleTreeInstructionInputs {
+            authority,
+            address_merkle_tree: Pubkey::new_from_array([1; 32]),
+            address_queue: Pubkey::new_from_array([2; 32]),
+            changelog_index: 1,
+            indexed_changelog_index: 2,
+            value: 3,
+            low_address_index: 4,
+            low_address_value: [5; 32],
+            low_address_next_index: 6,
+            low_address_next_value: [7; 32],
+            low_address_proof: [[8; 32]; 16],
+            is_metadata_forester: false,
+        };
+        assert_eq!(
+            registry.update_address_merkle_tree_instruction(update_inputs(), epoch),
+            create_update_address_merkle_tree_instruction(update_inputs(), epoch)
+        );
+
+        let rollover_inputs = || CreateRolloverMerkleTreeInstructionInputs {
+            authority,
+            new_queue: Pubkey::new_from_array([1; 32]),
+            new_merkle_tree: Pubkey::new_from_array([2; 32]),
+            old_queue: Pubkey::new_from_array([3; 32]),
+            old_merkle_tree: Pubkey::new_from_array([4; 32]),
+            cpi_context_account: Some(Pubkey::new_from_array([5; 32])),
+            is_metadata_forester: false,
+        };
+        assert_eq!(
+            registry.rollover_address_merkle_tree_instruction(rollover_inputs(), epoch),
+            create_rollover_address_merkle_tree_instruction(rollover_inputs(), epoch)
+        );
+        assert_eq!(
+            registry.rollover_state_merkle_tree_instruction(rollover_inputs(), epoch),
+            Some(create_rollover_state_merkle_tree_instruction(
+                rollover_inputs(),
+                epoch
+            ))
+        );
+    }
+
+    #[test]
+    fn test_other_registry_derives_own_pdas() {
+        let registry = RegistryProgram::new(Pubkey::new_unique());
+        let authority = Pubkey::new_unique();
+        let instruction = registry.report_work_instruction(&authority, 1);
+        assert_eq!(instruction.program_id, registry.program_id);
+        assert_ne!(
+            registry.forester_epoch_pda(&authority, 1),
+            get_forester_epoch_pda_from_authority(&authority, 1).0
+        );
+        assert_ne!(registry.epoch_pda(1), get_epoch_pda_address(1));
+    }
+}
@@ -2,6 +2,7 @@ mod operations;
 mod state;
 
 pub use operations::{
-    is_tree_ready_for_rollover, rollover_address_merkle_tree, rollover_state_merkle_tree,
+    is_tree_ready_for_rollover, is_tree_ready_for_rollover_auto, rollover_address_merkle_tree,
+    rollover_state_merkle_tree, rollover_with_retry, tree_type_of, RolloverOutcome,
 };
 pub use state::RolloverState;
@@ -1,32 +1,36 @@
+use std::future::Future;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use light_registry::account_compression_cpi::sdk::{
-    create_rollover_address_merkle_tree_instruction, create_rollover_state_merkle_tree_instruction,
-    CreateRolloverMerkleTreeInstructionInputs,
-};
+use light_registry::account_compression_cpi::sdk::CreateRolloverMerkleTreeInstructionInputs;
 use light_registry::protocol_config::state::ProtocolConfig;
-use log::info;
-use solana_sdk::instruction::Instruction;
+use log::{info, warn};
+use solana_sdk::instruction::{Instruction, InstructionError};
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::signature::Keypair;
+use solana_sdk::signature::{Keypair, Signature};
 use solana_sdk::signer::Signer;
-use solana_sdk::transaction::Transaction;
-use tokio::sync::Mutex;
+use solana_sdk::transaction::{Transaction, TransactionError};
+use tokio::sync::RwLock;
 
+use crate::config::RolloverConfirmation;
 use crate::errors::ForesterError;
+use crate::registry::RegistryProgram;
+use crate::signer::{self, sign_transaction};
+use crate::tree_helpers::fetch_merkle_tree_header;
+use crate::utils::log_simulation_result;
 use crate::ForesterConfig;
-use account_compression::utils::constants::{
-    STATE_MERKLE_TREE_CANOPY_DEPTH, STATE_MERKLE_TREE_HEIGHT,
-};
+use account_compression::errors::AccountCompressionErrorCode;
 use account_compression::{
     AddressMerkleTreeAccount, AddressMerkleTreeConfig, AddressQueueConfig, NullifierQueueConfig,
     QueueAccount, StateMerkleTreeAccount, StateMerkleTreeConfig,
 };
+use anchor_lang::Discriminator;
 use light_hasher::Poseidon;
 use light_merkle_tree_reference::MerkleTree;
 use light_test_utils::address_merkle_tree_config::{
     get_address_bundle_config, get_state_bundle_config,
 };
+use light_test_utils::create_account_instruction;
 use light_test_utils::forester_epoch::{TreeAccounts, TreeType};
 use light_test_utils::indexer::{
     AddressMerkleTreeAccounts, Indexer, StateMerkleTreeAccounts, StateMerkleTreeBundle,
@@ -34,9 +38,6 @@ use light_test_utils::indexer::{
 use light_test_utils::registry::RentExemption;
 use light_test_utils::rpc::errors::RpcError;
 use light_test_utils::rpc::rpc_connection::RpcConnection;
-use light_test_utils::{
-    create_account_instruction, get_concurrent_merkle_tree, get_indexed_merkle_tree,
-};
 
 pub async fn is_tree_ready_for_rollover<R: RpcConnection>(
     rpc: &mut R,
@@ -52,30 +53,25 @@ pub async fn is_tree_ready_for_rollover<R: RpcConnection>(
             let account = rpc
                 .get_anchor_account::<StateMerkleTreeAccount>(&tree_pubkey)
                 .await?
-                .unwrap();
+                .ok_or_else(|| ForesterError::Custom(format!("Tree {} not found", tree_pubkey)))?;
             info!("Account: {:?}", account);
             let is_already_rolled_over =
                 account.metadata.rollover_metadata.rolledover_slot != u64::MAX;
             if is_already_rolled_over {
                 return Ok(false);
             }
-            let merkle_tree =
-                get_concurrent_merkle_tree::<StateMerkleTreeAccount, R, Poseidon, 26>(
-                    rpc,
-                    tree_pubkey,
-                )
-                .await;
-            let height = 26;
-            let threshold = ((1 << height) * account.metadata.rollover_metadata.rollover_threshold
+            let header = fetch_merkle_tree_header(rpc, tree_pubkey, tree_type).await?;
+            let threshold = ((1 << header.height)
+                * account.metadata.rollover_metadata.rollover_threshold
                 / 100) as usize;
 
-            Ok(merkle_tree.next_index() >= threshold)
+            Ok(header.next_index >= threshold)
         }
         TreeType::Address => {
             let account = rpc
                 .get_anchor_account::<AddressMerkleTreeAccount>(&tree_pubkey)
                 .await?
-                .unwrap();
+                .ok_or_else(|| ForesterError::Custom(format!("Tree {} not found", tree_pubkey)))?;
             info!("Account: {:?}", account);
             let is_already_rolled_over =
                 account.metadata.rollover_metadata.rolledover_slot != u64::MAX;
@@ -83,45 +79,140 @@ pub async fn is_tree_ready_for_rollover<R: RpcConnection>(
                 return Ok(false);
             }
 
-            let merkle_tree =
-                get_indexed_merkle_tree::<AddressMerkleTreeAccount, R, Poseidon, usize, 26, 16>(
-                    rpc,
-                    tree_pubkey,
-                )
-                .await;
-
-            let height = 26;
-            let threshold = ((1 << height) * account.metadata.rollover_metadata.rollover_threshold
+            let header = fetch_merkle_tree_header(rpc, tree_pubkey, tree_type).await?;
+            let threshold = ((1 << header.height)
+                * account.metadata.rollover_metadata.rollover_threshold
                 / 100) as usize;
 
-            Ok(merkle_tree.next_index() >= threshold)
+            Ok(header.next_index >= threshold)
+        }
+    }
+}
+
+/// Detects the type of the tree at `tree_pubkey` from its account
+/// discriminator.
+pub async fn tree_type_of<R: RpcConnection>(
+    rpc: &mut R,
+    tree_pubkey: Pubkey,
+) -> Result<TreeType, ForesterError> {
+    let account = rpc
+        .get_account(tree_pubkey)
+        .await?
+        .ok_or_else(|| ForesterError::Custom(format!("Tree {} not found", tree_pubkey)))?;
+    match account.data.get(..8) {
+        Some(discriminator) if discriminator == StateMerkleTreeAccount::DISCRIMINATOR => {
+            Ok(TreeType::State)
         }
+        Some(discriminator) if discriminator == AddressMerkleTreeAccount::DISCRIMINATOR => {
+            Ok(TreeType::Address)
+        }
+        _ => Err(ForesterError::Custom(format!(
+            "{} is neither a state nor an address tree",
+            tree_pubkey
+        ))),
     }
 }
 
-#[allow(dead_code)]
+/// Like `is_tree_ready_for_rollover`, for a tree of unknown type.
+pub async fn is_tree_ready_for_rollover_auto<R: RpcConnection>(
+    rpc: &mut R,
+    tree_pubkey: Pubkey,
+) -> Result<bool, ForesterError> {
+    let tree_type = tree_type_of(rpc, tree_pubkey).await?;
+    is_tree_ready_for_rollover(rpc, tree_pubkey, tree_type).await
+}
+
+/// Re-checks the tree right before a rollover, so that a tree another
+/// forester rolled over since it was found ready isn't rolled over again,
+/// which would fail on chain after paying for the new accounts.
+async fn ensure_tree_ready_for_rollover<R: RpcConnection>(
+    rpc: &mut R,
+    tree_accounts: &TreeAccounts,
+) -> Result<(), ForesterError> {
+    if is_tree_ready_for_rollover(rpc, tree_accounts.merkle_tree, tree_accounts.tree_type).await? {
+        Ok(())
+    } else {
+        Err(ForesterError::TreeAlreadyRolledOver)
+    }
+}
+
+/// Rolls over a state tree and returns the accounts of the new tree.
+///
+/// The new cpi context account is sized according to `protocol_config`, which
+/// should be the protocol config deployed on chain.
 pub async fn rollover_state_merkle_tree<R: RpcConnection, I: Indexer<R>>(
     config: Arc<ForesterConfig>,
     rpc: &mut R,
-    indexer: Arc<Mutex<I>>,
+    indexer: Arc<RwLock<I>>,
+    signer: &dyn signer::ForesterSigner,
+    protocol_config: &ProtocolConfig,
     tree_accounts: &TreeAccounts,
-) -> Result<(), ForesterError> {
+) -> Result<TreeAccounts, ForesterError> {
+    ensure_tree_ready_for_rollover(rpc, tree_accounts).await?;
     let new_nullifier_queue_keypair = Keypair::new();
     let new_merkle_tree_keypair = Keypair::new();
     let new_cpi_signature_keypair = Keypair::new();
+    let new_tree_accounts = TreeAccounts::new(
+        new_merkle_tree_keypair.pubkey(),
+        new_nullifier_queue_keypair.pubkey(),
+        TreeType::State,
+        false,
+    );
+
+    if config.dry_run {
+        let mut instructions = config.compute_budget_instructions();
+        instructions.extend(
+            create_rollover_state_merkle_tree_instructions(
+                rpc,
+                &config.registry(),
+                &signer.pubkey(),
+                &new_nullifier_queue_keypair,
+                &new_merkle_tree_keypair,
+                &new_cpi_signature_keypair,
+                &tree_accounts.merkle_tree,
+                &tree_accounts.queue,
+                &Pubkey::default(),
+                protocol_config.cpi_context_size,
+            )
+            .await?,
+        );
+        simulate_rollover(
+            rpc,
+            signer,
+            &instructions,
+            &[
+                &new_nullifier_queue_keypair,
+                &new_merkle_tree_keypair,
+                &new_cpi_signature_keypair,
+            ],
+            tree_accounts,
+        )
+        .await?;
+        return Ok(new_tree_accounts);
+    }
 
     let rollover_signature = perform_state_merkle_tree_roll_over_forester(
-        &config.payer_keypair,
+        signer,
         rpc,
+        &config.registry(),
         &new_nullifier_queue_keypair,
         &new_merkle_tree_keypair,
         &new_cpi_signature_keypair,
         &tree_accounts.merkle_tree,
         &tree_accounts.queue,
         &Pubkey::default(),
+        protocol_config.cpi_context_size,
+        &config.compute_budget_instructions(),
+        &config.rollover_confirmation(),
     )
     .await?;
     println!("Rollover signature: {:?}", rollover_signature);
+    verify_state_merkle_tree_rollover(rpc, rollover_signature, &new_tree_accounts).await?;
+
+    // The reference tree must have the geometry of the new tree, which is
+    // copied from the old tree's config, for the indexer's proofs to match.
+    let header =
+        fetch_merkle_tree_header(rpc, new_tree_accounts.merkle_tree, TreeType::State).await?;
 
     let state_bundle = StateMerkleTreeBundle {
         // TODO: fetch correct fee when this property is used
@@ -132,107 +223,247 @@ pub async fn rollover_state_merkle_tree<R: RpcConnection, I: Indexer<R>>(
             cpi_context: new_cpi_signature_keypair.pubkey(),
         },
         merkle_tree: Box::new(MerkleTree::<Poseidon>::new(
-            STATE_MERKLE_TREE_HEIGHT as usize,
-            STATE_MERKLE_TREE_CANOPY_DEPTH as usize,
+            header.height,
+            header.canopy_depth,
         )),
     };
-    indexer.lock().await.add_state_bundle(state_bundle);
+    indexer.write().await.add_state_bundle(state_bundle);
+    Ok(new_tree_accounts)
+}
+
+/// Confirms the rollover transaction and checks that the new state tree
+/// account was initialized with the new queue, so that the new tree is only
+/// added to the indexer once it exists on chain.
+async fn verify_state_merkle_tree_rollover<R: RpcConnection>(
+    rpc: &mut R,
+    signature: Signature,
+    new_tree_accounts: &TreeAccounts,
+) -> Result<(), ForesterError> {
+    if !rpc.confirm_transaction(signature).await? {
+        return Err(ForesterError::RolloverNotVerified(format!(
+            "transaction {} was not confirmed",
+            signature
+        )));
+    }
+    let account = rpc
+        .get_anchor_account::<StateMerkleTreeAccount>(&new_tree_accounts.merkle_tree)
+        .await?
+        .ok_or_else(|| {
+            ForesterError::RolloverNotVerified(format!(
+                "state tree {} does not exist",
+                new_tree_accounts.merkle_tree
+            ))
+        })?;
+    if account.metadata.associated_queue != new_tree_accounts.queue {
+        return Err(ForesterError::RolloverNotVerified(format!(
+            "state tree {} is associated with queue {} instead of {}",
+            new_tree_accounts.merkle_tree,
+            account.metadata.associated_queue,
+            new_tree_accounts.queue
+        )));
+    }
     Ok(())
 }
 
 #[allow(clippy::too_many_arguments)]
 pub async fn perform_state_merkle_tree_roll_over_forester<R: RpcConnection>(
-    payer: &Keypair,
+    signer: &dyn signer::ForesterSigner,
     context: &mut R,
+    registry: &RegistryProgram,
     new_queue_keypair: &Keypair,
     new_address_merkle_tree_keypair: &Keypair,
     new_cpi_context_keypair: &Keypair,
     old_merkle_tree_pubkey: &Pubkey,
     old_queue_pubkey: &Pubkey,
     old_cpi_context_pubkey: &Pubkey,
-) -> Result<solana_sdk::signature::Signature, RpcError> {
-    let instructions = create_rollover_state_merkle_tree_instructions(
-        context,
-        &payer.pubkey(),
-        new_queue_keypair,
-        new_address_merkle_tree_keypair,
-        new_cpi_context_keypair,
-        old_merkle_tree_pubkey,
-        old_queue_pubkey,
-        old_cpi_context_pubkey,
-    )
-    .await;
-    let blockhash = context.get_latest_blockhash().await.unwrap();
-    let transaction = Transaction::new_signed_with_payer(
-        &instructions,
-        Some(&payer.pubkey()),
-        &vec![&payer, &new_queue_keypair, &new_address_merkle_tree_keypair],
-        blockhash,
+    cpi_context_size: u64,
+    compute_budget_instructions: &[Instruction],
+    confirmation: &RolloverConfirmation,
+) -> Result<Signature, ForesterError> {
+    let mut instructions = compute_budget_instructions.to_vec();
+    instructions.extend(
+        create_rollover_state_merkle_tree_instructions(
+            context,
+            registry,
+            &signer.pubkey(),
+            new_queue_keypair,
+            new_address_merkle_tree_keypair,
+            new_cpi_context_keypair,
+            old_merkle_tree_pubkey,
+            old_queue_pubkey,
+            old_cpi_context_pubkey,
+            cpi_context_size,
+        )
+        .await?,
     );
-    context.process_transaction(transaction).await
+    let blockhash = context.get_latest_blockhash().await?;
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&signer.pubkey()));
+    sign_transaction(
+        signer,
+        &mut transaction,
+        &[new_queue_keypair, new_address_merkle_tree_keypair],
+        blockhash,
+    )
+    .await?;
+    send_rollover_transaction(context, transaction, confirmation).await
+}
+
+/// Sends a rollover transaction and polls its status until it reaches the
+/// configured commitment or the confirmation times out.
+async fn send_rollover_transaction<R: RpcConnection>(
+    rpc: &mut R,
+    transaction: Transaction,
+    confirmation: &RolloverConfirmation,
+) -> Result<Signature, ForesterError> {
+    let signature = rpc.send_transaction(transaction).await?;
+    let start = Instant::now();
+    loop {
+        if let Some(result) = rpc
+            .get_signature_status_with_commitment(signature, confirmation.commitment)
+            .await?
+        {
+            result.map_err(RpcError::TransactionError)?;
+            return Ok(signature);
+        }
+        if start.elapsed() >= confirmation.timeout {
+            return Err(ForesterError::RolloverConfirmationTimeout {
+                signature,
+                timeout: confirmation.timeout,
+            });
+        }
+        tokio::time::sleep(confirmation.poll_interval).await;
+    }
 }
 
 pub async fn rollover_address_merkle_tree<R: RpcConnection, I: Indexer<R>>(
     config: Arc<ForesterConfig>,
     rpc: &mut R,
-    indexer: Arc<Mutex<I>>,
+    indexer: Arc<RwLock<I>>,
+    signer: &dyn signer::ForesterSigner,
     tree_data: &TreeAccounts,
-) -> Result<(), ForesterError> {
+) -> Result<TreeAccounts, ForesterError> {
+    ensure_tree_ready_for_rollover(rpc, tree_data).await?;
     let new_nullifier_queue_keypair = Keypair::new();
     let new_merkle_tree_keypair = Keypair::new();
+    let new_tree_accounts = TreeAccounts::new(
+        new_merkle_tree_keypair.pubkey(),
+        new_nullifier_queue_keypair.pubkey(),
+        TreeType::Address,
+        false,
+    );
+    if config.dry_run {
+        let mut instructions = config.compute_budget_instructions();
+        instructions.extend(
+            create_rollover_address_merkle_tree_instructions(
+                rpc,
+                &config.registry(),
+                &signer.pubkey(),
+                &new_nullifier_queue_keypair,
+                &new_merkle_tree_keypair,
+                &tree_data.merkle_tree,
+                &tree_data.queue,
+            )
+            .await?,
+        );
+        simulate_rollover(
+            rpc,
+            signer,
+            &instructions,
+            &[&new_nullifier_queue_keypair, &new_merkle_tree_keypair],
+            tree_data,
+        )
+        .await?;
+        return Ok(new_tree_accounts);
+    }
     perform_address_merkle_tree_roll_over(
-        &config.payer_keypair,
+        signer,
         rpc,
+        &config.registry(),
         &new_nullifier_queue_keypair,
         &new_merkle_tree_keypair,
         &tree_data.merkle_tree,
         &tree_data.queue,
+        &config.compute_budget_instructions(),
+        &config.rollover_confirmation(),
     )
     .await?;
 
-    indexer.lock().await.add_address_merkle_tree_accounts(
+    indexer.write().await.add_address_merkle_tree_accounts(
         &new_merkle_tree_keypair,
         &new_nullifier_queue_keypair,
         None,
     );
+    Ok(new_tree_accounts)
+}
+
+async fn simulate_rollover<R: RpcConnection>(
+    rpc: &mut R,
+    signer: &dyn signer::ForesterSigner,
+    instructions: &[Instruction],
+    new_account_keypairs: &[&Keypair],
+    tree_accounts: &TreeAccounts,
+) -> Result<(), ForesterError> {
+    let blockhash = rpc.get_latest_blockhash().await?;
+    let mut transaction = Transaction::new_with_payer(instructions, Some(&signer.pubkey()));
+    sign_transaction(signer, &mut transaction, new_account_keypairs, blockhash).await?;
+    let simulation = rpc.simulate_transaction(&transaction)?;
+    log_simulation_result(
+        &format!(
+            "{:?} tree {} rollover",
+            tree_accounts.tree_type, tree_accounts.merkle_tree
+        ),
+        &transaction,
+        &simulation,
+    );
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn perform_address_merkle_tree_roll_over<R: RpcConnection>(
-    payer: &Keypair,
+    signer: &dyn signer::ForesterSigner,
     context: &mut R,
+    registry: &RegistryProgram,
     new_queue_keypair: &Keypair,
     new_address_merkle_tree_keypair: &Keypair,
     old_merkle_tree_pubkey: &Pubkey,
     old_queue_pubkey: &Pubkey,
-) -> Result<solana_sdk::signature::Signature, RpcError> {
-    let instructions = create_rollover_address_merkle_tree_instructions(
-        context,
-        &payer.pubkey(),
-        new_queue_keypair,
-        new_address_merkle_tree_keypair,
-        old_merkle_tree_pubkey,
-        old_queue_pubkey,
-    )
-    .await;
-    let blockhash = context.get_latest_blockhash().await.unwrap();
-    let transaction = Transaction::new_signed_with_payer(
-        &instructions,
-        Some(&payer.pubkey()),
-        &vec![&payer, &new_queue_keypair, &new_address_merkle_tree_keypair],
-        blockhash,
+    compute_budget_instructions: &[Instruction],
+    confirmation: &RolloverConfirmation,
+) -> Result<Signature, ForesterError> {
+    let mut instructions = compute_budget_instructions.to_vec();
+    instructions.extend(
+        create_rollover_address_merkle_tree_instructions(
+            context,
+            registry,
+            &signer.pubkey(),
+            new_queue_keypair,
+            new_address_merkle_tree_keypair,
+            old_merkle_tree_pubkey,
+            old_queue_pubkey,
+        )
+        .await?,
     );
-    context.process_transaction(transaction).await
+    let blockhash = context.get_latest_blockhash().await?;
+    let mut transaction = Transaction::new_with_payer(&instructions, Some(&signer.pubkey()));
+    sign_transaction(
+        signer,
+        &mut transaction,
+        &[new_queue_keypair, new_address_merkle_tree_keypair],
+        blockhash,
+    )
+    .await?;
+    send_rollover_transaction(context, transaction, confirmation).await
 }
 
 pub async fn create_rollover_address_merkle_tree_instructions<R: RpcConnection>(
     rpc: &mut R,
+    registry: &RegistryProgram,
     authority: &Pubkey,
     new_nullifier_queue_keypair: &Keypair,
     new_address_merkle_tree_keypair: &Keypair,
     merkle_tree_pubkey: &Pubkey,
     nullifier_queue_pubkey: &Pubkey,
-) -> Vec<Instruction> {
+) -> Result<Vec<Instruction>, ForesterError> {
     let (merkle_tree_config, queue_config) = get_address_bundle_config(
         rpc,
         AddressMerkleTreeAccounts {
@@ -247,7 +478,7 @@ pub async fn create_rollover_address_merkle_tree_instructions<R: RpcConnection>(
             &merkle_tree_config,
             &queue_config,
         )
-        .await;
+        .await?;
     let create_nullifier_queue_instruction = create_account_instruction(
         authority,
         queue_rent_exemption.size,
@@ -263,7 +494,7 @@ pub async fn create_rollover_address_merkle_tree_instructions<R: RpcConnection>(
         Some(new_address_merkle_tree_keypair),
     );
 
-    let instruction = create_rollover_address_merkle_tree_instruction(
+    let instruction = registry.rollover_address_merkle_tree_instruction(
         CreateRolloverMerkleTreeInstructionInputs {
             authority: *authority,
             new_queue: new_nullifier_queue_keypair.pubkey(),
@@ -275,16 +506,17 @@ pub async fn create_rollover_address_merkle_tree_instructions<R: RpcConnection>(
         },
         0, // TODO: make epoch dynamic
     );
-    vec![
+    Ok(vec![
         create_nullifier_queue_instruction,
         create_state_merkle_tree_instruction,
         instruction,
-    ]
+    ])
 }
 
 #[allow(clippy::too_many_arguments)]
 pub async fn create_rollover_state_merkle_tree_instructions<R: RpcConnection>(
     rpc: &mut R,
+    registry: &RegistryProgram,
     authority: &Pubkey,
     new_nullifier_queue_keypair: &Keypair,
     new_state_merkle_tree_keypair: &Keypair,
@@ -292,7 +524,8 @@ pub async fn create_rollover_state_merkle_tree_instructions<R: RpcConnection>(
     merkle_tree_pubkey: &Pubkey,
     nullifier_queue_pubkey: &Pubkey,
     old_cpi_context_pubkey: &Pubkey,
-) -> Vec<Instruction> {
+    cpi_context_size: u64,
+) -> Result<Vec<Instruction>, ForesterError> {
     let (merkle_tree_config, queue_config) = get_state_bundle_config(
         rpc,
         StateMerkleTreeAccounts {
@@ -304,7 +537,7 @@ pub async fn create_rollover_state_merkle_tree_instructions<R: RpcConnection>(
     .await;
     let (state_merkle_tree_rent_exemption, queue_rent_exemption) =
         get_rent_exemption_for_state_merkle_tree_and_queue(rpc, &merkle_tree_config, &queue_config)
-            .await;
+            .await?;
     let create_nullifier_queue_instruction = create_account_instruction(
         authority,
         queue_rent_exemption.size,
@@ -321,48 +554,49 @@ pub async fn create_rollover_state_merkle_tree_instructions<R: RpcConnection>(
     );
 
     let rent_cpi_config = rpc
-        .get_minimum_balance_for_rent_exemption(ProtocolConfig::default().cpi_context_size as usize)
-        .await
-        .unwrap();
+        .get_minimum_balance_for_rent_exemption(cpi_context_size as usize)
+        .await?;
     let create_cpi_context_instruction = create_account_instruction(
         authority,
-        ProtocolConfig::default().cpi_context_size as usize,
+        cpi_context_size as usize,
         rent_cpi_config,
         &light_system_program::ID,
         Some(new_cpi_context_keypair),
     );
 
-    let instruction = create_rollover_state_merkle_tree_instruction(
-        CreateRolloverMerkleTreeInstructionInputs {
-            authority: *authority,
-            new_queue: new_nullifier_queue_keypair.pubkey(),
-            new_merkle_tree: new_state_merkle_tree_keypair.pubkey(),
-            old_queue: *nullifier_queue_pubkey,
-            old_merkle_tree: *merkle_tree_pubkey,
-            cpi_context_account: Some(new_cpi_context_keypair.pubkey()),
-            is_metadata_forester: false,
-        },
-        0, // TODO: make epoch dynamic
-    );
-    vec![
+    let instruction = registry
+        .rollover_state_merkle_tree_instruction(
+            CreateRolloverMerkleTreeInstructionInputs {
+                authority: *authority,
+                new_queue: new_nullifier_queue_keypair.pubkey(),
+                new_merkle_tree: new_state_merkle_tree_keypair.pubkey(),
+                old_queue: *nullifier_queue_pubkey,
+                old_merkle_tree: *merkle_tree_pubkey,
+                cpi_context_account: Some(new_cpi_context_keypair.pubkey()),
+                is_metadata_forester: false,
+            },
+            0, // TODO: make epoch dynamic
+        )
+        .ok_or_else(|| ForesterError::Custom("Missing cpi context account".to_string()))?;
+    Ok(vec![
         create_cpi_context_instruction,
         create_nullifier_queue_instruction,
         create_state_merkle_tree_instruction,
         instruction,
-    ]
+    ])
 }
 
 pub async fn get_rent_exemption_for_state_merkle_tree_and_queue<R: RpcConnection>(
     rpc: &mut R,
     merkle_tree_config: &StateMerkleTreeConfig,
     queue_config: &NullifierQueueConfig,
-) -> (RentExemption, RentExemption) {
-    let queue_size = QueueAccount::size(queue_config.capacity as usize).unwrap();
+) -> Result<(RentExemption, RentExemption), ForesterError> {
+    let queue_size = QueueAccount::size(queue_config.capacity as usize)
+        .map_err(|e| ForesterError::Custom(e.to_string()))?;
 
     let queue_rent_exempt_lamports = rpc
         .get_minimum_balance_for_rent_exemption(queue_size)
-        .await
-        .unwrap();
+        .await?;
     let tree_size = account_compression::state::StateMerkleTreeAccount::size(
         merkle_tree_config.height as usize,
         merkle_tree_config.changelog_size as usize,
@@ -371,9 +605,8 @@ pub async fn get_rent_exemption_for_state_merkle_tree_and_queue<R: RpcConnection
     );
     let merkle_tree_rent_exempt_lamports = rpc
         .get_minimum_balance_for_rent_exemption(tree_size)
-        .await
-        .unwrap();
-    (
+        .await?;
+    Ok((
         RentExemption {
             lamports: merkle_tree_rent_exempt_lamports,
             size: tree_size,
@@ -382,20 +615,20 @@ pub async fn get_rent_exemption_for_state_merkle_tree_and_queue<R: RpcConnection
             lamports: queue_rent_exempt_lamports,
             size: queue_size,
         },
-    )
+    ))
 }
 
 pub async fn get_rent_exemption_for_address_merkle_tree_and_queue<R: RpcConnection>(
     rpc: &mut R,
     address_merkle_tree_config: &AddressMerkleTreeConfig,
     address_queue_config: &AddressQueueConfig,
-) -> (RentExemption, RentExemption) {
-    let queue_size = QueueAccount::size(address_queue_config.capacity as usize).unwrap();
+) -> Result<(RentExemption, RentExemption), ForesterError> {
+    let queue_size = QueueAccount::size(address_queue_config.capacity as usize)
+        .map_err(|e| ForesterError::Custom(e.to_string()))?;
 
     let queue_rent_exempt_lamports = rpc
         .get_minimum_balance_for_rent_exemption(queue_size)
-        .await
-        .unwrap();
+        .await?;
     let tree_size = account_compression::state::AddressMerkleTreeAccount::size(
         address_merkle_tree_config.height as usize,
         address_merkle_tree_config.changelog_size as usize,
@@ -405,9 +638,8 @@ pub async fn get_rent_exemption_for_address_merkle_tree_and_queue<R: RpcConnecti
     );
     let merkle_tree_rent_exempt_lamports = rpc
         .get_minimum_balance_for_rent_exemption(tree_size)
-        .await
-        .unwrap();
-    (
+        .await?;
+    Ok((
         RentExemption {
             lamports: merkle_tree_rent_exempt_lamports,
             size: tree_size,
@@ -416,5 +648,182 @@ pub async fn get_rent_exemption_for_address_merkle_tree_and_queue<R: RpcConnecti
             lamports: queue_rent_exempt_lamports,
             size: queue_size,
         },
-    )
+    ))
+}
+
+const MAX_ROLLOVER_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Outcome of a rollover that did not fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RolloverOutcome {
+    /// The tree was rolled over into the contained new tree accounts.
+    RolledOver(TreeAccounts),
+    /// The tree had already been rolled over, e.g. by another forester.
+    AlreadyRolledOver,
+}
+
+/// Runs `rollover` until it succeeds, retrying transient RPC failures with
+/// exponential backoff. A tree that has already been rolled over is treated
+/// as success; any other error is returned immediately.
+pub async fn rollover_with_retry<F, Fut>(
+    max_retries: usize,
+    initial_delay: Duration,
+    mut rollover: F,
+) -> Result<RolloverOutcome, ForesterError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<TreeAccounts, ForesterError>>,
+{
+    let mut retry_delay = initial_delay;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match rollover().await {
+            Ok(new_tree_accounts) => return Ok(RolloverOutcome::RolledOver(new_tree_accounts)),
+            Err(e) if is_already_rolled_over(&e) => {
+                info!("Tree has already been rolled over, skipping");
+                return Ok(RolloverOutcome::AlreadyRolledOver);
+            }
+            Err(e) if is_transient_error(&e) && attempt <= max_retries => {
+                warn!(
+                    "Rollover attempt {} failed: {:?}. Retrying in {:?}",
+                    attempt, e, retry_delay
+                );
+                tokio::time::sleep(retry_delay).await;
+                retry_delay = std::cmp::min(retry_delay * 2, MAX_ROLLOVER_RETRY_DELAY);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn instruction_error_code(error: &RpcError) -> Option<u32> {
+    let transaction_error = match error {
+        RpcError::TransactionError(e) => Some(e.clone()),
+        RpcError::ClientError(e) => e.get_transaction_error(),
+        _ => None,
+    };
+    match transaction_error {
+        Some(TransactionError::InstructionError(_, InstructionError::Custom(code))) => Some(code),
+        _ => None,
+    }
+}
+
+fn is_already_rolled_over(error: &ForesterError) -> bool {
+    match error {
+        ForesterError::TreeAlreadyRolledOver => true,
+        ForesterError::RpcError(e) => {
+            instruction_error_code(e)
+                == Some(AccountCompressionErrorCode::MerkleTreeAlreadyRolledOver.into())
+        }
+        _ => false,
+    }
+}
+
+/// Network-level failures that did not produce a transaction error, and
+/// rollover transactions which were not confirmed in time. The retry checks
+/// again whether the tree is ready, so a transaction which landed after all
+/// results in [`RolloverOutcome::AlreadyRolledOver`].
+fn is_transient_error(error: &ForesterError) -> bool {
+    match error {
+        ForesterError::RolloverConfirmationTimeout { .. } => true,
+        ForesterError::RpcError(RpcError::ClientError(e)) => e.get_transaction_error().is_none(),
+        ForesterError::RpcError(RpcError::IoError(_)) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn new_tree_accounts() -> TreeAccounts {
+        TreeAccounts::new(
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            TreeType::State,
+            false,
+        )
+    }
+
+    fn transient_error() -> ForesterError {
+        ForesterError::RpcError(RpcError::IoError(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "connection timed out",
+        )))
+    }
+
+    #[tokio::test]
+    async fn test_rollover_with_retry_recovers_from_transient_failure() {
+        let attempts = AtomicUsize::new(0);
+        let tree_accounts = new_tree_accounts();
+        let result = rollover_with_retry(3, Duration::from_millis(1), || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(transient_error())
+            } else {
+                Ok(tree_accounts)
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), RolloverOutcome::RolledOver(tree_accounts));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rollover_with_retry_gives_up_after_max_retries() {
+        let attempts = AtomicUsize::new(0);
+        let result = rollover_with_retry(2, Duration::from_millis(1), || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(transient_error())
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rollover_with_retry_skips_already_rolled_over() {
+        let attempts = AtomicUsize::new(0);
+        let result = rollover_with_retry(3, Duration::from_millis(1), || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(ForesterError::RpcError(RpcError::TransactionError(
+                TransactionError::InstructionError(
+                    2,
+                    InstructionError::Custom(
+                        AccountCompressionErrorCode::MerkleTreeAlreadyRolledOver.into(),
+                    ),
+                ),
+            )))
+        })
+        .await;
+        assert_eq!(result.unwrap(), RolloverOutcome::AlreadyRolledOver);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let result = rollover_with_retry(3, Duration::from_millis(1), || async {
+            Err(ForesterError::TreeAlreadyRolledOver)
+        })
+        .await;
+        assert_eq!(result.unwrap(), RolloverOutcome::AlreadyRolledOver);
+    }
+
+    #[tokio::test]
+    async fn test_rollover_with_retry_rechecks_after_confirmation_timeout() {
+        let attempts = AtomicUsize::new(0);
+        let result = rollover_with_retry(3, Duration::from_millis(1), || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(ForesterError::RolloverConfirmationTimeout {
+                    signature: Signature::default(),
+                    timeout: Duration::from_secs(60),
+                })
+            } else {
+                // The timed out transaction landed after all.
+                Err(ForesterError::TreeAlreadyRolledOver)
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), RolloverOutcome::AlreadyRolledOver);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
 }
@@ -1,10 +1,21 @@
+use crate::metrics::{increment_rpc_pool_exhausted, observe_rpc_pool_wait};
 use crate::RpcConnection;
-use bb8::{Pool, PooledConnection};
+use bb8::{Pool, PooledConnection, RunError};
+use futures::future::join_all;
 use light_test_utils::rpc::errors::RpcError;
+use log::{debug, warn};
 use solana_sdk::commitment_config::CommitmentConfig;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
-use tokio::time::sleep;
+use tokio::time::{sleep, Instant};
+
+const DEFAULT_MAX_LATENCY: Duration = Duration::from_secs(2);
+const DEFAULT_ENDPOINT_COOLDOWN: Duration = Duration::from_secs(30);
+const DEFAULT_ACQUIRE_TIMEOUT: Duration = Duration::from_secs(15);
+/// Consecutive failures after which an endpoint is marked as down.
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
 
 #[derive(Error, Debug)]
 pub enum PoolError {
@@ -14,11 +25,87 @@ pub enum PoolError {
     RpcRequest(#[from] RpcError),
     #[error("Pool error: {0}")]
     Pool(String),
+    #[error("Connection too slow: {0:?}")]
+    SlowConnection(Duration),
+    /// All connections stayed checked out for the acquire timeout.
+    #[error("No connection available within {0:?}")]
+    Exhausted(Duration),
+}
+
+/// Outcome of the most recent health check of a pooled connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionHealth {
+    pub url: String,
+    pub healthy: bool,
+    pub latency: Duration,
+    pub error: Option<String>,
+    pub checked_at: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct EndpointHealth {
+    pub url: String,
+    pub available: bool,
+    pub connections: u32,
+    pub idle_connections: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct PoolHealth {
+    pub endpoints: Vec<EndpointHealth>,
+    pub connection_health: Vec<ConnectionHealth>,
+}
+
+/// Tracks failures of a single RPC endpoint. After
+/// `MAX_CONSECUTIVE_FAILURES` consecutive failures the endpoint is
+/// considered down until its cooldown has passed.
+#[derive(Debug)]
+struct EndpointState {
+    consecutive_failures: AtomicU32,
+    down_until: Mutex<Option<Instant>>,
+    cooldown: Duration,
+}
+
+impl EndpointState {
+    fn new(cooldown: Duration) -> Self {
+        Self {
+            consecutive_failures: AtomicU32::new(0),
+            down_until: Mutex::new(None),
+            cooldown,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        match *self.down_until.lock().unwrap() {
+            Some(down_until) => Instant::now() >= down_until,
+            None => true,
+        }
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *self.down_until.lock().unwrap() = None;
+    }
+
+    /// Returns true if the failure marked the endpoint as down.
+    fn record_failure(&self) -> bool {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < MAX_CONSECUTIVE_FAILURES {
+            return false;
+        }
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *self.down_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+        true
+    }
 }
 
 pub struct SolanaConnectionManager<R: RpcConnection> {
     url: String,
     commitment: CommitmentConfig,
+    max_latency: Duration,
+    max_size: usize,
+    health_checks: Arc<Mutex<Vec<ConnectionHealth>>>,
+    endpoint_state: Arc<EndpointState>,
     _phantom: std::marker::PhantomData<R>,
 }
 
@@ -27,9 +114,21 @@ impl<R: RpcConnection> SolanaConnectionManager<R> {
         Self {
             url,
             commitment,
+            max_latency: DEFAULT_MAX_LATENCY,
+            max_size: 1,
+            health_checks: Arc::new(Mutex::new(Vec::new())),
+            endpoint_state: Arc::new(EndpointState::new(DEFAULT_ENDPOINT_COOLDOWN)),
             _phantom: std::marker::PhantomData,
         }
     }
+
+    fn record_health_check(&self, health: ConnectionHealth) {
+        let mut health_checks = self.health_checks.lock().unwrap();
+        if health_checks.len() >= self.max_size {
+            health_checks.remove(0);
+        }
+        health_checks.push(health);
+    }
 }
 
 #[async_trait::async_trait]
@@ -41,8 +140,37 @@ impl<R: RpcConnection> bb8::ManageConnection for SolanaConnectionManager<R> {
         Ok(R::new(&self.url, Some(self.commitment)))
     }
 
+    /// Checked out connections which error or respond slower than
+    /// `max_latency` are evicted, and the pool hands out another one.
     async fn is_valid(&self, conn: &mut Self::Connection) -> Result<(), Self::Error> {
-        conn.health().map_err(PoolError::RpcRequest)
+        let start = Instant::now();
+        let result = conn.get_slot().await;
+        let latency = start.elapsed();
+        let result = match result {
+            Ok(_) if latency > self.max_latency => Err(PoolError::SlowConnection(latency)),
+            Ok(_) => Ok(()),
+            Err(e) => Err(PoolError::RpcRequest(e)),
+        };
+        self.record_health_check(ConnectionHealth {
+            url: self.url.clone(),
+            healthy: result.is_ok(),
+            latency,
+            error: result.as_ref().err().map(|e| e.to_string()),
+            checked_at: Instant::now(),
+        });
+        match &result {
+            Ok(()) => self.endpoint_state.record_success(),
+            Err(e) => {
+                warn!("Evicting unhealthy RPC connection to {}: {}", self.url, e);
+                if self.endpoint_state.record_failure() {
+                    warn!(
+                        "Marking RPC endpoint {} as down for {:?}",
+                        self.url, self.endpoint_state.cooldown
+                    );
+                }
+            }
+        }
+        result
     }
 
     fn has_broken(&self, _conn: &mut Self::Connection) -> bool {
@@ -51,8 +179,21 @@ impl<R: RpcConnection> bb8::ManageConnection for SolanaConnectionManager<R> {
 }
 
 #[derive(Debug)]
-pub struct SolanaRpcPool<R: RpcConnection> {
+struct Endpoint<R: RpcConnection> {
+    url: String,
     pool: Pool<SolanaConnectionManager<R>>,
+    state: Arc<EndpointState>,
+}
+
+/// Pool of RPC connections spread over one or more endpoints. Connections
+/// are handed out round-robin across endpoints, skipping endpoints that are
+/// down.
+#[derive(Debug)]
+pub struct SolanaRpcPool<R: RpcConnection> {
+    endpoints: Vec<Endpoint<R>>,
+    next_endpoint: AtomicUsize,
+    health_checks: Arc<Mutex<Vec<ConnectionHealth>>>,
+    acquire_timeout: Duration,
 }
 
 impl<R: RpcConnection> SolanaRpcPool<R> {
@@ -61,25 +202,164 @@ impl<R: RpcConnection> SolanaRpcPool<R> {
         commitment: CommitmentConfig,
         max_size: u32,
     ) -> Result<Self, PoolError> {
-        let manager = SolanaConnectionManager::new(url, commitment);
-        let pool = Pool::builder()
-            .max_size(max_size)
-            .connection_timeout(Duration::from_secs(15))
-            .idle_timeout(Some(Duration::from_secs(60 * 5)))
-            .build(manager)
-            .await
-            .map_err(|e| PoolError::Pool(e.to_string()))?;
+        Self::new_with_endpoints(
+            vec![url],
+            commitment,
+            max_size,
+            DEFAULT_MAX_LATENCY,
+            DEFAULT_ENDPOINT_COOLDOWN,
+            DEFAULT_ACQUIRE_TIMEOUT,
+        )
+        .await
+    }
+
+    /// Creates a pool of up to `max_size` connections per endpoint.
+    /// [`Self::get_connection`] fails with [`PoolError::Exhausted`] if no
+    /// connection becomes available within `acquire_timeout`.
+    pub async fn new_with_endpoints(
+        urls: Vec<String>,
+        commitment: CommitmentConfig,
+        max_size: u32,
+        max_latency: Duration,
+        endpoint_cooldown: Duration,
+        acquire_timeout: Duration,
+    ) -> Result<Self, PoolError> {
+        if urls.is_empty() {
+            return Err(PoolError::Pool("No RPC endpoints configured".to_string()));
+        }
+        let health_checks = Arc::new(Mutex::new(Vec::new()));
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for url in urls.iter() {
+            let state = Arc::new(EndpointState::new(endpoint_cooldown));
+            let manager = SolanaConnectionManager {
+                url: url.clone(),
+                commitment,
+                max_latency,
+                max_size: max_size as usize * urls.len(),
+                health_checks: health_checks.clone(),
+                endpoint_state: state.clone(),
+                _phantom: std::marker::PhantomData,
+            };
+            let pool = Pool::builder()
+                .max_size(max_size)
+                .connection_timeout(acquire_timeout)
+                .idle_timeout(Some(Duration::from_secs(60 * 5)))
+                .build(manager)
+                .await
+                .map_err(|e| PoolError::Pool(e.to_string()))?;
+            endpoints.push(Endpoint {
+                url: url.clone(),
+                pool,
+                state,
+            });
+        }
 
-        Ok(Self { pool })
+        Ok(Self {
+            endpoints,
+            next_endpoint: AtomicUsize::new(0),
+            health_checks,
+            acquire_timeout,
+        })
+    }
+
+    /// Results of the most recent connection health checks, at most one
+    /// per pooled connection.
+    pub fn pool_health(&self) -> PoolHealth {
+        PoolHealth {
+            endpoints: self
+                .endpoints
+                .iter()
+                .map(|endpoint| {
+                    let state = endpoint.pool.state();
+                    EndpointHealth {
+                        url: endpoint.url.clone(),
+                        available: endpoint.state.is_available(),
+                        connections: state.connections,
+                        idle_connections: state.idle_connections,
+                    }
+                })
+                .collect(),
+            connection_health: self.health_checks.lock().unwrap().clone(),
+        }
+    }
+
+    /// Periodically checks out every idle connection, which validates it and
+    /// replaces it if it is unhealthy.
+    pub fn spawn_health_check(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            loop {
+                sleep(interval).await;
+                for endpoint in self.endpoints.iter() {
+                    let idle_connections = endpoint.pool.state().idle_connections;
+                    let connections =
+                        join_all((0..idle_connections).map(|_| endpoint.pool.get())).await;
+                    let healthy = connections.iter().filter(|c| c.is_ok()).count();
+                    drop(connections);
+                    debug!(
+                        "RPC pool health check for {}: {}/{} connections healthy",
+                        endpoint.url, healthy, idle_connections
+                    );
+                }
+            }
+        });
+    }
+
+    /// Endpoint indices in round-robin order, endpoints which are down last
+    /// so that they are only tried when no other endpoint is available.
+    fn endpoint_order(&self) -> Vec<usize> {
+        let len = self.endpoints.len();
+        let start = self.next_endpoint.fetch_add(1, Ordering::Relaxed) % len;
+        let (mut available, down): (Vec<usize>, Vec<usize>) = (0..len)
+            .map(|i| (start + i) % len)
+            .partition(|i| self.endpoints[*i].state.is_available());
+        available.extend(down);
+        available
     }
 
     pub async fn get_connection(
         &self,
     ) -> Result<PooledConnection<'_, SolanaConnectionManager<R>>, PoolError> {
-        self.pool
-            .get()
-            .await
-            .map_err(|e| PoolError::Pool(e.to_string()))
+        let start = Instant::now();
+        let mut last_error = None;
+        let mut exhausted = true;
+        for index in self.endpoint_order() {
+            let endpoint = &self.endpoints[index];
+            match endpoint.pool.get().await {
+                Ok(conn) => {
+                    observe_rpc_pool_wait(start.elapsed());
+                    return Ok(conn);
+                }
+                // All connections of the endpoint are checked out. Failed
+                // connections were already recorded when validating them.
+                Err(RunError::TimedOut) => {
+                    debug!("No connection to {} available", endpoint.url);
+                }
+                Err(e) => {
+                    exhausted = false;
+                    warn!("Failed to get connection to {}: {:?}", endpoint.url, e);
+                    if endpoint.state.record_failure() {
+                        warn!(
+                            "Marking RPC endpoint {} as down for {:?}",
+                            endpoint.url, endpoint.state.cooldown
+                        );
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        observe_rpc_pool_wait(start.elapsed());
+        if exhausted {
+            warn!(
+                "RPC pool exhausted, no connection available within {:?}",
+                self.acquire_timeout
+            );
+            increment_rpc_pool_exhausted();
+            return Err(PoolError::Exhausted(self.acquire_timeout));
+        }
+        Err(PoolError::Pool(format!(
+            "No RPC endpoint available: {:?}",
+            last_error
+        )))
     }
 
     pub async fn get_connection_with_retry(
@@ -89,15 +369,43 @@ impl<R: RpcConnection> SolanaRpcPool<R> {
     ) -> Result<PooledConnection<'_, SolanaConnectionManager<R>>, PoolError> {
         let mut retries = 0;
         loop {
-            match self.pool.get().await {
+            match self.get_connection().await {
                 Ok(conn) => return Ok(conn),
                 Err(e) if retries < max_retries => {
                     retries += 1;
                     eprintln!("Failed to get connection (attempt {}): {:?}", retries, e);
                     sleep(delay).await;
                 }
-                Err(e) => return Err(PoolError::Pool(e.to_string())),
+                Err(e) => return Err(e),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_marked_down_after_consecutive_failures() {
+        let state = EndpointState::new(Duration::from_secs(60));
+        for _ in 0..MAX_CONSECUTIVE_FAILURES - 1 {
+            assert!(!state.record_failure());
+            assert!(state.is_available());
+        }
+        assert!(state.record_failure());
+        assert!(!state.is_available());
+
+        state.record_success();
+        assert!(state.is_available());
+    }
+
+    #[test]
+    fn test_endpoint_available_after_cooldown() {
+        let state = EndpointState::new(Duration::ZERO);
+        for _ in 0..MAX_CONSECUTIVE_FAILURES {
+            state.record_failure();
+        }
+        assert!(state.is_available());
+    }
+}
@@ -1,10 +1,14 @@
-use crate::config::ExternalServicesConfig;
+use crate::config::{ExternalServicesConfig, RetryPolicy, MAX_COMPUTE_UNIT_LIMIT};
 use crate::ForesterConfig;
 use account_compression::initialize_address_merkle_tree::Pubkey;
 use config::Config;
+use light_test_utils::forester_epoch::TreeType;
+use log::warn;
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::signature::{Keypair, Signer};
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
+use std::time::Duration;
 use std::{env, fmt};
 
 const REGISTRY_PUBKEY: &str = "Lighton6oQpVkeewmo2mcPTQQp7kYHr4fWpAgJyEmDX";
@@ -12,10 +16,13 @@ const REGISTRY_PUBKEY: &str = "Lighton6oQpVkeewmo2mcPTQQp7kYHr4fWpAgJyEmDX";
 pub enum SettingsKey {
     Payer,
     RpcUrl,
+    FallbackRpcUrls,
     WsRpcUrl,
     IndexerUrl,
     ProverUrl,
     PhotonApiKey,
+    RemoteSignerUrl,
+    IndexerWsUrl,
     IndexerBatchSize,
     IndexerMaxConcurrentBatches,
     TransactionBatchSize,
@@ -24,6 +31,59 @@ pub enum SettingsKey {
     CULimit,
     RpcPoolSize,
     SlotUpdateIntervalSeconds,
+    LogEligibilitySummary,
+    DryRun,
+    BatchSizing,
+    ShutdownGracePeriodSeconds,
+    MaxQueueItemAgeSlots,
+    ValidateProofRoots,
+    RpcMaxLatencyMs,
+    RpcHealthCheckIntervalSeconds,
+    EnableWorkProcessing,
+    EnableRollover,
+    EnableReporting,
+    RpcEndpointCooldownSeconds,
+    MaxTps,
+    PrioritizeFullQueues,
+    WorkCommitment,
+    RolloverCommitment,
+    MaxTransactionsPerEpoch,
+    RetryPolicy,
+    RetryBaseDelayMs,
+    RetryFactor,
+    RetryMaxDelayMs,
+    TreeType,
+    MaxProofRootSeqLag,
+    QueueMetricsIntervalSeconds,
+    ProofFetchTimeoutSeconds,
+    MinQueueItems,
+    PhaseEndMarginSlots,
+    MaxConcurrentBatchesPerTree,
+    WaitForEligibleSlot,
+    TreeDiscoveryIntervalSeconds,
+    MaxConcurrentEpochs,
+    ProofBatchSize,
+    ForceRegister,
+    CUPrice,
+    MetricsPort,
+    HealthStalenessSeconds,
+    NonceAccount,
+    NonceAuthority,
+    DeadLetterPath,
+    RolloverConfirmTimeoutSeconds,
+    RolloverConfirmPollIntervalMs,
+    RegistrationJitterSlots,
+    Observer,
+    RpcPoolAcquireTimeoutMs,
+    ProtocolConfigRefreshIntervalSeconds,
+    AddressMaxConcurrentBatches,
+    StateMaxConcurrentBatches,
+    RunDurationSeconds,
+    SerialTrees,
+    MaxConcurrentQueueTasks,
+    MinPayerBalance,
+    RegistryPubkey,
+    CatchUp,
 }
 
 impl Display for SettingsKey {
@@ -34,10 +94,13 @@ impl Display for SettingsKey {
             match self {
                 SettingsKey::Payer => "PAYER",
                 SettingsKey::RpcUrl => "RPC_URL",
+                SettingsKey::FallbackRpcUrls => "FALLBACK_RPC_URLS",
                 SettingsKey::WsRpcUrl => "WS_RPC_URL",
                 SettingsKey::IndexerUrl => "INDEXER_URL",
                 SettingsKey::ProverUrl => "PROVER_URL",
                 SettingsKey::PhotonApiKey => "PHOTON_API_KEY",
+                SettingsKey::RemoteSignerUrl => "REMOTE_SIGNER_URL",
+                SettingsKey::IndexerWsUrl => "INDEXER_WS_URL",
                 SettingsKey::IndexerBatchSize => "INDEXER_BATCH_SIZE",
                 SettingsKey::IndexerMaxConcurrentBatches => "INDEXER_MAX_CONCURRENT_BATCHES",
                 SettingsKey::TransactionBatchSize => "TRANSACTION_BATCH_SIZE",
@@ -47,6 +110,60 @@ impl Display for SettingsKey {
                 SettingsKey::CULimit => "CU_LIMIT",
                 SettingsKey::RpcPoolSize => "RPC_POOL_SIZE",
                 SettingsKey::SlotUpdateIntervalSeconds => "SLOT_UPDATE_INTERVAL_SECONDS",
+                SettingsKey::LogEligibilitySummary => "LOG_ELIGIBILITY_SUMMARY",
+                SettingsKey::DryRun => "DRY_RUN",
+                SettingsKey::BatchSizing => "BATCH_SIZING",
+                SettingsKey::ShutdownGracePeriodSeconds => "SHUTDOWN_GRACE_PERIOD_SECONDS",
+                SettingsKey::MaxQueueItemAgeSlots => "MAX_QUEUE_ITEM_AGE_SLOTS",
+                SettingsKey::ValidateProofRoots => "VALIDATE_PROOF_ROOTS",
+                SettingsKey::RpcMaxLatencyMs => "RPC_MAX_LATENCY_MS",
+                SettingsKey::RpcHealthCheckIntervalSeconds => "RPC_HEALTH_CHECK_INTERVAL_SECONDS",
+                SettingsKey::EnableWorkProcessing => "ENABLE_WORK_PROCESSING",
+                SettingsKey::EnableRollover => "ENABLE_ROLLOVER",
+                SettingsKey::EnableReporting => "ENABLE_REPORTING",
+                SettingsKey::RpcEndpointCooldownSeconds => "RPC_ENDPOINT_COOLDOWN_SECONDS",
+                SettingsKey::MaxTps => "MAX_TPS",
+                SettingsKey::PrioritizeFullQueues => "PRIORITIZE_FULL_QUEUES",
+                SettingsKey::WorkCommitment => "WORK_COMMITMENT",
+                SettingsKey::RolloverCommitment => "ROLLOVER_COMMITMENT",
+                SettingsKey::MaxTransactionsPerEpoch => "MAX_TRANSACTIONS_PER_EPOCH",
+                SettingsKey::RetryPolicy => "RETRY_POLICY",
+                SettingsKey::RetryBaseDelayMs => "RETRY_BASE_DELAY_MS",
+                SettingsKey::RetryFactor => "RETRY_FACTOR",
+                SettingsKey::RetryMaxDelayMs => "RETRY_MAX_DELAY_MS",
+                SettingsKey::TreeType => "TREE_TYPE",
+                SettingsKey::MaxProofRootSeqLag => "MAX_PROOF_ROOT_SEQ_LAG",
+                SettingsKey::QueueMetricsIntervalSeconds => "QUEUE_METRICS_INTERVAL_SECONDS",
+                SettingsKey::ProofFetchTimeoutSeconds => "PROOF_FETCH_TIMEOUT_SECONDS",
+                SettingsKey::MinQueueItems => "MIN_QUEUE_ITEMS",
+                SettingsKey::PhaseEndMarginSlots => "PHASE_END_MARGIN_SLOTS",
+                SettingsKey::MaxConcurrentBatchesPerTree => "MAX_CONCURRENT_BATCHES_PER_TREE",
+                SettingsKey::WaitForEligibleSlot => "WAIT_FOR_ELIGIBLE_SLOT",
+                SettingsKey::TreeDiscoveryIntervalSeconds => "TREE_DISCOVERY_INTERVAL_SECONDS",
+                SettingsKey::MaxConcurrentEpochs => "MAX_CONCURRENT_EPOCHS",
+                SettingsKey::ProofBatchSize => "PROOF_BATCH_SIZE",
+                SettingsKey::ForceRegister => "FORCE_REGISTER",
+                SettingsKey::CUPrice => "CU_PRICE",
+                SettingsKey::MetricsPort => "METRICS_PORT",
+                SettingsKey::HealthStalenessSeconds => "HEALTH_STALENESS_SECONDS",
+                SettingsKey::NonceAccount => "NONCE_ACCOUNT",
+                SettingsKey::NonceAuthority => "NONCE_AUTHORITY",
+                SettingsKey::DeadLetterPath => "DEAD_LETTER_PATH",
+                SettingsKey::RolloverConfirmTimeoutSeconds => "ROLLOVER_CONFIRM_TIMEOUT_SECONDS",
+                SettingsKey::RolloverConfirmPollIntervalMs => "ROLLOVER_CONFIRM_POLL_INTERVAL_MS",
+                SettingsKey::RegistrationJitterSlots => "REGISTRATION_JITTER_SLOTS",
+                SettingsKey::Observer => "OBSERVER",
+                SettingsKey::RpcPoolAcquireTimeoutMs => "RPC_POOL_ACQUIRE_TIMEOUT_MS",
+                SettingsKey::ProtocolConfigRefreshIntervalSeconds =>
+                    "PROTOCOL_CONFIG_REFRESH_INTERVAL_SECONDS",
+                SettingsKey::AddressMaxConcurrentBatches => "ADDRESS_MAX_CONCURRENT_BATCHES",
+                SettingsKey::StateMaxConcurrentBatches => "STATE_MAX_CONCURRENT_BATCHES",
+                SettingsKey::RunDurationSeconds => "RUN_DURATION_SECONDS",
+                SettingsKey::SerialTrees => "SERIAL_TREES",
+                SettingsKey::MaxConcurrentQueueTasks => "MAX_CONCURRENT_QUEUE_TASKS",
+                SettingsKey::MinPayerBalance => "MIN_PAYER_BALANCE",
+                SettingsKey::RegistryPubkey => "REGISTRY_PUBKEY",
+                SettingsKey::CatchUp => "CATCH_UP",
             }
         )
     }
@@ -79,7 +196,9 @@ pub fn init_config() -> ForesterConfig {
         .build()
         .unwrap();
 
-    let registry_pubkey = REGISTRY_PUBKEY.to_string();
+    let registry_pubkey = settings
+        .get_string(&SettingsKey::RegistryPubkey.to_string())
+        .unwrap_or_else(|_| REGISTRY_PUBKEY.to_string());
 
     let payer = settings
         .get_string(&SettingsKey::Payer.to_string())
@@ -90,6 +209,15 @@ pub fn init_config() -> ForesterConfig {
     let rpc_url = settings
         .get_string(&SettingsKey::RpcUrl.to_string())
         .expect("RPC_URL not found in config file or environment variables");
+    let fallback_rpc_urls = settings
+        .get_string(&SettingsKey::FallbackRpcUrls.to_string())
+        .map(|urls| {
+            urls.split(',')
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
     let ws_rpc_url = settings
         .get_string(&SettingsKey::WsRpcUrl.to_string())
         .expect("WS_RPC_URL not found in config file or environment variables");
@@ -102,6 +230,12 @@ pub fn init_config() -> ForesterConfig {
     let photon_api_key = settings
         .get_string(&SettingsKey::PhotonApiKey.to_string())
         .ok();
+    let remote_signer_url = settings
+        .get_string(&SettingsKey::RemoteSignerUrl.to_string())
+        .ok();
+    let indexer_ws_url = settings
+        .get_string(&SettingsKey::IndexerWsUrl.to_string())
+        .ok();
 
     let indexer_batch_size = settings
         .get_int(&SettingsKey::IndexerBatchSize.to_string())
@@ -126,6 +260,15 @@ pub fn init_config() -> ForesterConfig {
     let cu_limit = settings
         .get_int(&SettingsKey::CULimit.to_string())
         .expect("CU_LIMIT not found in config file or environment variables");
+    let cu_limit = if cu_limit > MAX_COMPUTE_UNIT_LIMIT as i64 {
+        warn!(
+            "CU_LIMIT {} exceeds the maximum of {}, using the maximum",
+            cu_limit, MAX_COMPUTE_UNIT_LIMIT
+        );
+        MAX_COMPUTE_UNIT_LIMIT as i64
+    } else {
+        cu_limit
+    };
     let rpc_pool_size = settings
         .get_int(&SettingsKey::CULimit.to_string())
         .expect("RPC_POOL_SIZE not found in config file or environment variables");
@@ -134,16 +277,280 @@ pub fn init_config() -> ForesterConfig {
         .get_int(&SettingsKey::SlotUpdateIntervalSeconds.to_string())
         .expect("SLOT_UPDATE_INTERVAL_SECONDS not found in config file or environment variables");
 
-    ForesterConfig {
+    let log_eligibility_summary = settings
+        .get_bool(&SettingsKey::LogEligibilitySummary.to_string())
+        .unwrap_or(false);
+
+    let dry_run = settings
+        .get_bool(&SettingsKey::DryRun.to_string())
+        .unwrap_or(false);
+
+    let batch_sizing = settings
+        .get_string(&SettingsKey::BatchSizing.to_string())
+        .map(|batch_sizing| {
+            batch_sizing
+                .parse()
+                .expect("BATCH_SIZING must be either `fixed` or `pack_to_limit`")
+        })
+        .unwrap_or_default();
+
+    let shutdown_grace_period_seconds = settings
+        .get_int(&SettingsKey::ShutdownGracePeriodSeconds.to_string())
+        .unwrap_or(30) as u64;
+
+    let max_queue_item_age_slots = settings
+        .get_int(&SettingsKey::MaxQueueItemAgeSlots.to_string())
+        .ok()
+        .map(|max_age| max_age as u64);
+
+    let validate_proof_roots = settings
+        .get_bool(&SettingsKey::ValidateProofRoots.to_string())
+        .unwrap_or(false);
+
+    let rpc_max_latency_ms = settings
+        .get_int(&SettingsKey::RpcMaxLatencyMs.to_string())
+        .unwrap_or(2000) as u64;
+
+    let rpc_health_check_interval_seconds = settings
+        .get_int(&SettingsKey::RpcHealthCheckIntervalSeconds.to_string())
+        .unwrap_or(30) as u64;
+
+    let enable_work_processing = settings
+        .get_bool(&SettingsKey::EnableWorkProcessing.to_string())
+        .unwrap_or(true);
+
+    let enable_rollover = settings
+        .get_bool(&SettingsKey::EnableRollover.to_string())
+        .unwrap_or(true);
+
+    let enable_reporting = settings
+        .get_bool(&SettingsKey::EnableReporting.to_string())
+        .unwrap_or(true);
+
+    let rpc_endpoint_cooldown_seconds = settings
+        .get_int(&SettingsKey::RpcEndpointCooldownSeconds.to_string())
+        .unwrap_or(30) as u64;
+
+    let max_tps = settings
+        .get_int(&SettingsKey::MaxTps.to_string())
+        .ok()
+        .map(|max_tps| max_tps as u32);
+
+    let prioritize_full_queues = settings
+        .get_bool(&SettingsKey::PrioritizeFullQueues.to_string())
+        .unwrap_or(false);
+
+    let work_commitment = settings
+        .get_string(&SettingsKey::WorkCommitment.to_string())
+        .map(|commitment| commitment.parse().expect("Invalid WORK_COMMITMENT"))
+        .unwrap_or(CommitmentConfig::confirmed());
+
+    let rollover_commitment = settings
+        .get_string(&SettingsKey::RolloverCommitment.to_string())
+        .map(|commitment| commitment.parse().expect("Invalid ROLLOVER_COMMITMENT"))
+        .unwrap_or(CommitmentConfig::confirmed());
+
+    let max_transactions_per_epoch = settings
+        .get_int(&SettingsKey::MaxTransactionsPerEpoch.to_string())
+        .ok()
+        .map(|max_transactions| max_transactions as usize);
+
+    let retry_policy = match settings
+        .get_string(&SettingsKey::RetryPolicy.to_string())
+        .as_deref()
+    {
+        Ok("deterministic") => RetryPolicy::Deterministic {
+            base_delay: Duration::from_millis(
+                settings
+                    .get_int(&SettingsKey::RetryBaseDelayMs.to_string())
+                    .unwrap_or(100) as u64,
+            ),
+            factor: settings
+                .get_int(&SettingsKey::RetryFactor.to_string())
+                .unwrap_or(2) as u32,
+            max: Duration::from_millis(
+                settings
+                    .get_int(&SettingsKey::RetryMaxDelayMs.to_string())
+                    .unwrap_or(10_000) as u64,
+            ),
+        },
+        Ok("jittered") | Err(_) => RetryPolicy::default(),
+        Ok(policy) => panic!(
+            "RETRY_POLICY must be either `jittered` or `deterministic`, got `{}`",
+            policy
+        ),
+    };
+
+    let tree_type = settings
+        .get_string(&SettingsKey::TreeType.to_string())
+        .ok()
+        .map(|tree_type| match tree_type.as_str() {
+            "state" => TreeType::State,
+            "address" => TreeType::Address,
+            _ => panic!(
+                "TREE_TYPE must be either `state` or `address`, got `{}`",
+                tree_type
+            ),
+        });
+
+    let max_proof_root_seq_lag = settings
+        .get_int(&SettingsKey::MaxProofRootSeqLag.to_string())
+        .ok()
+        .map(|max_lag| max_lag as u64);
+
+    let queue_metrics_interval_seconds = settings
+        .get_int(&SettingsKey::QueueMetricsIntervalSeconds.to_string())
+        .unwrap_or(60) as u64;
+
+    let proof_fetch_timeout_seconds = settings
+        .get_int(&SettingsKey::ProofFetchTimeoutSeconds.to_string())
+        .unwrap_or(30) as u64;
+
+    let min_queue_items = settings
+        .get_int(&SettingsKey::MinQueueItems.to_string())
+        .unwrap_or(0) as usize;
+
+    let phase_end_margin_slots = settings
+        .get_int(&SettingsKey::PhaseEndMarginSlots.to_string())
+        .unwrap_or(0) as u64;
+
+    let max_concurrent_batches_per_tree = settings
+        .get_int(&SettingsKey::MaxConcurrentBatchesPerTree.to_string())
+        .ok()
+        .map(|max_batches| max_batches as usize);
+
+    let wait_for_eligible_slot = settings
+        .get_bool(&SettingsKey::WaitForEligibleSlot.to_string())
+        .unwrap_or(false);
+
+    let tree_discovery_interval_seconds = settings
+        .get_int(&SettingsKey::TreeDiscoveryIntervalSeconds.to_string())
+        .unwrap_or(300) as u64;
+
+    let max_concurrent_epochs = settings
+        .get_int(&SettingsKey::MaxConcurrentEpochs.to_string())
+        .unwrap_or(3) as usize;
+
+    let proof_batch_size = settings
+        .get_int(&SettingsKey::ProofBatchSize.to_string())
+        .unwrap_or(indexer_batch_size) as usize;
+
+    let force_register = settings
+        .get_bool(&SettingsKey::ForceRegister.to_string())
+        .unwrap_or(false);
+
+    let cu_price = settings
+        .get_int(&SettingsKey::CUPrice.to_string())
+        .ok()
+        .map(|cu_price| cu_price as u64);
+
+    let metrics_port = settings
+        .get_int(&SettingsKey::MetricsPort.to_string())
+        .ok()
+        .map(|port| port as u16);
+
+    let health_staleness_seconds = settings
+        .get_int(&SettingsKey::HealthStalenessSeconds.to_string())
+        .unwrap_or(300) as u64;
+
+    let nonce_account = settings
+        .get_string(&SettingsKey::NonceAccount.to_string())
+        .ok()
+        .map(|nonce_account| Pubkey::from_str(&nonce_account).unwrap());
+
+    let nonce_authority = settings
+        .get_string(&SettingsKey::NonceAuthority.to_string())
+        .ok()
+        .map(|nonce_authority| {
+            let nonce_authority: Vec<u8> = convert(&nonce_authority).unwrap();
+            Keypair::from_bytes(&nonce_authority).unwrap()
+        });
+
+    let dead_letter_path = settings
+        .get_string(&SettingsKey::DeadLetterPath.to_string())
+        .ok();
+
+    let rollover_confirm_timeout_seconds = settings
+        .get_int(&SettingsKey::RolloverConfirmTimeoutSeconds.to_string())
+        .unwrap_or(60) as u64;
+
+    let rollover_confirm_poll_interval_ms = settings
+        .get_int(&SettingsKey::RolloverConfirmPollIntervalMs.to_string())
+        .unwrap_or(500) as u64;
+
+    let registration_jitter_slots = settings
+        .get_int(&SettingsKey::RegistrationJitterSlots.to_string())
+        .unwrap_or(0) as u64;
+
+    let observer = settings
+        .get_bool(&SettingsKey::Observer.to_string())
+        .unwrap_or(false);
+
+    let rpc_pool_acquire_timeout_ms = settings
+        .get_int(&SettingsKey::RpcPoolAcquireTimeoutMs.to_string())
+        .unwrap_or(15000) as u64;
+
+    let protocol_config_refresh_interval_seconds = settings
+        .get_int(&SettingsKey::ProtocolConfigRefreshIntervalSeconds.to_string())
+        .unwrap_or(300) as u64;
+
+    let address_max_concurrent_batches = settings
+        .get_int(&SettingsKey::AddressMaxConcurrentBatches.to_string())
+        .ok()
+        .map(|max_batches| max_batches as usize);
+
+    let state_max_concurrent_batches = settings
+        .get_int(&SettingsKey::StateMaxConcurrentBatches.to_string())
+        .ok()
+        .map(|max_batches| max_batches as usize);
+
+    let run_duration_seconds = settings
+        .get_int(&SettingsKey::RunDurationSeconds.to_string())
+        .ok()
+        .map(|seconds| seconds as u64);
+
+    let serial_trees = settings
+        .get_string(&SettingsKey::SerialTrees.to_string())
+        .map(|trees| {
+            trees
+                .split(',')
+                .map(str::trim)
+                .filter(|tree| !tree.is_empty())
+                .map(|tree| {
+                    Pubkey::from_str(tree)
+                        .unwrap_or_else(|_| panic!("Invalid pubkey in SERIAL_TREES: {}", tree))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let max_concurrent_queue_tasks = settings
+        .get_int(&SettingsKey::MaxConcurrentQueueTasks.to_string())
+        .unwrap_or(32) as usize;
+
+    let min_payer_balance = settings
+        .get_int(&SettingsKey::MinPayerBalance.to_string())
+        .ok()
+        .map(|lamports| lamports as u64);
+
+    let catch_up = settings
+        .get_bool(&SettingsKey::CatchUp.to_string())
+        .unwrap_or(false);
+
+    let config = ForesterConfig {
         external_services: ExternalServicesConfig {
             rpc_url,
+            fallback_rpc_urls,
             ws_rpc_url,
             indexer_url,
             prover_url,
             photon_api_key,
+            remote_signer_url,
+            indexer_ws_url,
             derivation: payer.pubkey().to_string(),
         },
-        registry_pubkey: Pubkey::from_str(&registry_pubkey).unwrap(),
+        registry_pubkey: Pubkey::from_str(&registry_pubkey)
+            .unwrap_or_else(|_| panic!("Invalid REGISTRY_PUBKEY: {}", registry_pubkey)),
         payer_keypair: payer,
         indexer_batch_size: indexer_batch_size as usize,
         indexer_max_concurrent_batches: indexer_max_concurrent_batches as usize,
@@ -153,7 +560,61 @@ pub fn init_config() -> ForesterConfig {
         cu_limit: cu_limit as u32,
         rpc_pool_size: rpc_pool_size as usize,
         slot_update_interval_seconds: slot_update_interval_seconds as u64,
+        log_eligibility_summary,
+        dry_run,
+        batch_sizing,
+        shutdown_grace_period_seconds,
+        max_queue_item_age_slots,
+        validate_proof_roots,
+        rpc_max_latency_ms,
+        rpc_health_check_interval_seconds,
+        enable_work_processing,
+        enable_rollover,
+        enable_reporting,
+        rpc_endpoint_cooldown_seconds,
+        max_tps,
+        prioritize_full_queues,
+        work_commitment,
+        rollover_commitment,
+        max_transactions_per_epoch,
+        retry_policy,
+        tree_type,
+        max_proof_root_seq_lag,
+        queue_metrics_interval_seconds,
+        proof_fetch_timeout_seconds,
+        min_queue_items,
+        phase_end_margin_slots,
+        max_concurrent_batches_per_tree,
+        wait_for_eligible_slot,
+        tree_discovery_interval_seconds,
+        max_concurrent_epochs,
+        proof_batch_size,
+        force_register,
+        cu_price,
+        metrics_port,
+        health_staleness_seconds,
+        nonce_account,
+        nonce_authority,
+        dead_letter_path,
+        rollover_confirm_timeout_seconds,
+        rollover_confirm_poll_interval_ms,
+        registration_jitter_slots,
+        observer,
+        rpc_pool_acquire_timeout_ms,
+        tx_observer: None,
+        protocol_config_refresh_interval_seconds,
+        address_max_concurrent_batches,
+        state_max_concurrent_batches,
+        run_duration_seconds,
+        serial_trees,
+        max_concurrent_queue_tasks,
+        min_payer_balance,
+        catch_up,
         address_tree_data: vec![],
         state_tree_data: vec![],
+    };
+    if let Err(e) = config.validate() {
+        panic!("Invalid config: {}", e);
     }
+    config
 }
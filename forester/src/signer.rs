@@ -0,0 +1,188 @@
+use crate::errors::ForesterError;
+use crate::ForesterConfig;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use solana_sdk::hash::Hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::Transaction;
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Signs transactions on behalf of the forester authority.
+#[async_trait::async_trait]
+pub trait ForesterSigner: Debug + Send + Sync {
+    fn pubkey(&self) -> Pubkey;
+
+    async fn sign_message(&self, message: &[u8]) -> crate::Result<Signature>;
+}
+
+#[async_trait::async_trait]
+impl ForesterSigner for Keypair {
+    fn pubkey(&self) -> Pubkey {
+        Signer::pubkey(self)
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> crate::Result<Signature> {
+        Ok(Signer::sign_message(self, message))
+    }
+}
+
+#[derive(Deserialize)]
+struct PubkeyResponse {
+    pubkey: String,
+}
+
+#[derive(Serialize)]
+struct SignRequest {
+    pubkey: String,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct SignResponse {
+    signature: String,
+}
+
+/// Signer backed by a remote signing service, e.g. in front of an HSM.
+///
+/// The service is expected to serve `GET {endpoint}/pubkey`, returning
+/// `{"pubkey": "<base58>"}`, and `POST {endpoint}/sign` with
+/// `{"pubkey": "<base58>", "message": "<base64>"}`, returning
+/// `{"signature": "<base58>"}`.
+#[derive(Debug)]
+pub struct RemoteSigner {
+    endpoint: String,
+    pubkey: Pubkey,
+    client: reqwest::Client,
+}
+
+impl RemoteSigner {
+    /// Creates a signer for the key served by `endpoint`.
+    pub async fn connect(endpoint: &str) -> crate::Result<Self> {
+        let endpoint = endpoint.trim_end_matches('/').to_string();
+        let client = reqwest::Client::new();
+        let response: PubkeyResponse = client
+            .get(format!("{}/pubkey", endpoint))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ForesterError::SignerError(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| ForesterError::SignerError(e.to_string()))?;
+        let pubkey = Pubkey::from_str(&response.pubkey)
+            .map_err(|e| ForesterError::SignerError(e.to_string()))?;
+        Ok(Self {
+            endpoint,
+            pubkey,
+            client,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ForesterSigner for RemoteSigner {
+    fn pubkey(&self) -> Pubkey {
+        self.pubkey
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> crate::Result<Signature> {
+        let request = SignRequest {
+            pubkey: self.pubkey.to_string(),
+            message: STANDARD.encode(message),
+        };
+        let response: SignResponse = self
+            .client
+            .post(format!("{}/sign", self.endpoint))
+            .json(&request)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ForesterError::SignerError(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| ForesterError::SignerError(e.to_string()))?;
+        let signature = Signature::from_str(&response.signature)
+            .map_err(|e| ForesterError::SignerError(e.to_string()))?;
+        if !signature.verify(self.pubkey.as_ref(), message) {
+            return Err(ForesterError::SignerError(
+                "Remote signer returned an invalid signature".to_string(),
+            ));
+        }
+        Ok(signature)
+    }
+}
+
+/// Uses the remote signer if `remote_signer_url` is configured, the payer
+/// keypair otherwise.
+pub async fn create_signer(config: &ForesterConfig) -> crate::Result<Arc<dyn ForesterSigner>> {
+    match &config.external_services.remote_signer_url {
+        Some(url) => Ok(Arc::new(RemoteSigner::connect(url).await?)),
+        None => Ok(Arc::new(config.payer_keypair.insecure_clone())),
+    }
+}
+
+/// Signs `transaction` with `signer` and the locally held `keypairs`, e.g.
+/// keypairs of accounts created by the transaction.
+pub async fn sign_transaction(
+    signer: &dyn ForesterSigner,
+    transaction: &mut Transaction,
+    keypairs: &[&Keypair],
+    recent_blockhash: Hash,
+) -> crate::Result<()> {
+    transaction
+        .try_partial_sign(keypairs, recent_blockhash)
+        .map_err(|e| ForesterError::SignerError(e.to_string()))?;
+    let position = transaction
+        .get_signing_keypair_positions(&[signer.pubkey()])
+        .map_err(|e| ForesterError::SignerError(e.to_string()))?[0]
+        .ok_or_else(|| {
+            ForesterError::SignerError(format!(
+                "{} is not a signer of the transaction",
+                signer.pubkey()
+            ))
+        })?;
+    transaction.signatures[position] = signer.sign_message(&transaction.message_data()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_sdk::system_instruction;
+
+    #[tokio::test]
+    async fn test_sign_transaction_with_additional_keypairs() {
+        let signer = Keypair::new();
+        let new_account = Keypair::new();
+        let instruction = system_instruction::create_account(
+            &ForesterSigner::pubkey(&signer),
+            &Signer::pubkey(&new_account),
+            1,
+            0,
+            &Pubkey::new_unique(),
+        );
+        let mut transaction =
+            Transaction::new_with_payer(&[instruction], Some(&ForesterSigner::pubkey(&signer)));
+
+        sign_transaction(
+            &signer,
+            &mut transaction,
+            &[&new_account],
+            Hash::new_unique(),
+        )
+        .await
+        .unwrap();
+        transaction.verify().unwrap();
+
+        let other = Keypair::new();
+        assert!(
+            sign_transaction(&other, &mut transaction, &[], Hash::new_unique())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -1,19 +1,30 @@
+use crate::metrics::set_slot_tracker_drift;
+use crate::rpc_pool::SolanaRpcPool;
 use light_test_utils::rpc::rpc_connection::RpcConnection;
-use log::{debug, error};
-use std::sync::atomic::{AtomicU64, Ordering};
+use log::{debug, error, warn};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::time::UNIX_EPOCH;
 use std::{sync::Arc, time::SystemTime};
+use tokio::sync::Notify;
 use tokio::time::{sleep, Duration};
 
 pub fn slot_duration() -> Duration {
     Duration::from_nanos(solana_sdk::genesis_config::GenesisConfig::default().ns_per_slot() as u64)
 }
 
+/// Drift in slots above which reconciliation logs a warning.
+const DRIFT_WARNING_THRESHOLD: u64 = 10;
+
 #[derive(Debug)]
 pub struct SlotTracker {
     last_known_slot: AtomicU64,
     last_update_time: AtomicU64,
+    /// Estimated minus actual slot, measured at the last update.
+    drift: AtomicI64,
     update_interval: Duration,
+    /// Wakes the waits for a slot, which otherwise sleep for the estimated
+    /// time until the slot is reached, when the estimate is reconciled.
+    updated: Notify,
 }
 
 impl SlotTracker {
@@ -25,17 +36,23 @@ impl SlotTracker {
         Self {
             last_known_slot: AtomicU64::new(initial_slot),
             last_update_time: AtomicU64::new(now),
+            drift: AtomicI64::new(0),
             update_interval,
+            updated: Notify::new(),
         }
     }
 
     pub fn update(&self, new_slot: u64) {
+        let drift = self.estimated_current_slot() as i64 - new_slot as i64;
+        self.drift.store(drift, Ordering::Release);
+        set_slot_tracker_drift(drift);
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis() as u64;
         self.last_known_slot.store(new_slot, Ordering::Release);
         self.last_update_time.store(now, Ordering::Release);
+        self.updated.notify_waiters();
     }
 
     pub fn estimated_current_slot(&self) -> u64 {
@@ -50,12 +67,62 @@ impl SlotTracker {
         last_slot + estimated_slots as u64
     }
 
-    pub async fn run<R: RpcConnection + Send + 'static>(self: Arc<Self>, rpc: &mut R) {
+    /// Time since the estimate was last reconciled with the actual slot.
+    pub fn since_last_update(&self) -> Duration {
+        let last_update = self.last_update_time.load(Ordering::Acquire);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        Duration::from_millis(now.saturating_sub(last_update))
+    }
+
+    /// The estimated slot, or the actual slot if the estimate wasn't
+    /// reconciled within `max_age`. Frequent callers, e.g. one per queue
+    /// update, thereby share a `getSlot` request per `max_age`.
+    pub async fn current_slot<R: RpcConnection>(
+        &self,
+        rpc: &mut R,
+        max_age: Duration,
+    ) -> crate::Result<u64> {
+        if self.since_last_update() <= max_age {
+            return Ok(self.estimated_current_slot());
+        }
+        let slot = rpc.get_slot().await?;
+        self.update(slot);
+        Ok(slot)
+    }
+
+    /// Difference in slots between the estimated and the actual slot at the
+    /// last update. Positive if the estimate was ahead.
+    pub fn drift(&self) -> i64 {
+        self.drift.load(Ordering::Acquire)
+    }
+
+    /// Reconciles the estimate with the actual slot every `update_interval`.
+    /// A connection is checked out of the pool for every reconciliation so
+    /// that an unhealthy endpoint does not stall the tracker.
+    pub async fn run<R: RpcConnection + Send + 'static>(
+        self: Arc<Self>,
+        rpc_pool: Arc<SolanaRpcPool<R>>,
+    ) {
         loop {
-            match rpc.get_slot().await {
+            let slot = match rpc_pool.get_connection().await {
+                Ok(mut rpc) => rpc.get_slot().await.map_err(|e| e.into()),
+                Err(e) => Err(e),
+            };
+            match slot {
                 Ok(slot) => {
                     self.update(slot);
-                    debug!("Updated slot to {}", slot);
+                    let drift = self.drift();
+                    if drift.unsigned_abs() > DRIFT_WARNING_THRESHOLD {
+                        warn!(
+                            "Corrected slot estimate to {} (drift: {} slots)",
+                            slot, drift
+                        );
+                    } else {
+                        debug!("Updated slot to {} (drift: {} slots)", slot, drift);
+                    }
                 }
                 Err(e) => error!("Failed to get slot: {:?}", e),
             }
@@ -64,6 +131,24 @@ impl SlotTracker {
     }
 }
 
+/// Sleeps until the estimated slot reaches `target_slot`, without holding
+/// an RPC connection while waiting.
+pub async fn wait_until_estimated_slot_reached(slot_tracker: &SlotTracker, target_slot: u64) {
+    loop {
+        // Created before the check so that an update in between isn't missed.
+        let updated = slot_tracker.updated.notified();
+        let current_estimated_slot = slot_tracker.estimated_current_slot();
+        if current_estimated_slot >= target_slot {
+            return;
+        }
+        let slots_to_wait = target_slot - current_estimated_slot;
+        tokio::select! {
+            _ = sleep(slot_duration() * slots_to_wait as u32) => {}
+            _ = updated => {}
+        }
+    }
+}
+
 pub async fn wait_until_slot_reached<R: RpcConnection>(
     rpc: &mut R,
     slot_tracker: &Arc<SlotTracker>,
@@ -72,11 +157,14 @@ pub async fn wait_until_slot_reached<R: RpcConnection>(
     debug!("Waiting for slot {}", target_slot);
 
     loop {
+        // Created before the check so that an update in between isn't missed.
+        let updated = slot_tracker.updated.notified();
         let current_estimated_slot = slot_tracker.estimated_current_slot();
 
         if current_estimated_slot >= target_slot {
             // Double-check with actual RPC call
             let actual_slot = rpc.get_slot().await?;
+            slot_tracker.update(actual_slot);
             if actual_slot >= target_slot {
                 break;
             }
@@ -89,9 +177,61 @@ pub async fn wait_until_slot_reached<R: RpcConnection>(
             slot_duration()
         };
 
-        sleep(sleep_duration).await;
+        tokio::select! {
+            _ = sleep(sleep_duration) => {}
+            _ = updated => {}
+        }
     }
 
     debug!("Slot {} reached", target_slot);
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_update_measures_drift() {
+        let slot_tracker = SlotTracker::new(100, Duration::from_secs(1));
+        let elapsed = slot_duration() * 10;
+        slot_tracker
+            .last_update_time
+            .fetch_sub(elapsed.as_millis() as u64, Ordering::AcqRel);
+        assert_eq!(slot_tracker.estimated_current_slot(), 110);
+
+        slot_tracker.update(105);
+        assert_eq!(slot_tracker.drift(), 5);
+        assert_eq!(slot_tracker.estimated_current_slot(), 105);
+
+        slot_tracker.update(107);
+        assert_eq!(slot_tracker.drift(), -2);
+    }
+
+    #[test]
+    fn test_since_last_update() {
+        let slot_tracker = SlotTracker::new(100, Duration::from_secs(1));
+        slot_tracker
+            .last_update_time
+            .fetch_sub(5_000, Ordering::AcqRel);
+        assert!(slot_tracker.since_last_update() >= Duration::from_secs(5));
+
+        slot_tracker.update(150);
+        assert!(slot_tracker.since_last_update() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_wait_is_woken_by_update() {
+        let slot_tracker = Arc::new(SlotTracker::new(100, Duration::from_secs(1)));
+        let wait = tokio::spawn({
+            let slot_tracker = slot_tracker.clone();
+            async move { wait_until_estimated_slot_reached(&slot_tracker, 1_000).await }
+        });
+        sleep(Duration::from_millis(10)).await;
+        slot_tracker.update(1_000);
+        tokio::time::timeout(Duration::from_secs(1), wait)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! Helpers shared by the unit tests.
+
+use crate::config::{BatchSizing, ExternalServicesConfig, ForesterConfig, RetryPolicy};
+use lazy_static::lazy_static;
+use light_test_utils::rpc::errors::RpcError;
+use light_test_utils::rpc::rpc_connection::RpcConnection;
+use light_test_utils::transaction_params::TransactionParams;
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_sdk::account::{Account, AccountSharedData};
+use solana_sdk::clock::Slot;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::hash::Hash;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::{Transaction, TransactionError};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub(crate) fn test_config() -> ForesterConfig {
+    let payer_keypair = Keypair::new();
+    ForesterConfig {
+        external_services: ExternalServicesConfig {
+            rpc_url: "http://localhost:8899".to_string(),
+            fallback_rpc_urls: vec![],
+            ws_rpc_url: "ws://localhost:8900".to_string(),
+            indexer_url: "http://localhost:8784".to_string(),
+            prover_url: "http://localhost:3001".to_string(),
+            photon_api_key: None,
+            remote_signer_url: None,
+            indexer_ws_url: None,
+            derivation: payer_keypair.pubkey().to_string(),
+        },
+        registry_pubkey: light_registry::ID,
+        payer_keypair,
+        cu_limit: 1_000_000,
+        indexer_batch_size: 50,
+        indexer_max_concurrent_batches: 10,
+        transaction_batch_size: 1,
+        transaction_max_concurrent_batches: 20,
+        max_retries: 5,
+        rpc_pool_size: 20,
+        slot_update_interval_seconds: 10,
+        log_eligibility_summary: false,
+        dry_run: false,
+        batch_sizing: BatchSizing::Fixed,
+        shutdown_grace_period_seconds: 30,
+        max_queue_item_age_slots: None,
+        validate_proof_roots: false,
+        rpc_max_latency_ms: 2000,
+        rpc_health_check_interval_seconds: 30,
+        enable_work_processing: true,
+        enable_rollover: true,
+        enable_reporting: true,
+        rpc_endpoint_cooldown_seconds: 30,
+        max_tps: None,
+        prioritize_full_queues: false,
+        work_commitment: CommitmentConfig::confirmed(),
+        rollover_commitment: CommitmentConfig::confirmed(),
+        max_transactions_per_epoch: None,
+        retry_policy: RetryPolicy::default(),
+        tree_type: None,
+        max_proof_root_seq_lag: None,
+        queue_metrics_interval_seconds: 60,
+        proof_fetch_timeout_seconds: 30,
+        min_queue_items: 0,
+        phase_end_margin_slots: 0,
+        max_concurrent_batches_per_tree: None,
+        wait_for_eligible_slot: false,
+        tree_discovery_interval_seconds: 300,
+        max_concurrent_epochs: 3,
+        proof_batch_size: 50,
+        force_register: false,
+        cu_price: None,
+        metrics_port: None,
+        health_staleness_seconds: 300,
+        nonce_account: None,
+        nonce_authority: None,
+        dead_letter_path: None,
+        rollover_confirm_timeout_seconds: 60,
+        rollover_confirm_poll_interval_ms: 500,
+        registration_jitter_slots: 0,
+        observer: false,
+        rpc_pool_acquire_timeout_ms: 15000,
+        tx_observer: None,
+        protocol_config_refresh_interval_seconds: 300,
+        address_max_concurrent_batches: None,
+        state_max_concurrent_batches: None,
+        run_duration_seconds: None,
+        serial_trees: vec![],
+        max_concurrent_queue_tasks: 32,
+        min_payer_balance: None,
+        catch_up: false,
+        address_tree_data: vec![],
+        state_tree_data: vec![],
+    }
+}
+
+lazy_static! {
+    static ref MOCK_RPC_STATES: Mutex<HashMap<String, Arc<MockRpcState>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// State shared by all [`MockRpc`] connections created for the same url,
+/// so that tests can observe connections handed out by a pool.
+#[derive(Debug, Default)]
+pub(crate) struct MockRpcState {
+    pub slot: AtomicU64,
+    pub accounts: Mutex<HashMap<Pubkey, Account>>,
+    /// Transactions sent through any of the `process_transaction*` or
+    /// `send_transaction` methods.
+    pub sent_transactions: Mutex<Vec<Transaction>>,
+    pub simulated_transactions: AtomicUsize,
+    /// Errors returned by the next sends, in order, before anything is
+    /// recorded in `sent_transactions`.
+    pub send_errors: Mutex<VecDeque<RpcError>>,
+    /// Accounts added to `accounts` by the next successful send, standing in
+    /// for accounts created by the transaction.
+    pub created_accounts: Mutex<Vec<(Pubkey, Account)>>,
+}
+
+impl MockRpcState {
+    /// Registers a new state and returns the url to create connections to it
+    /// with.
+    pub fn register() -> (String, Arc<Self>) {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let url = format!("mock://{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let state = Arc::new(Self::default());
+        MOCK_RPC_STATES
+            .lock()
+            .unwrap()
+            .insert(url.clone(), state.clone());
+        (url, state)
+    }
+
+    pub fn sent_transactions(&self) -> usize {
+        self.sent_transactions.lock().unwrap().len()
+    }
+}
+
+/// In-memory [`RpcConnection`] which accepts every transaction without
+/// executing it.
+#[derive(Debug)]
+pub(crate) struct MockRpc {
+    state: Arc<MockRpcState>,
+    payer: Keypair,
+}
+
+impl MockRpc {
+    fn send(&self, transaction: Transaction) -> Result<Signature, RpcError> {
+        if let Some(error) = self.state.send_errors.lock().unwrap().pop_front() {
+            return Err(error);
+        }
+        let signature = transaction.signatures[0];
+        let created_accounts = std::mem::take(&mut *self.state.created_accounts.lock().unwrap());
+        self.state.accounts.lock().unwrap().extend(created_accounts);
+        self.state
+            .sent_transactions
+            .lock()
+            .unwrap()
+            .push(transaction);
+        Ok(signature)
+    }
+}
+
+impl RpcConnection for MockRpc {
+    fn new<U: ToString>(url: U, _commitment_config: Option<CommitmentConfig>) -> Self
+    where
+        Self: Sized,
+    {
+        let state = MOCK_RPC_STATES
+            .lock()
+            .unwrap()
+            .get(&url.to_string())
+            .cloned()
+            .expect("mock rpc state is not registered");
+        Self {
+            state,
+            payer: Keypair::new(),
+        }
+    }
+
+    fn get_program_accounts(
+        &self,
+        program_id: &Pubkey,
+    ) -> Result<Vec<(Pubkey, Account)>, RpcError> {
+        Ok(self
+            .state
+            .accounts
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, account)| account.owner == *program_id)
+            .map(|(pubkey, account)| (*pubkey, account.clone()))
+            .collect())
+    }
+
+    fn simulate_transaction(
+        &self,
+        _transaction: &Transaction,
+    ) -> Result<RpcSimulateTransactionResult, RpcError> {
+        self.state
+            .simulated_transactions
+            .fetch_add(1, Ordering::Relaxed);
+        Ok(RpcSimulateTransactionResult {
+            err: None,
+            logs: None,
+            accounts: None,
+            units_consumed: None,
+            return_data: None,
+            inner_instructions: None,
+        })
+    }
+
+    async fn process_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<Signature, RpcError> {
+        self.send(transaction)
+    }
+
+    async fn process_transaction_with_context(
+        &mut self,
+        transaction: Transaction,
+    ) -> Result<(Signature, Slot), RpcError> {
+        let slot = self.state.slot.load(Ordering::Relaxed);
+        Ok((self.send(transaction)?, slot))
+    }
+
+    async fn get_signature_status_with_commitment(
+        &mut self,
+        _signature: Signature,
+        _commitment: CommitmentConfig,
+    ) -> Result<Option<Result<(), TransactionError>>, RpcError> {
+        Ok(Some(Ok(())))
+    }
+
+    async fn create_and_send_transaction_with_event<T>(
+        &mut self,
+        _instruction: &[Instruction],
+        _authority: &Pubkey,
+        _signers: &[&Keypair],
+        _transaction_params: Option<TransactionParams>,
+    ) -> Result<Option<(T, Signature, Slot)>, RpcError>
+    where
+        T: borsh::BorshDeserialize + Send + Debug,
+    {
+        unimplemented!()
+    }
+
+    async fn confirm_transaction(&mut self, _signature: Signature) -> Result<bool, RpcError> {
+        Ok(true)
+    }
+
+    fn get_payer(&self) -> &Keypair {
+        &self.payer
+    }
+
+    async fn get_account(&mut self, address: Pubkey) -> Result<Option<Account>, RpcError> {
+        Ok(self.state.accounts.lock().unwrap().get(&address).cloned())
+    }
+
+    fn set_account(&mut self, address: &Pubkey, account: &AccountSharedData) {
+        self.state
+            .accounts
+            .lock()
+            .unwrap()
+            .insert(*address, account.clone().into());
+    }
+
+    async fn get_minimum_balance_for_rent_exemption(
+        &mut self,
+        _data_len: usize,
+    ) -> Result<u64, RpcError> {
+        Ok(0)
+    }
+
+    async fn airdrop_lamports(
+        &mut self,
+        _to: &Pubkey,
+        _lamports: u64,
+    ) -> Result<Signature, RpcError> {
+        Ok(Signature::default())
+    }
+
+    async fn get_balance(&mut self, pubkey: &Pubkey) -> Result<u64, RpcError> {
+        Ok(self
+            .state
+            .accounts
+            .lock()
+            .unwrap()
+            .get(pubkey)
+            .map_or(0, |account| account.lamports))
+    }
+
+    async fn get_latest_blockhash(&mut self) -> Result<Hash, RpcError> {
+        Ok(Hash::new_unique())
+    }
+
+    async fn get_slot(&mut self) -> Result<u64, RpcError> {
+        Ok(self.state.slot.load(Ordering::Relaxed))
+    }
+}
@@ -3,18 +3,36 @@ use account_compression::utils::check_discrimininator::check_discriminator;
 use account_compression::{AddressMerkleTreeAccount, MerkleTreeMetadata, StateMerkleTreeAccount};
 use borsh::BorshDeserialize;
 use light_test_utils::forester_epoch::{TreeAccounts, TreeType};
+use light_test_utils::rpc::errors::RpcError;
 use light_test_utils::rpc::rpc_connection::RpcConnection;
 use log::debug;
 use solana_sdk::account::Account;
 use solana_sdk::pubkey::Pubkey;
 
 pub async fn fetch_trees<R: RpcConnection>(rpc: &R) -> Vec<TreeAccounts> {
+    try_fetch_trees(rpc).await.unwrap()
+}
+
+/// Same as [`fetch_trees`], but returns rpc errors instead of panicking.
+pub async fn try_fetch_trees<R: RpcConnection>(rpc: &R) -> Result<Vec<TreeAccounts>, RpcError> {
     let program_id = account_compression::id();
     debug!("Fetching accounts for program: {}", program_id);
-    rpc.get_program_accounts(&program_id)
-        .unwrap()
+    Ok(rpc
+        .get_program_accounts(&program_id)?
         .into_iter()
         .filter_map(|(pubkey, account)| process_account(pubkey, account))
+        .collect())
+}
+
+/// Same as [`fetch_trees`], but only returns trees of `tree_type`.
+pub async fn fetch_trees_by_type<R: RpcConnection>(
+    rpc: &R,
+    tree_type: TreeType,
+) -> Vec<TreeAccounts> {
+    fetch_trees(rpc)
+        .await
+        .into_iter()
+        .filter(|tree| tree.tree_type == tree_type)
         .collect()
 }
 
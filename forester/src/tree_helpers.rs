@@ -0,0 +1,103 @@
+use crate::errors::ForesterError;
+use account_compression::{AddressMerkleTreeAccount, StateMerkleTreeAccount};
+use light_test_utils::forester_epoch::TreeType;
+use light_test_utils::rpc::rpc_connection::RpcConnection;
+use solana_sdk::pubkey::Pubkey;
+use std::mem;
+
+/// Leading fields of a concurrent Merkle tree, which address trees start
+/// with as well. They precede every field whose size depends on the tree
+/// height, so they can be read without knowing the height in advance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MerkleTreeHeader {
+    pub height: usize,
+    pub canopy_depth: usize,
+    pub next_index: usize,
+    pub sequence_number: usize,
+}
+
+impl MerkleTreeHeader {
+    /// Reads the header from the data of a tree account of `tree_type`,
+    /// `None` if the data is too short.
+    pub fn from_account_data(tree_type: TreeType, data: &[u8]) -> Option<Self> {
+        let offset = 8 + match tree_type {
+            TreeType::State => mem::size_of::<StateMerkleTreeAccount>(),
+            TreeType::Address => mem::size_of::<AddressMerkleTreeAccount>(),
+        };
+        let read_usize = |index: usize| {
+            let start = offset + index * mem::size_of::<usize>();
+            data.get(start..start + mem::size_of::<usize>())
+                .map(|bytes| usize::from_ne_bytes(bytes.try_into().unwrap()))
+        };
+        Some(Self {
+            height: read_usize(0)?,
+            canopy_depth: read_usize(1)?,
+            next_index: read_usize(2)?,
+            sequence_number: read_usize(3)?,
+        })
+    }
+}
+
+/// Fetches the tree account at `tree_pubkey` and reads its header.
+pub async fn fetch_merkle_tree_header<R: RpcConnection>(
+    rpc: &mut R,
+    tree_pubkey: Pubkey,
+    tree_type: TreeType,
+) -> crate::Result<MerkleTreeHeader> {
+    let account = rpc
+        .get_account(tree_pubkey)
+        .await?
+        .ok_or_else(|| ForesterError::Custom(format!("Tree {} not found", tree_pubkey)))?;
+    MerkleTreeHeader::from_account_data(tree_type, &account.data).ok_or_else(|| {
+        ForesterError::Custom(format!(
+            "Account {} is too small for a {:?} tree",
+            tree_pubkey, tree_type
+        ))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use light_concurrent_merkle_tree::zero_copy::ConcurrentMerkleTreeZeroCopyMut;
+    use light_concurrent_merkle_tree::ConcurrentMerkleTree;
+    use light_hasher::Poseidon;
+
+    #[test]
+    fn test_merkle_tree_header() {
+        const HEIGHT: usize = 20;
+        const CANOPY: usize = 4;
+        let offset = 8 + mem::size_of::<StateMerkleTreeAccount>();
+        let tree_size =
+            ConcurrentMerkleTree::<Poseidon, HEIGHT>::size_in_account(HEIGHT, 16, 16, CANOPY);
+        let mut data = vec![0; offset + tree_size];
+        {
+            let mut merkle_tree =
+                ConcurrentMerkleTreeZeroCopyMut::<Poseidon, HEIGHT>::from_bytes_zero_copy_init(
+                    &mut data[offset..],
+                    HEIGHT,
+                    CANOPY,
+                    16,
+                    16,
+                )
+                .unwrap();
+            merkle_tree.init().unwrap();
+            merkle_tree.append(&[1; 32]).unwrap();
+            merkle_tree.append(&[2; 32]).unwrap();
+        }
+
+        assert_eq!(
+            MerkleTreeHeader::from_account_data(TreeType::State, &data),
+            Some(MerkleTreeHeader {
+                height: HEIGHT,
+                canopy_depth: CANOPY,
+                next_index: 2,
+                sequence_number: 2,
+            })
+        );
+        assert_eq!(
+            MerkleTreeHeader::from_account_data(TreeType::State, &data[..offset + 8]),
+            None
+        );
+    }
+}
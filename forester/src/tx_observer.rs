@@ -0,0 +1,26 @@
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signature;
+use std::fmt::Debug;
+
+/// A transaction batch sent by the forester and its outcome.
+#[derive(Debug, Clone)]
+pub struct TransactionRecord {
+    pub signature: Signature,
+    pub epoch: u64,
+    /// Merkle trees of the work items in the batch.
+    pub trees: Vec<Pubkey>,
+    pub instructions: Vec<Instruction>,
+    /// The error message if the transaction failed.
+    pub result: Result<(), String>,
+}
+
+/// Receives every transaction batch the forester sends, e.g. to ship audit
+/// logs to an external system. Set it via
+/// [`ForesterConfig::tx_observer`](crate::ForesterConfig::tx_observer).
+///
+/// Called on the task which sent the transaction, so implementations should
+/// hand records off, e.g. to a channel, rather than block.
+pub trait TxObserver: Debug + Send + Sync {
+    fn on_transaction(&self, record: &TransactionRecord);
+}
@@ -1,7 +1,12 @@
+use crate::registry::RegistryProgram;
 use light_registry::protocol_config::state::{ProtocolConfig, ProtocolConfigPda};
-use light_registry::utils::get_protocol_config_pda_address;
+use light_test_utils::rpc::errors::RpcError;
 use light_test_utils::rpc::rpc_connection::RpcConnection;
-use log::{debug, info};
+use log::{debug, info, warn};
+use solana_client::rpc_response::RpcSimulateTransactionResult;
+use solana_sdk::system_instruction::SystemInstruction;
+use solana_sdk::system_program;
+use solana_sdk::transaction::Transaction;
 use std::process::Command;
 use sysinfo::{Signal, System};
 
@@ -82,13 +87,73 @@ pub fn u8_arr_to_hex_string(arr: &[u8]) -> String {
         .join("")
 }
 
-pub async fn get_protocol_config<R: RpcConnection>(rpc: &mut R) -> ProtocolConfig {
-    let authority_pda = get_protocol_config_pda_address();
+pub async fn get_protocol_config<R: RpcConnection>(
+    rpc: &mut R,
+    registry: &RegistryProgram,
+) -> ProtocolConfig {
     let protocol_config_account = rpc
-        .get_anchor_account::<ProtocolConfigPda>(&authority_pda.0)
+        .get_anchor_account::<ProtocolConfigPda>(&registry.protocol_config_pda())
         .await
         .unwrap()
         .unwrap();
     info!("Protocol config account: {:?}", protocol_config_account);
     protocol_config_account.config
 }
+
+/// Like [`get_protocol_config`], but fails instead of panicking if the
+/// account can't be fetched.
+pub async fn try_get_protocol_config<R: RpcConnection>(
+    rpc: &mut R,
+    registry: &RegistryProgram,
+) -> Result<ProtocolConfig, RpcError> {
+    rpc.get_anchor_account::<ProtocolConfigPda>(&registry.protocol_config_pda())
+        .await?
+        .map(|account| account.config)
+        .ok_or_else(|| RpcError::CustomError("Protocol config account not found".to_string()))
+}
+
+/// Base fee charged per transaction signature.
+pub const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+
+/// Estimates the lamports a transaction would cost the payer: signature fees
+/// plus the lamports funded into accounts created by system instructions.
+pub fn estimate_transaction_cost(transaction: &Transaction) -> u64 {
+    let signature_fees = transaction.signatures.len() as u64 * LAMPORTS_PER_SIGNATURE;
+    let funded_lamports: u64 = transaction
+        .message
+        .instructions
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| transaction.message.program_id(*index) == Some(&system_program::ID))
+        .filter_map(
+            |(_, instruction)| match bincode::deserialize(&instruction.data) {
+                Ok(SystemInstruction::CreateAccount { lamports, .. }) => Some(lamports),
+                _ => None,
+            },
+        )
+        .sum();
+    signature_fees + funded_lamports
+}
+
+pub fn log_simulation_result(
+    context: &str,
+    transaction: &Transaction,
+    result: &RpcSimulateTransactionResult,
+) {
+    let estimated_cost = estimate_transaction_cost(transaction);
+    match &result.err {
+        None => info!(
+            "[dry run] {}: simulation succeeded, units consumed: {:?}, estimated cost: {} lamports",
+            context, result.units_consumed, estimated_cost
+        ),
+        Some(e) => warn!(
+            "[dry run] {}: simulation failed: {:?}, estimated cost: {} lamports",
+            context, e, estimated_cost
+        ),
+    }
+    if let Some(logs) = &result.logs {
+        for log in logs {
+            debug!("[dry run] {}: {}", context, log);
+        }
+    }
+}
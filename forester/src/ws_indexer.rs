@@ -0,0 +1,359 @@
+use crate::photon_indexer::{merkle_proofs_from_photon, new_address_proofs_from_photon};
+use account_compression::initialize_address_merkle_tree::Pubkey;
+use futures::{SinkExt, StreamExt};
+use light_test_utils::indexer::{Indexer, IndexerError, MerkleProof, NewAddressProofWithContext};
+use light_test_utils::rpc::rpc_connection::RpcConnection;
+use log::{debug, error, info, warn};
+use photon_api::models::{
+    GetCompressedAccountsByOwnerPost200ResponseResult,
+    GetMultipleCompressedAccountProofsPost200ResponseResult,
+    GetMultipleNewAddressProofsPost200ResponseResult,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use solana_sdk::bs58;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::marker::PhantomData;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep, timeout};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Requests queued while the connection is busy or reconnecting.
+const REQUEST_QUEUE_SIZE: usize = 1024;
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+type ResponseSender = oneshot::Sender<Result<Value, IndexerError>>;
+
+struct IndexerRequest {
+    method: &'static str,
+    params: Value,
+    response: ResponseSender,
+}
+
+/// Photon indexer client which sends JSON-RPC requests over a single
+/// WebSocket connection. Requests are pipelined: they are written as soon
+/// as they are made and responses are matched to requests by id, so that
+/// concurrent batch tasks share the connection.
+///
+/// The connection is re-established if it drops; requests in flight at
+/// that time fail. Requests without a response within the request timeout
+/// fail as well.
+pub struct WsPhotonIndexer<R: RpcConnection> {
+    url: String,
+    requests: mpsc::Sender<IndexerRequest>,
+    request_timeout: Duration,
+    _rpc: PhantomData<R>,
+}
+
+impl<R: RpcConnection> WsPhotonIndexer<R> {
+    /// Must be called from within a tokio runtime, which runs the
+    /// connection.
+    pub fn new(url: String) -> Self {
+        Self::new_with_request_timeout(url, DEFAULT_REQUEST_TIMEOUT)
+    }
+
+    pub fn new_with_request_timeout(url: String, request_timeout: Duration) -> Self {
+        let (requests, receiver) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        tokio::spawn(run_connection(url.clone(), receiver));
+        Self {
+            url,
+            requests,
+            request_timeout,
+            _rpc: PhantomData,
+        }
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: &'static str,
+        params: Value,
+    ) -> Result<T, IndexerError> {
+        let (response, receiver) = oneshot::channel();
+        self.requests
+            .send(IndexerRequest {
+                method,
+                params,
+                response,
+            })
+            .await
+            .map_err(|_| IndexerError::Custom("Indexer connection stopped".to_string()))?;
+        // Dropping the receiver on timeout lets the connection forget the
+        // request.
+        let result = timeout(self.request_timeout, receiver)
+            .await
+            .map_err(|_| {
+                IndexerError::Custom(format!(
+                    "Indexer request {} timed out after {:?}",
+                    method, self.request_timeout
+                ))
+            })?
+            .map_err(|_| {
+                IndexerError::Custom("Indexer connection dropped the request".to_string())
+            })??;
+        serde_json::from_value(result).map_err(|e| IndexerError::Custom(e.to_string()))
+    }
+}
+
+impl<R: RpcConnection> Debug for WsPhotonIndexer<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WsPhotonIndexer")
+            .field("url", &self.url)
+            .finish()
+    }
+}
+
+impl<R: RpcConnection> Indexer<R> for WsPhotonIndexer<R> {
+    async fn get_multiple_compressed_account_proofs(
+        &self,
+        hashes: Vec<String>,
+    ) -> Result<Vec<MerkleProof>, IndexerError> {
+        debug!("Getting proofs for {:?}", hashes);
+        let result: GetMultipleCompressedAccountProofsPost200ResponseResult = self
+            .request("getMultipleCompressedAccountProofs", json!(hashes))
+            .await?;
+        Ok(merkle_proofs_from_photon(&result.value))
+    }
+
+    async fn get_rpc_compressed_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+    ) -> Result<Vec<String>, IndexerError> {
+        let result: GetCompressedAccountsByOwnerPost200ResponseResult = self
+            .request(
+                "getCompressedAccountsByOwner",
+                json!({ "owner": owner.to_string() }),
+            )
+            .await?;
+        Ok(result
+            .value
+            .items
+            .into_iter()
+            .map(|account| account.hash)
+            .collect())
+    }
+
+    async fn get_multiple_new_address_proofs(
+        &self,
+        _merkle_tree_pubkey: [u8; 32],
+        addresses: Vec<[u8; 32]>,
+    ) -> Result<Vec<NewAddressProofWithContext>, IndexerError> {
+        let addresses_bs58: Vec<String> = addresses
+            .iter()
+            .map(|x| bs58::encode(x).into_string())
+            .collect();
+        let result: GetMultipleNewAddressProofsPost200ResponseResult = self
+            .request("getMultipleNewAddressProofs", json!(addresses_bs58))
+            .await?;
+        Ok(new_address_proofs_from_photon(&result.value))
+    }
+}
+
+/// Connects to `url` and serves `requests` until all indexer handles are
+/// dropped, reconnecting whenever the connection is lost.
+async fn run_connection(url: String, mut requests: mpsc::Receiver<IndexerRequest>) {
+    loop {
+        match connect_async(url.as_str()).await {
+            Ok((stream, _)) => {
+                info!("Connected to indexer at {}", url);
+                if !serve_requests(stream, &mut requests).await {
+                    return;
+                }
+                warn!("Indexer connection closed, reconnecting");
+            }
+            Err(e) => error!("Failed to connect to indexer at {}: {:?}", url, e),
+        }
+        sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Returns `false` once no more requests can be made, `true` if the
+/// connection was lost.
+async fn serve_requests(
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    requests: &mut mpsc::Receiver<IndexerRequest>,
+) -> bool {
+    let (mut sink, mut source) = stream.split();
+    let mut pending: HashMap<u64, ResponseSender> = HashMap::new();
+    let mut next_id: u64 = 0;
+    let reconnect = loop {
+        tokio::select! {
+            request = requests.recv() => {
+                let Some(request) = request else {
+                    break false;
+                };
+                remove_abandoned_requests(&mut pending);
+                next_id += 1;
+                let message = json!({
+                    "jsonrpc": "2.0",
+                    "id": next_id,
+                    "method": request.method,
+                    "params": request.params,
+                });
+                if let Err(e) = sink.send(Message::Text(message.to_string())).await {
+                    request.response.send(Err(IndexerError::Custom(e.to_string()))).ok();
+                    break true;
+                }
+                pending.insert(next_id, request.response);
+            }
+            message = source.next() => match message {
+                Some(Ok(Message::Text(text))) => match parse_response(&text) {
+                    Some((id, result)) => match pending.remove(&id) {
+                        Some(response) => {
+                            response.send(result).ok();
+                        }
+                        None => warn!("Received indexer response for unknown request {}", id),
+                    },
+                    None => warn!("Received malformed indexer response: {}", text),
+                },
+                Some(Ok(Message::Close(_))) | None => break true,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    error!("Indexer connection error: {:?}", e);
+                    break true;
+                }
+            },
+        }
+    };
+    for (_, response) in pending.drain() {
+        response
+            .send(Err(IndexerError::Custom(
+                "Indexer connection closed".to_string(),
+            )))
+            .ok();
+    }
+    reconnect
+}
+
+/// Forgets requests whose caller stopped waiting, e.g. after a timeout, so
+/// that responses which never arrive don't accumulate.
+fn remove_abandoned_requests(pending: &mut HashMap<u64, ResponseSender>) {
+    pending.retain(|_, response| !response.is_closed());
+}
+
+/// Parses a JSON-RPC response into its request id and result.
+fn parse_response(text: &str) -> Option<(u64, Result<Value, IndexerError>)> {
+    let mut response: Value = serde_json::from_str(text).ok()?;
+    let id = response.get("id")?.as_u64()?;
+    if let Some(error) = response.get("error") {
+        let message = error
+            .get("message")
+            .and_then(Value::as_str)
+            .map_or_else(|| error.to_string(), str::to_string);
+        return Some((id, Err(IndexerError::Custom(message))));
+    }
+    let result = response.get_mut("result")?.take();
+    Some((id, Ok(result)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use light_test_utils::rpc::SolanaRpcConnection;
+    use tokio::net::TcpListener;
+
+    async fn accept(listener: &TcpListener) -> WebSocketStream<TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio_tungstenite::accept_async(stream).await.unwrap()
+    }
+
+    /// Reads the next request, returning its id and params.
+    async fn read_request(stream: &mut WebSocketStream<TcpStream>) -> (u64, Value) {
+        loop {
+            if let Message::Text(text) = stream.next().await.unwrap().unwrap() {
+                let request: Value = serde_json::from_str(&text).unwrap();
+                return (request["id"].as_u64().unwrap(), request["params"].clone());
+            }
+        }
+    }
+
+    async fn respond(stream: &mut WebSocketStream<TcpStream>, id: u64, result: Value) {
+        let response = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+        stream
+            .send(Message::Text(response.to_string()))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_responses_are_matched_by_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut stream = accept(&listener).await;
+            let first = read_request(&mut stream).await;
+            let second = read_request(&mut stream).await;
+            // Respond in reverse order, echoing the params.
+            for (id, params) in [second, first] {
+                respond(&mut stream, id, params).await;
+            }
+        });
+        let indexer = WsPhotonIndexer::<SolanaRpcConnection>::new(url);
+
+        let (first, second) = tokio::join!(
+            indexer.request::<Value>("first", json!(["a"])),
+            indexer.request::<Value>("second", json!(["b"])),
+        );
+        assert_eq!(first.unwrap(), json!(["a"]));
+        assert_eq!(second.unwrap(), json!(["b"]));
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_request_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut stream = accept(&listener).await;
+            // The first request is never answered.
+            read_request(&mut stream).await;
+            let (id, params) = read_request(&mut stream).await;
+            respond(&mut stream, id, params).await;
+        });
+        let indexer = WsPhotonIndexer::<SolanaRpcConnection>::new_with_request_timeout(
+            url,
+            Duration::from_millis(200),
+        );
+
+        let result = indexer.request::<Value>("first", json!(["a"])).await;
+        assert!(
+            matches!(result, Err(IndexerError::Custom(message)) if message.contains("timed out"))
+        );
+        let result = indexer.request::<Value>("second", json!(["b"])).await;
+        assert_eq!(result.unwrap(), json!(["b"]));
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_remove_abandoned_requests() {
+        let (abandoned, receiver) = oneshot::channel();
+        drop(receiver);
+        let (waiting, _receiver) = oneshot::channel();
+        let mut pending = HashMap::from([(1, abandoned), (2, waiting)]);
+
+        remove_abandoned_requests(&mut pending);
+        assert_eq!(pending.keys().collect::<Vec<_>>(), vec![&2]);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let (id, result) =
+            parse_response(r#"{"jsonrpc":"2.0","id":3,"result":{"value":[]}}"#).unwrap();
+        assert_eq!(id, 3);
+        assert_eq!(result.unwrap(), json!({ "value": [] }));
+
+        let (id, result) = parse_response(
+            r#"{"jsonrpc":"2.0","id":4,"error":{"code":-32000,"message":"not found"}}"#,
+        )
+        .unwrap();
+        assert_eq!(id, 4);
+        assert!(matches!(result, Err(IndexerError::Custom(message)) if message == "not found"));
+
+        assert!(parse_response(r#"{"jsonrpc":"2.0","result":{}}"#).is_none());
+        assert!(parse_response("not json").is_none());
+    }
+}
@@ -1,8 +1,10 @@
+use forester::epoch_manager::ForesterEvent;
 use forester::queue_helpers::fetch_queue_item_data;
 use forester::rpc_pool::SolanaRpcPool;
-use forester::run_pipeline;
 use forester::utils::LightValidatorConfig;
+use forester::{run_pipeline, run_pipeline_with_events};
 use light_test_utils::e2e_test_env::E2ETestEnv;
+use light_test_utils::forester_epoch::TreeType;
 use light_test_utils::indexer::{AddressMerkleTreeAccounts, StateMerkleTreeAccounts, TestIndexer};
 use light_test_utils::registry::register_test_forester;
 use light_test_utils::rpc::rpc_connection::RpcConnection;
@@ -11,12 +13,14 @@ use light_test_utils::rpc::SolanaRpcConnection;
 use light_test_utils::test_env::EnvAccounts;
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::Keypair;
 use solana_sdk::signer::Signer;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::select;
-use tokio::sync::{mpsc, oneshot, Mutex};
-use tokio::time::sleep;
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::time::{sleep, timeout};
 
 mod test_utils;
 use test_utils::*;
@@ -145,7 +149,7 @@ async fn test_epoch_monitor_with_test_indexer_and_1_forester() {
 
     let service_handle = tokio::spawn(run_pipeline(
         config.clone(),
-        Arc::new(Mutex::new(env.indexer)),
+        Arc::new(RwLock::new(env.indexer)),
         shutdown_receiver,
         work_report_sender,
     ));
@@ -309,7 +313,7 @@ async fn test_epoch_monitor_with_2_foresters() {
     let (work_report_sender1, mut work_report_receiver1) = mpsc::channel(100);
     let (work_report_sender2, mut work_report_receiver2) = mpsc::channel(100);
 
-    let indexer = Arc::new(Mutex::new(env.indexer));
+    let indexer = Arc::new(RwLock::new(env.indexer));
 
     let service_handle1 = tokio::spawn(run_pipeline(
         config1.clone(),
@@ -384,3 +388,325 @@ async fn test_epoch_monitor_with_2_foresters() {
     service_handle1.await.unwrap().unwrap();
     service_handle2.await.unwrap().unwrap();
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_epoch_monitor_with_only_rollover_enabled() {
+    init(None).await;
+    let forester_keypair = Keypair::new();
+
+    let mut env_accounts = EnvAccounts::get_local_test_validator_accounts();
+    env_accounts.forester = forester_keypair.insecure_clone();
+
+    let mut config = forester_config();
+    config.payer_keypair = forester_keypair.insecure_clone();
+    config.enable_work_processing = false;
+    config.enable_reporting = false;
+    let config = Arc::new(config);
+
+    let pool = SolanaRpcPool::<SolanaRpcConnection>::new(
+        config.external_services.rpc_url.to_string(),
+        CommitmentConfig::confirmed(),
+        config.rpc_pool_size as u32,
+    )
+    .await
+    .unwrap();
+
+    let mut rpc = SolanaRpcConnection::new(SolanaRpcUrl::Localnet, None);
+    rpc.payer = forester_keypair.insecure_clone();
+
+    for keypair in [&forester_keypair, &env_accounts.governance_authority] {
+        rpc.airdrop_lamports(&keypair.pubkey(), LAMPORTS_PER_SOL * 100_000)
+            .await
+            .unwrap();
+    }
+
+    register_test_forester(
+        &mut rpc,
+        &env_accounts.governance_authority,
+        &forester_keypair.pubkey(),
+        light_registry::ForesterConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    let indexer: TestIndexer<SolanaRpcConnection> = TestIndexer::init_from_env(
+        &config.payer_keypair,
+        &env_accounts,
+        keypair_action_config().inclusion(),
+        keypair_action_config().non_inclusion(),
+    )
+    .await;
+
+    let mut env = E2ETestEnv::<SolanaRpcConnection, TestIndexer<SolanaRpcConnection>>::new(
+        rpc,
+        indexer,
+        &env_accounts,
+        keypair_action_config(),
+        general_action_config(),
+        0,
+        Some(0),
+    )
+    .await;
+
+    // Trees with a rollover threshold of 0 are ready for rollover right away.
+    env.create_state_tree(Some(0)).await;
+    env.create_address_tree(Some(0)).await;
+
+    let user_index = 0;
+    let balance = env
+        .rpc
+        .get_balance(&env.users[user_index].keypair.pubkey())
+        .await
+        .unwrap();
+    env.compress_sol(user_index, balance).await;
+    let iterations = 5;
+    for i in 0..iterations {
+        println!("Round {} of {}", i, iterations);
+        env.transfer_sol(user_index).await;
+        sleep(std::time::Duration::from_millis(100)).await;
+        env.create_address(None).await;
+    }
+
+    let mut queues: Vec<Pubkey> = env
+        .indexer
+        .state_merkle_trees
+        .iter()
+        .map(|x| x.accounts.nullifier_queue)
+        .collect();
+    queues.extend(
+        env.indexer
+            .address_merkle_trees
+            .iter()
+            .map(|x| x.accounts.queue),
+    );
+
+    let mut queue_lengths_before = Vec::new();
+    for queue in queues.iter() {
+        let mut rpc = pool.get_connection().await.unwrap();
+        let queue_length = fetch_queue_item_data(&mut *rpc, queue).await.unwrap().len();
+        queue_lengths_before.push(queue_length);
+    }
+    assert!(queue_lengths_before.iter().any(|length| *length > 0));
+
+    let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+    let (work_report_sender, mut work_report_receiver) = mpsc::channel(100);
+    let (event_sender, mut event_receiver) = mpsc::channel(100);
+
+    let service_handle = tokio::spawn(run_pipeline_with_events(
+        config.clone(),
+        Arc::new(RwLock::new(env.indexer)),
+        shutdown_receiver,
+        work_report_sender,
+        Some(event_sender),
+        None,
+    ));
+
+    let mut state_tree_rolled_over = false;
+    let mut address_tree_rolled_over = false;
+    timeout(Duration::from_secs(300), async {
+        while let Some(event) = event_receiver.recv().await {
+            match event {
+                ForesterEvent::RolloverPerformed {
+                    tree_type: TreeType::State,
+                    ..
+                } => state_tree_rolled_over = true,
+                ForesterEvent::RolloverPerformed {
+                    tree_type: TreeType::Address,
+                    ..
+                } => address_tree_rolled_over = true,
+                ForesterEvent::BatchProcessed { .. } => {
+                    panic!("Work processing is disabled but a batch was processed")
+                }
+                _ => {}
+            }
+            if state_tree_rolled_over && address_tree_rolled_over {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("Timed out waiting for rollovers");
+
+    assert!(state_tree_rolled_over, "State tree was not rolled over");
+    assert!(address_tree_rolled_over, "Address tree was not rolled over");
+
+    for (queue, queue_length_before) in queues.iter().zip(queue_lengths_before) {
+        let mut rpc = pool.get_connection().await.unwrap();
+        let queue_length = fetch_queue_item_data(&mut *rpc, queue).await.unwrap().len();
+        assert_eq!(queue_length, queue_length_before);
+    }
+    assert!(
+        work_report_receiver.try_recv().is_err(),
+        "Reporting is disabled but work was reported"
+    );
+
+    shutdown_sender
+        .send(())
+        .expect("Failed to send shutdown signal");
+    service_handle.await.unwrap().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_epoch_phase_transitions() {
+    init(None).await;
+    let forester_keypair = Keypair::new();
+
+    let mut env_accounts = EnvAccounts::get_local_test_validator_accounts();
+    env_accounts.forester = forester_keypair.insecure_clone();
+
+    let mut config = forester_config();
+    config.payer_keypair = forester_keypair.insecure_clone();
+    let config = Arc::new(config);
+
+    let pool = SolanaRpcPool::<SolanaRpcConnection>::new(
+        config.external_services.rpc_url.to_string(),
+        CommitmentConfig::confirmed(),
+        config.rpc_pool_size as u32,
+    )
+    .await
+    .unwrap();
+
+    let mut rpc = SolanaRpcConnection::new(SolanaRpcUrl::Localnet, None);
+    rpc.payer = forester_keypair.insecure_clone();
+
+    for keypair in [&forester_keypair, &env_accounts.governance_authority] {
+        rpc.airdrop_lamports(&keypair.pubkey(), LAMPORTS_PER_SOL * 100_000)
+            .await
+            .unwrap();
+    }
+
+    register_test_forester(
+        &mut rpc,
+        &env_accounts.governance_authority,
+        &forester_keypair.pubkey(),
+        light_registry::ForesterConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    let indexer: TestIndexer<SolanaRpcConnection> = TestIndexer::init_from_env(
+        &config.payer_keypair,
+        &env_accounts,
+        keypair_action_config().inclusion(),
+        keypair_action_config().non_inclusion(),
+    )
+    .await;
+
+    let mut env = E2ETestEnv::<SolanaRpcConnection, TestIndexer<SolanaRpcConnection>>::new(
+        rpc,
+        indexer,
+        &env_accounts,
+        keypair_action_config(),
+        general_action_config(),
+        0,
+        Some(0),
+    )
+    .await;
+
+    // Seed the nullifier and address queues.
+    let user_index = 0;
+    let balance = env
+        .rpc
+        .get_balance(&env.users[user_index].keypair.pubkey())
+        .await
+        .unwrap();
+    env.compress_sol(user_index, balance).await;
+    for _ in 0..3 {
+        env.transfer_sol(user_index).await;
+        env.create_address(None).await;
+    }
+
+    let mut queues: Vec<Pubkey> = env
+        .indexer
+        .state_merkle_trees
+        .iter()
+        .map(|x| x.accounts.nullifier_queue)
+        .collect();
+    queues.extend(
+        env.indexer
+            .address_merkle_trees
+            .iter()
+            .map(|x| x.accounts.queue),
+    );
+    let mut seeded_items = 0;
+    for queue in queues.iter() {
+        let mut rpc = pool.get_connection().await.unwrap();
+        seeded_items += fetch_queue_item_data(&mut *rpc, queue).await.unwrap().len();
+    }
+    assert_ne!(seeded_items, 0);
+
+    let (shutdown_sender, shutdown_receiver) = oneshot::channel();
+    let (work_report_sender, mut work_report_receiver) = mpsc::channel(100);
+    let (event_sender, mut event_receiver) = mpsc::channel(100);
+
+    let service_handle = tokio::spawn(run_pipeline_with_events(
+        config.clone(),
+        Arc::new(RwLock::new(env.indexer)),
+        shutdown_receiver,
+        work_report_sender,
+        Some(event_sender),
+        None,
+    ));
+
+    // Events of the first epoch the forester registers for, in the order
+    // they were emitted.
+    let mut epoch = None;
+    let mut events = Vec::new();
+    timeout(Duration::from_secs(300), async {
+        while let Some(event) = event_receiver.recv().await {
+            let event_epoch = match &event {
+                ForesterEvent::Registered { epoch }
+                | ForesterEvent::ActivePhaseStarted { epoch }
+                | ForesterEvent::BatchProcessed { epoch, .. }
+                | ForesterEvent::WorkReported { epoch, .. } => *epoch,
+                ForesterEvent::RolloverPerformed { .. } => continue,
+            };
+            if *epoch.get_or_insert(event_epoch) != event_epoch {
+                continue;
+            }
+            let reported = matches!(event, ForesterEvent::WorkReported { .. });
+            events.push(event);
+            if reported {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("Timed out waiting for the epoch to be reported");
+
+    assert!(matches!(
+        events.first(),
+        Some(ForesterEvent::Registered { .. })
+    ));
+    assert!(matches!(
+        events.get(1),
+        Some(ForesterEvent::ActivePhaseStarted { .. })
+    ));
+    let batches = &events[2..events.len() - 1];
+    assert!(!batches.is_empty(), "No batches were processed");
+    assert!(batches
+        .iter()
+        .all(|event| matches!(event, ForesterEvent::BatchProcessed { .. })));
+    let processed_items = match events.last() {
+        Some(ForesterEvent::WorkReported {
+            processed_items, ..
+        }) => *processed_items,
+        event => panic!("Expected the work report last, got {:?}", event),
+    };
+    assert!(processed_items > 0, "No items were processed");
+
+    let report = work_report_receiver.recv().await.unwrap();
+    assert_eq!(Some(report.epoch), epoch);
+    assert_eq!(report.processed_items, processed_items);
+
+    for queue in queues.iter() {
+        let mut rpc = pool.get_connection().await.unwrap();
+        let queue_length = fetch_queue_item_data(&mut *rpc, queue).await.unwrap().len();
+        assert_eq!(queue_length, 0);
+    }
+
+    shutdown_sender
+        .send(())
+        .expect("Failed to send shutdown signal");
+    service_handle.await.unwrap().unwrap();
+}
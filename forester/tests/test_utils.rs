@@ -1,6 +1,6 @@
 use account_compression::initialize_address_merkle_tree::Pubkey;
 use env_logger::Env;
-use forester::config::ExternalServicesConfig;
+use forester::config::{BatchSizing, ExternalServicesConfig, RetryPolicy};
 use forester::photon_indexer::PhotonIndexer;
 use forester::utils::{spawn_validator, LightValidatorConfig};
 use forester::ForesterConfig;
@@ -11,7 +11,9 @@ use light_test_utils::rpc::SolanaRpcConnection;
 use light_test_utils::test_env::get_test_env_accounts;
 use log::{debug, info};
 use once_cell::sync::OnceCell;
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::signature::{Keypair, Signer};
+use std::time::Duration;
 
 #[allow(dead_code)]
 pub async fn init(config: Option<LightValidatorConfig>) {
@@ -87,10 +89,13 @@ pub fn forester_config() -> ForesterConfig {
     ForesterConfig {
         external_services: ExternalServicesConfig {
             rpc_url: "http://localhost:8899".to_string(),
+            fallback_rpc_urls: vec![],
             ws_rpc_url: "ws://localhost:8900".to_string(),
             indexer_url: "http://localhost:8784".to_string(),
             prover_url: "http://localhost:3001".to_string(),
             photon_api_key: None,
+            remote_signer_url: None,
+            indexer_ws_url: None,
             derivation: "En9a97stB3Ek2n6Ey3NJwCUJnmTzLMMEA5C69upGDuQP".to_string(),
         },
         registry_pubkey: light_registry::ID,
@@ -103,6 +108,60 @@ pub fn forester_config() -> ForesterConfig {
         cu_limit: 1_000_000,
         rpc_pool_size: 20,
         slot_update_interval_seconds: 10,
+        log_eligibility_summary: false,
+        dry_run: false,
+        batch_sizing: BatchSizing::Fixed,
+        shutdown_grace_period_seconds: 30,
+        max_queue_item_age_slots: None,
+        validate_proof_roots: false,
+        rpc_max_latency_ms: 2000,
+        rpc_health_check_interval_seconds: 30,
+        enable_work_processing: true,
+        enable_rollover: true,
+        enable_reporting: true,
+        rpc_endpoint_cooldown_seconds: 30,
+        max_tps: None,
+        prioritize_full_queues: false,
+        work_commitment: CommitmentConfig::confirmed(),
+        rollover_commitment: CommitmentConfig::confirmed(),
+        max_transactions_per_epoch: None,
+        retry_policy: RetryPolicy::Deterministic {
+            base_delay: Duration::ZERO,
+            factor: 1,
+            max: Duration::ZERO,
+        },
+        tree_type: None,
+        max_proof_root_seq_lag: None,
+        queue_metrics_interval_seconds: 60,
+        proof_fetch_timeout_seconds: 30,
+        min_queue_items: 0,
+        phase_end_margin_slots: 0,
+        max_concurrent_batches_per_tree: None,
+        wait_for_eligible_slot: false,
+        tree_discovery_interval_seconds: 300,
+        max_concurrent_epochs: 3,
+        proof_batch_size: 50,
+        force_register: false,
+        cu_price: None,
+        metrics_port: None,
+        health_staleness_seconds: 300,
+        nonce_account: None,
+        nonce_authority: None,
+        dead_letter_path: None,
+        rollover_confirm_timeout_seconds: 60,
+        rollover_confirm_poll_interval_ms: 500,
+        registration_jitter_slots: 0,
+        observer: false,
+        rpc_pool_acquire_timeout_ms: 15000,
+        tx_observer: None,
+        protocol_config_refresh_interval_seconds: 300,
+        address_max_concurrent_batches: None,
+        state_max_concurrent_batches: None,
+        run_duration_seconds: None,
+        serial_trees: vec![],
+        max_concurrent_queue_tasks: 32,
+        min_payer_balance: None,
+        catch_up: false,
         address_tree_data: vec![],
         state_tree_data: vec![],
     }
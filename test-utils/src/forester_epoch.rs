@@ -171,6 +171,15 @@ impl TreeForesterSchedule {
     pub fn is_eligible(&self, forester_slot: u64) -> bool {
         self.slots[forester_slot as usize].is_some()
     }
+
+    /// Returns the first slot at or after `light_slot` in which the forester
+    /// is eligible, if any.
+    pub fn next_eligible_slot(&self, light_slot: u64) -> Option<&ForesterSlot> {
+        self.slots
+            .iter()
+            .skip(light_slot as usize)
+            .find_map(|slot| slot.as_ref())
+    }
 }
 
 #[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, Default, PartialEq, Eq)]
@@ -441,6 +450,36 @@ impl Epoch {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_next_eligible_slot() {
+        let forester_slot = |slot: u64| ForesterSlot {
+            slot,
+            start_solana_slot: slot * 10,
+            end_solana_slot: slot * 10 + 10,
+            forester_index: 0,
+        };
+        let schedule = TreeForesterSchedule {
+            tree_accounts: TreeAccounts::new(
+                Pubkey::new_unique(),
+                Pubkey::new_unique(),
+                TreeType::State,
+                false,
+            ),
+            slots: vec![
+                Some(forester_slot(0)),
+                None,
+                None,
+                Some(forester_slot(3)),
+                None,
+            ],
+        };
+        assert_eq!(schedule.next_eligible_slot(0), Some(&forester_slot(0)));
+        assert_eq!(schedule.next_eligible_slot(1), Some(&forester_slot(3)));
+        assert_eq!(schedule.next_eligible_slot(3), Some(&forester_slot(3)));
+        assert_eq!(schedule.next_eligible_slot(4), None);
+        assert_eq!(schedule.next_eligible_slot(10), None);
+    }
+
     #[test]
     fn test_epoch_phases() {
         let config = ProtocolConfig {
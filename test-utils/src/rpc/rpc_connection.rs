@@ -3,12 +3,13 @@ use crate::transaction_params::TransactionParams;
 use account_compression::initialize_address_merkle_tree::{AnchorDeserialize, Pubkey};
 use anchor_lang::solana_program::clock::Slot;
 use anchor_lang::solana_program::instruction::Instruction;
+use solana_client::rpc_response::RpcSimulateTransactionResult;
 use solana_sdk::account::{Account, AccountSharedData};
 use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::epoch_info::EpochInfo;
 use solana_sdk::hash::Hash;
 use solana_sdk::signature::{Keypair, Signature};
-use solana_sdk::transaction::Transaction;
+use solana_sdk::transaction::{Transaction, TransactionError};
 use std::fmt::Debug;
 
 pub trait RpcConnection: Send + Sync + Debug + 'static {
@@ -30,6 +31,13 @@ pub trait RpcConnection: Send + Sync + Debug + 'static {
     fn get_program_accounts(&self, program_id: &Pubkey)
         -> Result<Vec<(Pubkey, Account)>, RpcError>;
 
+    fn simulate_transaction(
+        &self,
+        _transaction: &Transaction,
+    ) -> Result<RpcSimulateTransactionResult, RpcError> {
+        unimplemented!()
+    }
+
     fn process_transaction(
         &mut self,
         transaction: Transaction,
@@ -40,6 +48,31 @@ pub trait RpcConnection: Send + Sync + Debug + 'static {
         transaction: Transaction,
     ) -> impl std::future::Future<Output = Result<(Signature, Slot), RpcError>> + Send;
 
+    /// Sends the transaction and waits until it reaches `commitment`,
+    /// regardless of the commitment the connection was created with.
+    fn process_transaction_with_commitment(
+        &mut self,
+        transaction: Transaction,
+        _commitment: CommitmentConfig,
+    ) -> impl std::future::Future<Output = Result<Signature, RpcError>> + Send {
+        self.process_transaction(transaction)
+    }
+
+    /// Sends the transaction without waiting for it to be confirmed.
+    fn send_transaction(
+        &mut self,
+        transaction: Transaction,
+    ) -> impl std::future::Future<Output = Result<Signature, RpcError>> + Send {
+        self.process_transaction(transaction)
+    }
+
+    /// Result of the transaction, `None` while it hasn't reached `commitment`.
+    fn get_signature_status_with_commitment(
+        &mut self,
+        signature: Signature,
+        commitment: CommitmentConfig,
+    ) -> impl std::future::Future<Output = Result<Option<Result<(), TransactionError>>, RpcError>> + Send;
+
     fn create_and_send_transaction_with_event<T>(
         &mut self,
         instruction: &[Instruction],
@@ -80,6 +113,21 @@ pub trait RpcConnection: Send + Sync + Debug + 'static {
         &mut self,
         address: Pubkey,
     ) -> impl std::future::Future<Output = Result<Option<Account>, RpcError>> + Send;
+
+    /// Accounts in the order of `addresses`, `None` for accounts which don't
+    /// exist.
+    fn get_multiple_accounts(
+        &mut self,
+        addresses: &[Pubkey],
+    ) -> impl std::future::Future<Output = Result<Vec<Option<Account>>, RpcError>> + Send {
+        async move {
+            let mut accounts = Vec::with_capacity(addresses.len());
+            for address in addresses {
+                accounts.push(self.get_account(*address).await?);
+            }
+            Ok(accounts)
+        }
+    }
     fn set_account(&mut self, address: &Pubkey, account: &AccountSharedData);
 
     fn get_minimum_balance_for_rent_exemption(
@@ -8,6 +8,8 @@ use anchor_lang::AnchorDeserialize;
 use log::{debug, warn};
 use solana_client::rpc_client::RpcClient;
 use solana_client::rpc_config::RpcTransactionConfig;
+use solana_client::rpc_request::MAX_MULTIPLE_ACCOUNTS;
+use solana_client::rpc_response::RpcSimulateTransactionResult;
 use solana_program_test::BanksClientError;
 use solana_sdk::account::{Account, AccountSharedData};
 use solana_sdk::bs58;
@@ -156,6 +158,16 @@ impl RpcConnection for SolanaRpcConnection {
             .map_err(RpcError::from)
     }
 
+    fn simulate_transaction(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<RpcSimulateTransactionResult, RpcError> {
+        self.client
+            .simulate_transaction(transaction)
+            .map(|response| response.value)
+            .map_err(RpcError::from)
+    }
+
     async fn process_transaction(
         &mut self,
         transaction: Transaction,
@@ -183,6 +195,37 @@ impl RpcConnection for SolanaRpcConnection {
         }
     }
 
+    async fn process_transaction_with_commitment(
+        &mut self,
+        transaction: Transaction,
+        commitment: CommitmentConfig,
+    ) -> Result<Signature, RpcError> {
+        debug!("CommitmentConfig: {:?}", commitment);
+        let signature = self.client.send_transaction(&transaction)?;
+        loop {
+            if let Some(result) = self
+                .client
+                .get_signature_status_with_commitment(&signature, commitment)?
+            {
+                return result.map(|_| signature).map_err(RpcError::from);
+            }
+            let blockhash_valid = self.client.is_blockhash_valid(
+                &transaction.message.recent_blockhash,
+                CommitmentConfig::processed(),
+            )?;
+            if !blockhash_valid {
+                warn!(
+                    "Transaction {} expired before reaching {:?} commitment",
+                    signature, commitment.commitment
+                );
+                return Err(RpcError::TransactionError(
+                    TransactionError::BlockhashNotFound,
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+
     async fn create_and_send_transaction_with_event<T>(
         &mut self,
         instructions: &[Instruction],
@@ -271,6 +314,22 @@ impl RpcConnection for SolanaRpcConnection {
         Ok(result)
     }
 
+    async fn send_transaction(&mut self, transaction: Transaction) -> Result<Signature, RpcError> {
+        self.client
+            .send_transaction(&transaction)
+            .map_err(RpcError::from)
+    }
+
+    async fn get_signature_status_with_commitment(
+        &mut self,
+        signature: Signature,
+        commitment: CommitmentConfig,
+    ) -> Result<Option<Result<(), TransactionError>>, RpcError> {
+        self.client
+            .get_signature_status_with_commitment(&signature, commitment)
+            .map_err(RpcError::from)
+    }
+
     async fn confirm_transaction(&mut self, transaction: Signature) -> Result<bool, RpcError> {
         self.client
             .confirm_transaction(&transaction)
@@ -289,6 +348,21 @@ impl RpcConnection for SolanaRpcConnection {
         result.map(|account| account.value).map_err(RpcError::from)
     }
 
+    async fn get_multiple_accounts(
+        &mut self,
+        addresses: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, RpcError> {
+        let mut accounts = Vec::with_capacity(addresses.len());
+        for chunk in addresses.chunks(MAX_MULTIPLE_ACCOUNTS) {
+            let result = self
+                .client
+                .get_multiple_accounts_with_commitment(chunk, self.client.commitment())
+                .map_err(RpcError::from)?;
+            accounts.extend(result.value);
+        }
+        Ok(accounts)
+    }
+
     fn set_account(&mut self, _address: &Pubkey, _account: &AccountSharedData) {
         todo!()
     }
@@ -7,6 +7,7 @@ use anchor_lang::solana_program::system_instruction;
 use anchor_lang::AnchorDeserialize;
 use solana_program_test::{BanksClientError, ProgramTestContext};
 use solana_sdk::account::{Account, AccountSharedData};
+use solana_sdk::commitment_config::CommitmentConfig;
 use solana_sdk::instruction::{Instruction, InstructionError};
 use solana_sdk::signature::{Keypair, Signature};
 use solana_sdk::signer::Signer;
@@ -183,6 +184,16 @@ impl RpcConnection for ProgramTestRpcConnection {
         Ok(result)
     }
 
+    async fn get_signature_status_with_commitment(
+        &mut self,
+        _signature: Signature,
+        _commitment: CommitmentConfig,
+    ) -> Result<Option<Result<(), TransactionError>>, RpcError> {
+        // Transactions are processed when they are sent and a failed
+        // transaction is returned as an error of `send_transaction`.
+        Ok(Some(Ok(())))
+    }
+
     async fn confirm_transaction(&mut self, _transaction: Signature) -> Result<bool, RpcError> {
         Ok(true)
     }